@@ -1,11 +1,59 @@
+use std::sync::Arc;
 use web_core::error_prelude::*;
 
 #[derive(thiserror::Error, Debug)]
 pub enum ExtensionError {
     #[error("Distribute cache missing.")]
     DistributeCacheMissing,
+    #[error("Event bus missing.")]
+    EventBusMissing,
     #[error("Memory cache missing.")]
     MemoryCacheMissing,
+    #[error(transparent)]
+    MemoryCache(#[from] MemoryCacheError),
 }
 
 app_error_impl!(ExtensionError);
+
+#[derive(thiserror::Error, Debug)]
+pub enum MemoryCacheError {
+    #[error("Memory cache namespace `{0}` has been registered with other key/value types.")]
+    NamespaceTypeMismatch(String),
+    #[error("Memory cache namespace `{0}` has been registered with another policy.")]
+    NamespacePolicyMismatch(String),
+    #[error("Memory cache snapshot version `{0}` is not supported.")]
+    SnapshotVersionMismatch(u32),
+}
+
+app_error_impl!(MemoryCacheError);
+
+#[derive(thiserror::Error, Debug)]
+pub enum CodecError {
+    #[error("Unknown cache codec `{0}`.")]
+    UnknownCodec(u8),
+    #[error("Unknown cache compression `{0}`.")]
+    UnknownCompression(u8),
+    #[error("Unsupported cache value format version `{0}`.")]
+    UnsupportedVersion(u8),
+    #[error("Cache value larger than {0} bytes once decompressed.")]
+    TooLarge(usize),
+}
+
+app_error_impl!(CodecError);
+
+/// The error of a loader shared by the calls coalesced on it, kept as the `source`.
+/// Returned as it was instead, if a single call got it.
+#[derive(Debug, Clone)]
+pub struct SharedLoadError(pub Arc<anyhow::Error>);
+
+impl std::fmt::Display for SharedLoadError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("Shared loader failed.")
+    }
+}
+
+impl std::error::Error for SharedLoadError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&**self.0)
+    }
+}
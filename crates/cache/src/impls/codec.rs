@@ -0,0 +1,285 @@
+/// Codecs of the distribute cache values.
+/// Every value starts with a header, so values written in other formats can still be read:
+/// `[MAGIC, FORMAT_VERSION, codec, compression]`.
+/// Values without the header are the legacy ones, plain json.
+use crate::error::CodecError;
+use serde::{de::DeserializeOwned, Serialize};
+use std::borrow::Cow;
+use std::io::Read;
+use web_core::prelude::*;
+
+const MAGIC: u8 = 0xCA;
+const FORMAT_VERSION: u8 = 1;
+const HEADER_LEN: usize = 4;
+/// Bytes. Values above it will be compressed.
+const DEFAULT_COMPRESS_THRESHOLD: usize = 1024;
+const ZSTD_LEVEL: i32 = 3;
+/// Bytes. As large as a redis value can be, a corrupted or forged value can't take more memory on decompression.
+const MAX_DECOMPRESSED_LEN: usize = 512 * 1024 * 1024;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum CacheCodec {
+    #[default]
+    Json,
+    MessagePack,
+    /// Compact, but not self-describing,
+    /// so `serde_json::Value` and `#[serde(untagged)]` types can't be read back.
+    Bincode,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum CacheCompression {
+    None,
+    #[default]
+    Zstd,
+    Lz4,
+}
+
+impl CacheCodec {
+    #[inline]
+    fn id(self) -> u8 {
+        match self {
+            CacheCodec::Json => 0,
+            CacheCodec::MessagePack => 1,
+            CacheCodec::Bincode => 2,
+        }
+    }
+
+    #[inline]
+    fn from_id(id: u8) -> std::result::Result<Self, CodecError> {
+        match id {
+            0 => Ok(CacheCodec::Json),
+            1 => Ok(CacheCodec::MessagePack),
+            2 => Ok(CacheCodec::Bincode),
+            id => Err(CodecError::UnknownCodec(id)),
+        }
+    }
+
+    fn serialize<T: Serialize>(self, value: &T) -> Result<Vec<u8>> {
+        Ok(match self {
+            CacheCodec::Json => serde_json::to_vec(value)?,
+            // Named, so the fields can be added or reordered.
+            CacheCodec::MessagePack => rmp_serde::to_vec_named(value)?,
+            CacheCodec::Bincode => bincode::serialize(value)?,
+        })
+    }
+
+    fn deserialize<T: DeserializeOwned>(self, content: &[u8]) -> Result<T> {
+        Ok(match self {
+            CacheCodec::Json => serde_json::from_slice(content)?,
+            CacheCodec::MessagePack => rmp_serde::from_slice(content)?,
+            CacheCodec::Bincode => bincode::deserialize(content)?,
+        })
+    }
+}
+
+impl CacheCompression {
+    #[inline]
+    fn id(self) -> u8 {
+        match self {
+            CacheCompression::None => 0,
+            CacheCompression::Zstd => 1,
+            CacheCompression::Lz4 => 2,
+        }
+    }
+
+    #[inline]
+    fn from_id(id: u8) -> std::result::Result<Self, CodecError> {
+        match id {
+            0 => Ok(CacheCompression::None),
+            1 => Ok(CacheCompression::Zstd),
+            2 => Ok(CacheCompression::Lz4),
+            id => Err(CodecError::UnknownCompression(id)),
+        }
+    }
+
+    fn compress(self, content: Vec<u8>) -> Result<Vec<u8>> {
+        Ok(match self {
+            CacheCompression::None => content,
+            CacheCompression::Zstd => zstd::encode_all(content.as_slice(), ZSTD_LEVEL)?,
+            CacheCompression::Lz4 => lz4_flex::compress_prepend_size(&content),
+        })
+    }
+
+    /// At most `max_len` bytes.
+    fn decompress(self, content: &[u8], max_len: usize) -> Result<Vec<u8>> {
+        Ok(match self {
+            CacheCompression::None => content.to_vec(),
+            CacheCompression::Zstd => {
+                let mut decompressed = Vec::new();
+                zstd::Decoder::new(content)?.take(max_len as u64 + 1).read_to_end(&mut decompressed)?;
+                if decompressed.len() > max_len {
+                    return Err(CodecError::TooLarge(max_len).into());
+                }
+
+                decompressed
+            }
+            CacheCompression::Lz4 => {
+                // The size is prepended, so it's checked before allocating.
+                let (len, _) = lz4_flex::block::uncompressed_size(content)?;
+                if len > max_len {
+                    return Err(CodecError::TooLarge(max_len).into());
+                }
+
+                lz4_flex::decompress_size_prepended(content)?
+            }
+        })
+    }
+}
+
+/// How the values are written, read from the env:
+/// - `DISTRIBUTE_CACHE_CODEC`, `json` (default), `msgpack` or `bincode`.
+/// - `DISTRIBUTE_CACHE_COMPRESSION`, `zstd` (default), `lz4` or `none`.
+/// - `DISTRIBUTE_CACHE_COMPRESS_THRESHOLD`, e.g. `1KiB`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CacheCodecConfig {
+    pub codec: CacheCodec,
+    pub compression: CacheCompression,
+    pub compress_threshold: usize,
+}
+
+impl Default for CacheCodecConfig {
+    fn default() -> Self {
+        CacheCodecConfig {
+            codec: Default::default(),
+            compression: Default::default(),
+            compress_threshold: DEFAULT_COMPRESS_THRESHOLD,
+        }
+    }
+}
+
+impl CacheCodecConfig {
+    pub fn from_env() -> Result<Self> {
+        let default = CacheCodecConfig::default();
+
+        Ok(CacheCodecConfig {
+            codec: match web_env::var("DISTRIBUTE_CACHE_CODEC")?.as_deref() {
+                None => default.codec,
+                Some("json") => CacheCodec::Json,
+                Some("msgpack") => CacheCodec::MessagePack,
+                Some("bincode") => CacheCodec::Bincode,
+                Some(codec) => anyhow::bail!("Unknown DISTRIBUTE_CACHE_CODEC `{codec}`."),
+            },
+            compression: match web_env::var("DISTRIBUTE_CACHE_COMPRESSION")?.as_deref() {
+                None => default.compression,
+                Some("none") => CacheCompression::None,
+                Some("zstd") => CacheCompression::Zstd,
+                Some("lz4") => CacheCompression::Lz4,
+                Some(compression) => anyhow::bail!("Unknown DISTRIBUTE_CACHE_COMPRESSION `{compression}`."),
+            },
+            compress_threshold: match web_env::var("DISTRIBUTE_CACHE_COMPRESS_THRESHOLD")? {
+                Some(content) => content
+                    .parse::<bytesize::ByteSize>()
+                    .map_err(|error| {
+                        anyhow::anyhow!(
+                            "Failed to parse DISTRIBUTE_CACHE_COMPRESS_THRESHOLD environment variable: {error}"
+                        )
+                    })?
+                    .as_u64() as usize,
+                None => default.compress_threshold,
+            },
+        })
+    }
+
+    /// Serialize with the configured codec, compress if it's large enough.
+    pub fn encode<T: Serialize>(&self, value: &T) -> Result<Vec<u8>> {
+        let content = self.codec.serialize(value)?;
+        let compression = match content.len() > self.compress_threshold {
+            true => self.compression,
+            false => CacheCompression::None,
+        };
+        let content = compression.compress(content)?;
+
+        let mut encoded = Vec::with_capacity(HEADER_LEN + content.len());
+        encoded.extend_from_slice(&[MAGIC, FORMAT_VERSION, self.codec.id(), compression.id()]);
+        encoded.extend_from_slice(&content);
+
+        Ok(encoded)
+    }
+
+    /// Decode with the codec in the header, whatever is configured now.
+    #[inline]
+    pub fn decode<T: DeserializeOwned>(&self, content: &[u8]) -> Result<T> {
+        self.decompress(content)?.deserialize()
+    }
+
+    /// Decompressed once, so it can be deserialized more than once, e.g. a header first.
+    pub(crate) fn decompress<'a>(&self, content: &'a [u8]) -> Result<Decompressed<'a>> {
+        // Json starts with whitespace, `{`, `[`, `"`, `-`, a digit, `t`, `f` or `n`, never with the magic byte.
+        let [MAGIC, version, codec, compression, content @ ..] = content else {
+            return Ok(Decompressed { codec: CacheCodec::Json, content: Cow::Borrowed(content) });
+        };
+
+        if *version != FORMAT_VERSION {
+            return Err(CodecError::UnsupportedVersion(*version).into());
+        }
+
+        let codec = CacheCodec::from_id(*codec)?;
+        let content = match CacheCompression::from_id(*compression)? {
+            CacheCompression::None => Cow::Borrowed(content),
+            compression => Cow::Owned(compression.decompress(content, MAX_DECOMPRESSED_LEN)?),
+        };
+
+        Ok(Decompressed { codec, content })
+    }
+}
+
+/// The content of a value, with the codec it was written in.
+pub(crate) struct Decompressed<'a> {
+    codec: CacheCodec,
+    content: Cow<'a, [u8]>,
+}
+
+impl Decompressed<'_> {
+    #[inline]
+    pub(crate) fn deserialize<T: DeserializeOwned>(&self) -> Result<T> {
+        self.codec.deserialize(&self.content)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{CacheCodec, CacheCodecConfig, CacheCompression};
+    use serde::{Deserialize, Serialize};
+
+    #[derive(Serialize, Deserialize, PartialEq, Debug)]
+    struct User {
+        id: u64,
+        name: String,
+    }
+
+    #[test]
+    fn round_trip() {
+        let user = User { id: 42, name: "x".repeat(4096) };
+
+        for codec in [CacheCodec::Json, CacheCodec::MessagePack, CacheCodec::Bincode] {
+            for compression in [CacheCompression::None, CacheCompression::Zstd, CacheCompression::Lz4] {
+                let config = CacheCodecConfig { codec, compression, compress_threshold: 1024 };
+                let encoded = config.encode(&user).unwrap();
+                if compression != CacheCompression::None {
+                    assert!(encoded.len() < 1024);
+                }
+
+                // Readable whatever is configured now.
+                assert_eq!(CacheCodecConfig::default().decode::<User>(&encoded).unwrap(), user);
+            }
+        }
+    }
+
+    #[test]
+    fn legacy_json() {
+        let user = CacheCodecConfig::default().decode::<User>(br#"{"id":1,"name":"Alice"}"#).unwrap();
+        assert_eq!(user, User { id: 1, name: "Alice".into() });
+    }
+
+    #[test]
+    fn reject_too_large() {
+        // A few bytes once compressed, too large once not.
+        for compression in [CacheCompression::Zstd, CacheCompression::Lz4] {
+            let compressed = compression.compress(vec![0; 4096]).unwrap();
+            assert!(compressed.len() < 1024);
+            assert_eq!(compression.decompress(&compressed, 4096).unwrap().len(), 4096);
+            assert!(compression.decompress(&compressed, 4095).is_err());
+        }
+    }
+}
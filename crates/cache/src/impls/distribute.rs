@@ -1,44 +1,318 @@
 use crate::error::ExtensionError;
-use fred::prelude::*;
+use crate::impls::codec::CacheCodecConfig;
+use crate::impls::loading::{loading_lock_resource, KeyedLocks, LOADING_LOCK_MAX_WAIT, LOADING_LOCK_TTL};
+use crate::impls::stats::{CacheStats, CacheStatsSnapshot};
 use ntex::{
     http::{Payload, RequestHead},
     util::Extensions,
     web::{FromRequest, HttpRequest, WebRequest},
 };
-use std::{
-    ops::{Deref, DerefMut},
-    sync::Arc,
-    time::Duration,
-};
+use serde::{de::DeserializeOwned, Serialize};
+use std::{future::Future, ops::Deref, sync::Arc, time::Duration};
+use tokio::sync::broadcast;
 use web_core::prelude::*;
+use web_guard::async_op::{AcquireOptions, AsyncOpGuard};
+
+pub mod memory;
+pub mod redis;
 
 pub mod prelude {
-    pub use crate::impls::distribute::DistributeCacheExt;
-    pub use fred::prelude::*;
+    pub use crate::impls::distribute::{DistributeCacheBackend, DistributeCacheExt};
 }
 
 pub type DistributeCacheKey = &'static str;
 pub type DistributeCacheGlobal = Arc<DistributeCache>;
-pub type DistributeCacheConfig = RedisConfig;
+
+/// Which backend the distribute cache runs on.
+#[derive(Clone, Debug)]
+pub enum DistributeCacheConfig {
+    Redis(redis::RedisDistributeCacheConfig),
+    /// In-process, nothing is shared with other instances.
+    /// For local development and tests without a Redis server.
+    Memory,
+}
+
+/// A message received from the publish-subscribe interface.
+#[derive(Clone, Debug)]
+pub struct DistributeCacheMessage {
+    pub channel: Arc<str>,
+    pub payload: Vec<u8>,
+}
+
+/// The operations every distribute cache backend supports.
+#[async_trait::async_trait]
+pub trait DistributeCacheBackend: Send + Sync {
+    /// Unique on each instance, tells where a published message comes from.
+    fn id(&self) -> &str;
+
+    async fn get(&self, key: &str) -> Result<Option<Vec<u8>>>;
+
+    /// No `ttl` means the value never expires.
+    async fn set(&self, key: &str, value: Vec<u8>, ttl: Option<Duration>) -> Result<()>;
+
+    /// Whether the key existed.
+    async fn del(&self, key: &str) -> Result<bool>;
+
+    /// Whether the key exists, so the `ttl` has been set.
+    async fn expire(&self, key: &str, ttl: Duration) -> Result<bool>;
+
+    /// Atomic, a missing key is treated as `0`, the current ttl is kept.
+    async fn incr_by(&self, key: &str, delta: i64) -> Result<i64>;
+
+    /// Same as `set`, and add the `key` into the set stored at each of the `tag_keys`, in one go.
+    /// A set expires with the longest-lived of its members, never if any of them doesn't.
+    async fn set_tagged(&self, key: &str, value: Vec<u8>, ttl: Option<Duration>, tag_keys: &[String]) -> Result<()>;
+
+    /// Delete the members of the set stored at `key`, and the set itself, in one go.
+    /// Returns how many members existed, a missing key is treated as an empty set.
+    async fn del_tagged(&self, tag_key: &str) -> Result<usize>;
+
+    async fn publish(&self, channel: &str, payload: Vec<u8>) -> Result<()>;
+
+    /// Messages of every subscribed channel are sent to all receivers,
+    /// check the `channel` before handling them.
+    async fn subscribe(&self, channel: &str) -> Result<broadcast::Receiver<DistributeCacheMessage>>;
+}
 
 pub trait DistributeCacheExt {
     fn distribute_cache(&self) -> std::result::Result<DistributeCacheExtension, ExtensionError>;
 }
 
 pub struct DistributeCache {
-    client: RedisClient,
+    backend: Box<dyn DistributeCacheBackend>,
+    stats: Arc<CacheStats>,
+    codec: CacheCodecConfig,
+    loading: KeyedLocks,
 }
 
-impl Deref for DistributeCache {
-    type Target = RedisClient;
-    fn deref(&self) -> &Self::Target {
-        &self.client
+/// Counts what goes through the backend.
+/// Redis expires keys on its own, so evictions are never counted.
+struct CountedBackend {
+    inner: Box<dyn DistributeCacheBackend>,
+    stats: Arc<CacheStats>,
+}
+
+#[async_trait::async_trait]
+impl DistributeCacheBackend for CountedBackend {
+    #[inline]
+    fn id(&self) -> &str {
+        self.inner.id()
+    }
+
+    async fn get(&self, key: &str) -> Result<Option<Vec<u8>>> {
+        let value = self.inner.get(key).await?;
+        self.stats.record_get(value.is_some());
+
+        Ok(value)
+    }
+
+    async fn set(&self, key: &str, value: Vec<u8>, ttl: Option<Duration>) -> Result<()> {
+        self.inner.set(key, value, ttl).await?;
+        self.stats.record_insert();
+
+        Ok(())
+    }
+
+    async fn del(&self, key: &str) -> Result<bool> {
+        let existed = self.inner.del(key).await?;
+        if existed {
+            self.stats.record_invalidation();
+        }
+
+        Ok(existed)
+    }
+
+    async fn expire(&self, key: &str, ttl: Duration) -> Result<bool> {
+        self.inner.expire(key, ttl).await
+    }
+
+    async fn incr_by(&self, key: &str, delta: i64) -> Result<i64> {
+        self.inner.incr_by(key, delta).await
+    }
+
+    async fn set_tagged(&self, key: &str, value: Vec<u8>, ttl: Option<Duration>, tag_keys: &[String]) -> Result<()> {
+        self.inner.set_tagged(key, value, ttl, tag_keys).await?;
+        self.stats.record_insert();
+
+        Ok(())
+    }
+
+    async fn del_tagged(&self, tag_key: &str) -> Result<usize> {
+        let count = self.inner.del_tagged(tag_key).await?;
+        for _ in 0..count {
+            self.stats.record_invalidation();
+        }
+
+        Ok(count)
+    }
+
+    async fn publish(&self, channel: &str, payload: Vec<u8>) -> Result<()> {
+        self.inner.publish(channel, payload).await
+    }
+
+    async fn subscribe(&self, channel: &str) -> Result<broadcast::Receiver<DistributeCacheMessage>> {
+        self.inner.subscribe(channel).await
+    }
+}
+
+/// Where the members of the `tag` are kept.
+#[inline]
+fn tag_key(tag: &str) -> String {
+    format!("web_cache:tag:{tag}")
+}
+
+impl DistributeCache {
+    #[inline]
+    pub fn stats(&self) -> CacheStatsSnapshot {
+        self.stats.snapshot()
+    }
+
+    /// What the `get_as` and `set_as` values are written in.
+    #[inline]
+    pub fn codec(&self) -> CacheCodecConfig {
+        self.codec
+    }
+
+    /// Invalidated by `invalidate_tag` with any of the `tags`, e.g. `&["user:42"]`.
+    /// The members of a tag are kept in a set, which expires with the longest-lived of them.
+    /// On a redis cluster, the key and its tags must hash to the same slot, e.g. with a `{hash tag}`.
+    pub async fn insert_tagged<V: Serialize>(
+        &self,
+        key: &str,
+        value: &V,
+        ttl: Option<Duration>,
+        tags: &[&str],
+    ) -> Result<()> {
+        let tag_keys = tags.iter().map(|tag| tag_key(tag)).collect::<Vec<_>>();
+
+        self.backend.set_tagged(key, self.codec.encode(value)?, ttl, &tag_keys).await
+    }
+
+    /// Invalidate all the entries tagged with `tag`, returns how many existed.
+    #[inline]
+    pub async fn invalidate_tag(&self, tag: &str) -> Result<usize> {
+        self.backend.del_tagged(&tag_key(tag)).await
+    }
+
+    /// Cache-aside, the loaded value will be stored with `ttl`.
+    /// Concurrent calls on the same missing key are coalesced in-process,
+    /// late arrivals wait for the value stored by the winner.
+    pub async fn get_or_load<V, F>(&self, key: &str, ttl: Option<Duration>, loader: F) -> Result<V>
+    where
+        V: Serialize + DeserializeOwned + Send + Sync,
+        F: Future<Output = Result<V>>,
+    {
+        self.load(key, ttl, None, loader).await
+    }
+
+    /// Same as `get_or_load`, but loaders on other instances are coalesced as well.
+    /// Loaded without the cross-instance lock if it can't be taken within `LOADING_LOCK_MAX_WAIT`.
+    pub async fn get_or_load_exclusive<V, F>(
+        &self,
+        key: &str,
+        ttl: Option<Duration>,
+        async_op_guard: &AsyncOpGuard,
+        loader: F,
+    ) -> Result<V>
+    where
+        V: Serialize + DeserializeOwned + Send + Sync,
+        F: Future<Output = Result<V>>,
+    {
+        let options = AcquireOptions::new(LOADING_LOCK_MAX_WAIT);
+
+        self.load(key, ttl, Some((async_op_guard, &options)), loader).await
+    }
+
+    /// Like `get_or_load_exclusive`, but waits for the cross-instance lock as the `options` say.
+    pub async fn get_or_load_exclusive_within<V, F>(
+        &self,
+        key: &str,
+        ttl: Option<Duration>,
+        async_op_guard: &AsyncOpGuard,
+        options: &AcquireOptions,
+        loader: F,
+    ) -> Result<V>
+    where
+        V: Serialize + DeserializeOwned + Send + Sync,
+        F: Future<Output = Result<V>>,
+    {
+        self.load(key, ttl, Some((async_op_guard, options)), loader).await
+    }
+
+    async fn load<V, F>(
+        &self,
+        key: &str,
+        ttl: Option<Duration>,
+        exclusive: Option<(&AsyncOpGuard, &AcquireOptions)>,
+        loader: F,
+    ) -> Result<V>
+    where
+        V: Serialize + DeserializeOwned + Send + Sync,
+        F: Future<Output = Result<V>>,
+    {
+        if let Some(value) = self.get_as(key).await? {
+            return Ok(value);
+        }
+
+        let _loading = self.loading.lock(key).await;
+
+        // The winner in this process may have stored it.
+        if let Some(value) = self.get_as(key).await? {
+            return Ok(value);
+        }
+
+        let store = async {
+            let value = loader.await?;
+            self.set_as(key, &value, ttl).await?;
+
+            Ok(value)
+        };
+
+        let Some((async_op_guard, options)) = exclusive else {
+            return store.await;
+        };
+
+        // Better loaded twice than stuck, e.g. if the lock nodes are down.
+        let resource = loading_lock_resource(key);
+        let lock = match async_op_guard.acquire_within(resource.as_bytes(), LOADING_LOCK_TTL, options).await {
+            Ok(lock) => Some(lock),
+            Err(error) => {
+                warn!(key, %error, "Loading without the cross-instance lock.");
+                None
+            }
+        };
+
+        // The winner on other instances may have stored it.
+        let result = match self.get_as(key).await {
+            Ok(Some(value)) => Ok(value),
+            Ok(None) => store.await,
+            Err(error) => Err(error),
+        };
+        if let Some(lock) = lock {
+            lock.release().await;
+        }
+
+        result
+    }
+
+    /// Decoded with the codec it was written in.
+    pub async fn get_as<V: DeserializeOwned>(&self, key: &str) -> Result<Option<V>> {
+        match self.backend.get(key).await? {
+            Some(content) => Ok(Some(self.codec.decode(&content)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Encoded with the configured codec, compressed if it's large enough.
+    pub async fn set_as<V: Serialize>(&self, key: &str, value: &V, ttl: Option<Duration>) -> Result<()> {
+        self.backend.set(key, self.codec.encode(value)?, ttl).await
     }
 }
 
-impl DerefMut for DistributeCache {
-    fn deref_mut(&mut self) -> &mut Self::Target {
-        &mut self.client
+impl Deref for DistributeCache {
+    type Target = dyn DistributeCacheBackend;
+    fn deref(&self) -> &Self::Target {
+        self.backend.as_ref()
     }
 }
 
@@ -77,30 +351,20 @@ impl<Err> FromRequest<Err> for DistributeCacheExtension {
     }
 }
 
-pub async fn generate(config: DistributeCacheConfig) -> Result<DistributeCacheGlobal> {
-    let client = DistributeCache {
-        client: RedisClient::new(
-            config,
-            Some(PerformanceConfig {
-                // 10s.
-                default_command_timeout: Duration::from_secs(10),
-                ..Default::default()
-            }),
-            None,
-            Some(ReconnectPolicy::new_constant(u32::MAX, 8_000)),
-        ),
+pub async fn generate(config: DistributeCacheConfig, codec: CacheCodecConfig) -> Result<DistributeCacheGlobal> {
+    let backend: Box<dyn DistributeCacheBackend> = match config {
+        DistributeCacheConfig::Redis(config) => Box::new(redis::generate(config).await?),
+        DistributeCacheConfig::Memory => Box::new(memory::InMemoryDistributeCache::default()),
     };
 
-    debug!("Connecting to the redis cache.");
-
-    // No need to wait for being connected.
-    #[allow(clippy::let_underscore_future)]
-    let _ = client.connect();
+    let stats = Arc::new(CacheStats::default());
 
-    // No need to use the `?` to wait for being connected.
-    let _ = client.wait_for_connect().await;
-
-    Ok(Arc::new(client))
+    Ok(Arc::new(DistributeCache {
+        backend: Box::new(CountedBackend { inner: backend, stats: Arc::clone(&stats) }),
+        stats,
+        codec,
+        loading: Default::default(),
+    }))
 }
 
 macro_rules! impl_ext {
@@ -126,3 +390,92 @@ macro_rules! impl_ext {
 impl_ext!(HttpRequest);
 impl_ext!(WebRequest<Err>);
 impl_ext!(RequestHead);
+
+#[cfg(test)]
+mod tests {
+    use super::{generate, tag_key, DistributeCacheConfig};
+    use crate::impls::loading::{loading_lock_resource, LOADING_LOCK_TTL};
+    use serde_json::json;
+    use std::time::Duration;
+    use web_guard::async_op::{generate_async_op_guard, AcquireOptions, AsyncOpGuardBackendConfig, AsyncOpGuardConfig};
+
+    #[ntex::test]
+    async fn invalidate_tag() {
+        let cache = generate(DistributeCacheConfig::Memory, Default::default()).await.unwrap();
+        cache.insert_tagged("a", &json!(1), None, &["user:42"]).await.unwrap();
+        cache.insert_tagged("b", &json!(2), None, &["user:42", "user:43"]).await.unwrap();
+        cache.insert_tagged("c", &json!(3), None, &["user:43"]).await.unwrap();
+
+        assert_eq!(cache.invalidate_tag("user:42").await.unwrap(), 2);
+        assert_eq!(cache.get("a").await.unwrap(), None);
+        assert_eq!(cache.get("b").await.unwrap(), None);
+        assert_eq!(cache.get_as::<u64>("c").await.unwrap(), Some(3));
+
+        // Only the existing ones are counted.
+        assert_eq!(cache.invalidate_tag("user:43").await.unwrap(), 1);
+    }
+
+    #[ntex::test]
+    async fn expire_tag_with_entries() {
+        let cache = generate(DistributeCacheConfig::Memory, Default::default()).await.unwrap();
+        cache.insert_tagged("a", &json!(1), Some(Duration::from_millis(20)), &["user:42"]).await.unwrap();
+        cache.insert_tagged("b", &json!(2), Some(Duration::from_millis(50)), &["user:42"]).await.unwrap();
+
+        // Lives as long as the longest-lived entry.
+        tokio::time::sleep(Duration::from_millis(30)).await;
+        assert!(cache.get(&tag_key("user:42")).await.is_err());
+        tokio::time::sleep(Duration::from_millis(30)).await;
+        assert_eq!(cache.get(&tag_key("user:42")).await.unwrap(), None);
+
+        // Kept while an entry never expires.
+        cache.insert_tagged("c", &json!(3), None, &["user:43"]).await.unwrap();
+        cache.insert_tagged("d", &json!(4), Some(Duration::from_millis(10)), &["user:43"]).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert_eq!(cache.invalidate_tag("user:43").await.unwrap(), 1);
+    }
+
+    #[ntex::test]
+    async fn invalidate_tag_while_inserting() {
+        let cache = generate(DistributeCacheConfig::Memory, Default::default()).await.unwrap();
+
+        let inserts = (0..100).map(|index| {
+            let cache = cache.clone();
+            tokio::spawn(async move {
+                let key = format!("tests:{index}");
+                cache.insert_tagged(&key, &json!(index), None, &["tests"]).await.unwrap();
+            })
+        });
+        let invalidations = (0..10).map(|_| {
+            let cache = cache.clone();
+            tokio::spawn(async move {
+                cache.invalidate_tag("tests").await.unwrap();
+            })
+        });
+        for task in inserts.chain(invalidations).collect::<Vec<_>>() {
+            task.await.unwrap();
+        }
+
+        // Every entry is still tagged, whichever of the calls came first.
+        cache.invalidate_tag("tests").await.unwrap();
+        for index in 0..100 {
+            assert_eq!(cache.get(&format!("tests:{index}")).await.unwrap(), None);
+        }
+    }
+
+    #[ntex::test]
+    async fn load_without_the_lock_held_too_long() {
+        let cache = generate(DistributeCacheConfig::Memory, Default::default()).await.unwrap();
+        let async_op_guard = generate_async_op_guard(AsyncOpGuardConfig::new(AsyncOpGuardBackendConfig::Memory));
+        let held = async_op_guard.lock(loading_lock_resource("tests").as_bytes(), LOADING_LOCK_TTL).await.unwrap();
+
+        let options = AcquireOptions::new(Duration::from_millis(50));
+        let value = cache
+            .get_or_load_exclusive_within("tests", None, &async_op_guard, &options, async { Ok(1) })
+            .await
+            .unwrap();
+        assert_eq!(value, 1);
+        assert_eq!(cache.get_as::<u64>("tests").await.unwrap(), Some(1));
+
+        held.release().await;
+    }
+}
@@ -0,0 +1,264 @@
+/// In-process backend of the distribute cache.
+/// Behaves like the redis one, but nothing is shared with other instances.
+use crate::impls::distribute::{DistributeCacheBackend, DistributeCacheMessage};
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tokio::sync::broadcast;
+use web_core::prelude::*;
+
+/// How many messages can be buffered for the slowest receiver.
+const MESSAGE_CAPACITY: usize = 1024;
+/// Expired entries are dropped once every such accesses.
+const PURGE_INTERVAL: usize = 1024;
+
+static INSTANCE_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+enum Value {
+    Bytes(Vec<u8>),
+    Set(HashSet<String>),
+}
+
+struct Entry {
+    value: Value,
+    expires_at: Option<Instant>,
+}
+
+#[inline]
+fn wrong_type(key: &str) -> anyhow::Error {
+    anyhow::anyhow!("Key `{key}` holds the wrong kind of value.")
+}
+
+impl Entry {
+    #[inline]
+    fn is_expired(&self, now: Instant) -> bool {
+        self.expires_at.is_some_and(|expires_at| expires_at <= now)
+    }
+}
+
+pub struct InMemoryDistributeCache {
+    id: String,
+    entries: Mutex<HashMap<String, Entry>>,
+    accesses: AtomicUsize,
+    /// One per subscribed channel, so the receivers only get the messages of theirs.
+    channels: Mutex<HashMap<String, broadcast::Sender<DistributeCacheMessage>>>,
+}
+
+impl Default for InMemoryDistributeCache {
+    fn default() -> Self {
+        InMemoryDistributeCache {
+            id: format!("in-memory-{}", INSTANCE_COUNTER.fetch_add(1, Ordering::Relaxed)),
+            entries: Default::default(),
+            accesses: Default::default(),
+            channels: Default::default(),
+        }
+    }
+}
+
+impl InMemoryDistributeCache {
+    /// Run `f` with the live entries, expired ones are removed before.
+    fn with_entries<R>(&self, key: &str, f: impl FnOnce(&mut HashMap<String, Entry>) -> R) -> R {
+        // UNWRAP: Nothing panics while holding the lock.
+        let mut entries = self.entries.lock().unwrap();
+        let now = Instant::now();
+
+        if self.accesses.fetch_add(1, Ordering::Relaxed) % PURGE_INTERVAL == 0 {
+            entries.retain(|_, entry| !entry.is_expired(now));
+        } else if entries.get(key).is_some_and(|entry| entry.is_expired(now)) {
+            entries.remove(key);
+        }
+
+        f(&mut entries)
+    }
+}
+
+#[async_trait::async_trait]
+impl DistributeCacheBackend for InMemoryDistributeCache {
+    #[inline]
+    fn id(&self) -> &str {
+        &self.id
+    }
+
+    async fn get(&self, key: &str) -> Result<Option<Vec<u8>>> {
+        self.with_entries(key, |entries| match entries.get(key).map(|entry| &entry.value) {
+            Some(Value::Bytes(value)) => Ok(Some(value.clone())),
+            Some(Value::Set(_)) => Err(wrong_type(key)),
+            None => Ok(None),
+        })
+    }
+
+    async fn set(&self, key: &str, value: Vec<u8>, ttl: Option<Duration>) -> Result<()> {
+        let expires_at = ttl.map(|ttl| Instant::now() + ttl);
+        self.with_entries(key, |entries| {
+            entries.insert(key.to_string(), Entry { value: Value::Bytes(value), expires_at })
+        });
+
+        Ok(())
+    }
+
+    async fn del(&self, key: &str) -> Result<bool> {
+        Ok(self.with_entries(key, |entries| entries.remove(key).is_some()))
+    }
+
+    async fn expire(&self, key: &str, ttl: Duration) -> Result<bool> {
+        Ok(self.with_entries(key, |entries| match entries.get_mut(key) {
+            Some(entry) => {
+                entry.expires_at = Some(Instant::now() + ttl);
+                true
+            }
+            None => false,
+        }))
+    }
+
+    async fn incr_by(&self, key: &str, delta: i64) -> Result<i64> {
+        self.with_entries(key, |entries| {
+            let entry = entries
+                .entry(key.to_string())
+                .or_insert_with(|| Entry { value: Value::Bytes(b"0".to_vec()), expires_at: None });
+            let Value::Bytes(bytes) = &mut entry.value else {
+                return Err(wrong_type(key));
+            };
+
+            let value = std::str::from_utf8(bytes)
+                .ok()
+                .and_then(|value| value.parse::<i64>().ok())
+                .and_then(|value| value.checked_add(delta))
+                .ok_or_else(|| anyhow::anyhow!("Value is not an integer or out of range."))?;
+            *bytes = value.to_string().into_bytes();
+
+            Ok(value)
+        })
+    }
+
+    async fn set_tagged(&self, key: &str, value: Vec<u8>, ttl: Option<Duration>, tag_keys: &[String]) -> Result<()> {
+        let now = Instant::now();
+        let expires_at = ttl.map(|ttl| now + ttl);
+        self.with_entries(key, |entries| {
+            // Checked first, so nothing is changed on failure.
+            for tag_key in tag_keys {
+                if entries
+                    .get(tag_key)
+                    .is_some_and(|entry| !entry.is_expired(now) && matches!(entry.value, Value::Bytes(_)))
+                {
+                    return Err(wrong_type(tag_key));
+                }
+            }
+
+            entries.insert(key.to_string(), Entry { value: Value::Bytes(value), expires_at });
+            for tag_key in tag_keys {
+                let entry = entries
+                    .entry(tag_key.clone())
+                    .and_modify(|entry| {
+                        if entry.is_expired(now) {
+                            *entry = Entry { value: Value::Set(Default::default()), expires_at }
+                        }
+                    })
+                    .or_insert_with(|| Entry { value: Value::Set(Default::default()), expires_at });
+                // Lives as long as its longest-lived member.
+                entry.expires_at = entry.expires_at.zip(expires_at).map(|(a, b)| a.max(b));
+                if let Value::Set(set) = &mut entry.value {
+                    set.insert(key.to_string());
+                }
+            }
+
+            Ok(())
+        })
+    }
+
+    async fn del_tagged(&self, tag_key: &str) -> Result<usize> {
+        let now = Instant::now();
+        self.with_entries(tag_key, |entries| {
+            let members = match entries.remove(tag_key) {
+                Some(Entry { value: Value::Set(set), .. }) => set,
+                Some(entry) => {
+                    entries.insert(tag_key.to_string(), entry);
+                    return Err(wrong_type(tag_key));
+                }
+                None => return Ok(0),
+            };
+
+            Ok(members
+                .iter()
+                .filter_map(|member| entries.remove(member))
+                .filter(|entry| !entry.is_expired(now))
+                .count())
+        })
+    }
+
+    async fn publish(&self, channel: &str, payload: Vec<u8>) -> Result<()> {
+        // UNWRAP: Nothing panics while holding the lock.
+        if let Some(message_tx) = self.channels.lock().unwrap().get(channel) {
+            // Nobody is listening now, that's fine.
+            let _ = message_tx.send(DistributeCacheMessage { channel: channel.into(), payload });
+        }
+
+        Ok(())
+    }
+
+    async fn subscribe(&self, channel: &str) -> Result<broadcast::Receiver<DistributeCacheMessage>> {
+        // UNWRAP: Nothing panics while holding the lock.
+        let mut channels = self.channels.lock().unwrap();
+        // The channels nobody listens to any more.
+        channels.retain(|_, message_tx| message_tx.receiver_count() > 0);
+
+        Ok(channels.entry(channel.to_string()).or_insert_with(|| broadcast::channel(MESSAGE_CAPACITY).0).subscribe())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::InMemoryDistributeCache;
+    use crate::impls::distribute::DistributeCacheBackend;
+    use std::time::Duration;
+
+    #[ntex::test]
+    async fn expire_entries() {
+        let cache = InMemoryDistributeCache::default();
+        cache.set("a", b"1".to_vec(), Some(Duration::from_millis(20))).await.unwrap();
+        cache.set("b", b"2".to_vec(), None).await.unwrap();
+        assert_eq!(cache.get("a").await.unwrap().as_deref(), Some(&b"1"[..]));
+
+        tokio::time::sleep(Duration::from_millis(30)).await;
+        assert_eq!(cache.get("a").await.unwrap(), None);
+        assert!(!cache.expire("a", Duration::from_secs(1)).await.unwrap());
+        assert!(cache.del("b").await.unwrap());
+        assert!(!cache.del("b").await.unwrap());
+    }
+
+    #[ntex::test]
+    async fn incr_by() {
+        let cache = InMemoryDistributeCache::default();
+        assert_eq!(cache.incr_by("counter", 2).await.unwrap(), 2);
+        assert_eq!(cache.incr_by("counter", -5).await.unwrap(), -3);
+        assert_eq!(cache.get("counter").await.unwrap().as_deref(), Some(&b"-3"[..]));
+
+        cache.set("text", b"abc".to_vec(), None).await.unwrap();
+        assert!(cache.incr_by("text", 1).await.is_err());
+    }
+
+    #[ntex::test]
+    async fn publish_subscribe() {
+        let cache = InMemoryDistributeCache::default();
+        let mut message_rx = cache.subscribe("events").await.unwrap();
+        cache.publish("events", b"hello".to_vec()).await.unwrap();
+
+        let message = message_rx.recv().await.unwrap();
+        assert_eq!((&*message.channel, &message.payload[..]), ("events", &b"hello"[..]));
+    }
+
+    #[ntex::test]
+    async fn subscribe_own_channel_only() {
+        let cache = InMemoryDistributeCache::default();
+        let mut events_rx = cache.subscribe("events").await.unwrap();
+        let mut others_rx = cache.subscribe("others").await.unwrap();
+        cache.publish("others", b"1".to_vec()).await.unwrap();
+        cache.publish("events", b"2".to_vec()).await.unwrap();
+        cache.publish("unknown", b"3".to_vec()).await.unwrap();
+
+        assert_eq!(&events_rx.recv().await.unwrap().payload[..], b"2");
+        assert!(events_rx.try_recv().is_err());
+        assert_eq!(&others_rx.recv().await.unwrap().payload[..], b"1");
+        assert!(others_rx.try_recv().is_err());
+    }
+}
@@ -0,0 +1,450 @@
+/// Redis backend of the distribute cache.
+use crate::impls::distribute::{DistributeCacheBackend, DistributeCacheMessage};
+use fred::clients::SubscriberClient;
+use fred::prelude::*;
+use std::time::Duration;
+use tokio::sync::{broadcast, OnceCell};
+use web_core::prelude::*;
+use web_guard::lease::redis::RedisLeaseConnection;
+
+/// How many messages can be buffered for the slowest receiver.
+const MESSAGE_CAPACITY: usize = 1024;
+const DEFAULT_COMMAND_TIMEOUT: Duration = Duration::from_secs(10);
+const DEFAULT_RECONNECT_DELAY: Duration = Duration::from_secs(8);
+const DEFAULT_RECONNECT_MAX_DELAY: Duration = Duration::from_secs(60);
+
+/// KEYS: the key and the tags. ARGV: the value and the ttl in milliseconds, `0` if never expires.
+const SET_TAGGED: &str = r#"
+local ttl = tonumber(ARGV[2])
+if ttl > 0 then
+    redis.call('SET', KEYS[1], ARGV[1], 'PX', ttl)
+else
+    redis.call('SET', KEYS[1], ARGV[1])
+end
+for i = 2, #KEYS do
+    local tag_ttl = redis.call('PTTL', KEYS[i])
+    redis.call('SADD', KEYS[i], KEYS[1])
+    if ttl == 0 then
+        redis.call('PERSIST', KEYS[i])
+    elseif tag_ttl == -2 or (tag_ttl >= 0 and tag_ttl < ttl) then
+        redis.call('PEXPIRE', KEYS[i], ttl)
+    end
+end
+return 1
+"#;
+
+/// KEYS: the tag.
+const DEL_TAGGED: &str = r#"
+local count = 0
+for _, key in ipairs(redis.call('SMEMBERS', KEYS[1])) do
+    count = count + redis.call('UNLINK', key)
+end
+redis.call('UNLINK', KEYS[1])
+return count
+"#;
+
+/// Topology, connections and reconnection of redis, read from the env:
+/// - `REDIS_URI`, e.g. `redis://`, `rediss://`, `redis-sentinel://` or `redis-cluster://` ones.
+///   Without it, the server is built from:
+///   - `REDIS_TOPOLOGY`, `centralized` (default), `sentinel` or `cluster`.
+///   - `REDIS_HOSTS`, e.g. `10.0.0.1:26379,10.0.0.2:26379`.
+///   - `REDIS_SENTINEL_SERVICE_NAME`, required by the sentinel topology.
+///   - `REDIS_USERNAME`, `REDIS_PASSWORD` and `REDIS_DATABASE`.
+/// - `REDIS_TLS`, `true` to connect with TLS, requires the `redis-tls` feature.
+/// - `REDIS_POOL_SIZE`, `1` by default.
+/// - `REDIS_COMMAND_TIMEOUT`, `10s` by default.
+/// - `REDIS_RECONNECT_POLICY`, `constant` (default), `linear` or `exponential`.
+/// - `REDIS_RECONNECT_MAX_ATTEMPTS`, unlimited by default.
+/// - `REDIS_RECONNECT_DELAY`, `8s` by default, the min delay of the exponential policy.
+/// - `REDIS_RECONNECT_MAX_DELAY`, `60s` by default, not used by the constant policy.
+#[derive(Clone, Debug)]
+pub struct RedisDistributeCacheConfig {
+    pub config: RedisConfig,
+    pub pool_size: usize,
+    pub command_timeout: Duration,
+    pub reconnect_policy: ReconnectPolicy,
+}
+
+impl RedisDistributeCacheConfig {
+    /// With the default pool size, command timeout and reconnect policy.
+    pub fn from_url(url: &str) -> Result<Self> {
+        Ok(RedisDistributeCacheConfig {
+            config: RedisConfig::from_url(url)?,
+            pool_size: 1,
+            command_timeout: DEFAULT_COMMAND_TIMEOUT,
+            reconnect_policy: ReconnectPolicy::new_constant(u32::MAX, DEFAULT_RECONNECT_DELAY.as_millis() as u32),
+        })
+    }
+
+    pub fn from_env() -> Result<Self> {
+        let mut config = match web_env::var("REDIS_URI")? {
+            Some(uri) => RedisConfig::from_url(&uri)?,
+            None => config_from_env()?,
+        };
+
+        if web_env::var_parsed::<bool>("REDIS_TLS")?.unwrap_or_default() {
+            enable_tls(&mut config)?;
+        }
+
+        let delay = duration_var("REDIS_RECONNECT_DELAY")?.unwrap_or(DEFAULT_RECONNECT_DELAY).as_millis() as u32;
+        let max_delay =
+            duration_var("REDIS_RECONNECT_MAX_DELAY")?.unwrap_or(DEFAULT_RECONNECT_MAX_DELAY).as_millis() as u32;
+        let max_attempts = web_env::var_parsed::<u32>("REDIS_RECONNECT_MAX_ATTEMPTS")?.unwrap_or(u32::MAX);
+        let reconnect_policy = match web_env::var("REDIS_RECONNECT_POLICY")?.as_deref() {
+            None | Some("constant") => ReconnectPolicy::new_constant(max_attempts, delay),
+            Some("linear") => ReconnectPolicy::new_linear(max_attempts, max_delay, delay),
+            Some("exponential") => ReconnectPolicy::new_exponential(max_attempts, delay, max_delay, 2),
+            Some(policy) => anyhow::bail!("Unknown REDIS_RECONNECT_POLICY `{policy}`."),
+        };
+
+        let pool_size = web_env::var_parsed::<usize>("REDIS_POOL_SIZE")?.unwrap_or(1);
+        if pool_size == 0 {
+            anyhow::bail!("REDIS_POOL_SIZE must be greater than 0.");
+        }
+
+        Ok(RedisDistributeCacheConfig {
+            config,
+            pool_size,
+            command_timeout: duration_var("REDIS_COMMAND_TIMEOUT")?.unwrap_or(DEFAULT_COMMAND_TIMEOUT),
+            reconnect_policy,
+        })
+    }
+
+    /// For the redis leases, so they connect the same way, only the server differs.
+    pub fn lease_connection(&self) -> RedisLeaseConnection {
+        RedisLeaseConnection {
+            #[cfg(feature = "redis-tls")]
+            tls: self.config.tls.clone(),
+            pool_size: self.pool_size,
+            command_timeout: self.command_timeout,
+            reconnect_policy: self.reconnect_policy.clone(),
+        }
+    }
+}
+
+#[inline]
+fn duration_var(key: &str) -> Result<Option<Duration>> {
+    Ok(web_env::var_parsed::<humantime::Duration>(key)?.map(Duration::from))
+}
+
+fn config_from_env() -> Result<RedisConfig> {
+    let hosts = web_env::list("REDIS_HOSTS")?
+        .iter()
+        .map(|host| {
+            let (host, port) =
+                host.rsplit_once(':').ok_or_else(|| anyhow::anyhow!("Redis host `{host}` requires a port."))?;
+
+            Ok((host.to_string(), port.parse::<u16>()?))
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    let server = match web_env::var("REDIS_TOPOLOGY")?.as_deref() {
+        None | Some("centralized") => match hosts.as_slice() {
+            [] => ServerConfig::default(),
+            [(host, port)] => ServerConfig::new_centralized(host.as_str(), *port),
+            _ => anyhow::bail!("The centralized redis accepts only one host."),
+        },
+        Some("sentinel") => ServerConfig::new_sentinel(hosts, web_env::required_var("REDIS_SENTINEL_SERVICE_NAME")?),
+        Some("cluster") => ServerConfig::new_clustered(hosts),
+        Some(topology) => anyhow::bail!("Unknown REDIS_TOPOLOGY `{topology}`."),
+    };
+
+    Ok(RedisConfig {
+        server,
+        username: web_env::var("REDIS_USERNAME")?,
+        password: web_env::var("REDIS_PASSWORD")?,
+        database: web_env::var_parsed("REDIS_DATABASE")?,
+        ..Default::default()
+    })
+}
+
+#[cfg(feature = "redis-tls")]
+fn enable_tls(config: &mut RedisConfig) -> Result<()> {
+    if config.tls.is_none() {
+        config.tls = Some(fred::types::TlsConnector::default_rustls()?.into());
+    }
+
+    Ok(())
+}
+
+#[cfg(not(feature = "redis-tls"))]
+fn enable_tls(_config: &mut RedisConfig) -> Result<()> {
+    anyhow::bail!("REDIS_TLS requires the `redis-tls` feature.")
+}
+
+pub struct RedisDistributeCache {
+    id: String,
+    pool: RedisPool,
+    subscriber: OnceCell<(SubscriberClient, broadcast::Sender<DistributeCacheMessage>)>,
+}
+
+impl RedisDistributeCache {
+    /// The publish-subscribe interface needs a standalone connection,
+    /// it will be lazily connected and shared by all subscriptions.
+    async fn subscriber(&self) -> &(SubscriberClient, broadcast::Sender<DistributeCacheMessage>) {
+        self.subscriber
+            .get_or_init(|| async {
+                let client = self.pool.next();
+                let subscriber = SubscriberClient::new(
+                    client.client_config(),
+                    Some(client.perf_config()),
+                    Some(client.connection_config().clone()),
+                    client.client_reconnect_policy(),
+                );
+
+                debug!("Connecting to the redis subscriber.");
+
+                // No need to wait for being connected.
+                #[allow(clippy::let_underscore_future)]
+                let _ = subscriber.connect();
+                let _ = subscriber.wait_for_connect().await;
+
+                // Subscriptions will be restored after reconnecting.
+                #[allow(clippy::let_underscore_future)]
+                let _ = subscriber.manage_subscriptions();
+
+                let (message_tx, _) = broadcast::channel(MESSAGE_CAPACITY);
+                let mut message_rx = subscriber.message_rx();
+                let forward_tx = message_tx.clone();
+
+                tokio::spawn(async move {
+                    loop {
+                        let message = match message_rx.recv().await {
+                            Ok(message) => message,
+                            Err(broadcast::error::RecvError::Lagged(count)) => {
+                                warn!(count, "Redis subscriber lagged, messages skipped.");
+                                continue;
+                            }
+                            Err(broadcast::error::RecvError::Closed) => break,
+                        };
+
+                        match message.value.as_bytes() {
+                            Some(payload) => {
+                                // Nobody is listening now, that's fine.
+                                let _ = forward_tx.send(DistributeCacheMessage {
+                                    channel: (*message.channel).into(),
+                                    payload: payload.to_vec(),
+                                });
+                            }
+                            None => warn!(value = ?message.value, "Unsupported redis message."),
+                        }
+                    }
+                });
+
+                (subscriber, message_tx)
+            })
+            .await
+    }
+}
+
+#[async_trait::async_trait]
+impl DistributeCacheBackend for RedisDistributeCache {
+    #[inline]
+    fn id(&self) -> &str {
+        &self.id
+    }
+
+    async fn get(&self, key: &str) -> Result<Option<Vec<u8>>> {
+        Ok(self.pool.get::<Option<Vec<u8>>, _>(key).await?)
+    }
+
+    async fn set(&self, key: &str, value: Vec<u8>, ttl: Option<Duration>) -> Result<()> {
+        let expiration = ttl.map(|ttl| Expiration::PX(ttl.as_millis() as i64));
+        self.pool.set::<(), _, _>(key, value, expiration, None, false).await?;
+
+        Ok(())
+    }
+
+    async fn del(&self, key: &str) -> Result<bool> {
+        Ok(self.pool.del::<u32, _>(key).await? > 0)
+    }
+
+    async fn expire(&self, key: &str, ttl: Duration) -> Result<bool> {
+        Ok(self.pool.pexpire::<bool, _>(key, ttl.as_millis() as i64, None).await?)
+    }
+
+    async fn incr_by(&self, key: &str, delta: i64) -> Result<i64> {
+        Ok(self.pool.incr_by::<i64, _>(key, delta).await?)
+    }
+
+    async fn set_tagged(&self, key: &str, value: Vec<u8>, ttl: Option<Duration>, tag_keys: &[String]) -> Result<()> {
+        let mut keys = vec![key.to_string()];
+        keys.extend_from_slice(tag_keys);
+        let ttl = ttl.map_or(0, |ttl| ttl.as_millis().max(1) as i64);
+        self.pool
+            .eval::<(), _, _, _>(SET_TAGGED, keys, vec![RedisValue::Bytes(value.into()), RedisValue::Integer(ttl)])
+            .await?;
+
+        Ok(())
+    }
+
+    async fn del_tagged(&self, tag_key: &str) -> Result<usize> {
+        Ok(self.pool.eval::<u64, _, _, _>(DEL_TAGGED, vec![tag_key], Vec::<RedisValue>::new()).await? as usize)
+    }
+
+    async fn publish(&self, channel: &str, payload: Vec<u8>) -> Result<()> {
+        self.pool.next().publish::<(), _, _>(channel, payload).await?;
+
+        Ok(())
+    }
+
+    async fn subscribe(&self, channel: &str) -> Result<broadcast::Receiver<DistributeCacheMessage>> {
+        let (subscriber, message_tx) = self.subscriber().await;
+        // Subscribe the receiver first, so no message will be missed.
+        let message_rx = message_tx.subscribe();
+        subscriber.subscribe(channel).await?;
+
+        Ok(message_rx)
+    }
+}
+
+/// Shared by the other redis backends, e.g. the job queue.
+pub(crate) async fn connect(config: RedisDistributeCacheConfig) -> Result<RedisPool> {
+    let pool = RedisPool::new(
+        config.config,
+        Some(PerformanceConfig { default_command_timeout: config.command_timeout, ..Default::default() }),
+        None,
+        Some(config.reconnect_policy),
+        config.pool_size,
+    )?;
+
+    debug!(size = pool.size(), "Connecting to the redis cache.");
+
+    // No need to wait for being connected.
+    #[allow(clippy::let_underscore_future)]
+    let _ = pool.connect();
+
+    // No need to use the `?` to wait for being connected.
+    let _ = pool.wait_for_connect().await;
+
+    Ok(pool)
+}
+
+pub async fn generate(config: RedisDistributeCacheConfig) -> Result<RedisDistributeCache> {
+    let pool = connect(config).await?;
+
+    Ok(RedisDistributeCache { id: pool.next().id().to_string(), pool, subscriber: Default::default() })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::RedisDistributeCacheConfig;
+    use fred::prelude::*;
+    use std::sync::Mutex;
+    use std::time::Duration;
+
+    /// The env is shared by the tests.
+    static MUTEX: Mutex<()> = Mutex::new(());
+
+    const KEYS: [&str; 14] = [
+        "REDIS_URI",
+        "REDIS_TOPOLOGY",
+        "REDIS_HOSTS",
+        "REDIS_SENTINEL_SERVICE_NAME",
+        "REDIS_USERNAME",
+        "REDIS_PASSWORD",
+        "REDIS_DATABASE",
+        "REDIS_TLS",
+        "REDIS_POOL_SIZE",
+        "REDIS_COMMAND_TIMEOUT",
+        "REDIS_RECONNECT_POLICY",
+        "REDIS_RECONNECT_MAX_ATTEMPTS",
+        "REDIS_RECONNECT_DELAY",
+        "REDIS_RECONNECT_MAX_DELAY",
+    ];
+
+    fn from_env(vars: &[(&str, &str)]) -> anyhow::Result<RedisDistributeCacheConfig> {
+        // The `.env` is loaded on the first read, never overriding what's set.
+        let _ = web_env::var("REDIS_URI");
+
+        for key in KEYS {
+            std::env::remove_var(key);
+        }
+        for (key, value) in vars {
+            std::env::set_var(key, value);
+        }
+
+        RedisDistributeCacheConfig::from_env()
+    }
+
+    fn hosts(config: &RedisDistributeCacheConfig) -> Vec<String> {
+        config.config.server.hosts().iter().map(|server| format!("{}:{}", &*server.host, server.port)).collect()
+    }
+
+    #[test]
+    fn topologies() {
+        let _guard = MUTEX.lock().unwrap();
+
+        let config = from_env(&[("REDIS_URI", "redis://:secret@10.0.0.1:6380/2")]).unwrap();
+        assert!(config.config.server.is_centralized());
+        assert_eq!(hosts(&config), ["10.0.0.1:6380"]);
+        assert_eq!((config.config.password.as_deref(), config.config.database), (Some("secret"), Some(2)));
+
+        let config = from_env(&[("REDIS_HOSTS", "10.0.0.1:6379"), ("REDIS_DATABASE", "1")]).unwrap();
+        assert!(config.config.server.is_centralized());
+        assert_eq!(hosts(&config), ["10.0.0.1:6379"]);
+        assert_eq!(config.config.database, Some(1));
+
+        let config = from_env(&[
+            ("REDIS_TOPOLOGY", "sentinel"),
+            ("REDIS_HOSTS", "10.0.0.1:26379,10.0.0.2:26379"),
+            ("REDIS_SENTINEL_SERVICE_NAME", "mymaster"),
+        ])
+        .unwrap();
+        assert!(
+            matches!(&config.config.server, ServerConfig::Sentinel { service_name, .. } if service_name == "mymaster")
+        );
+        assert_eq!(hosts(&config), ["10.0.0.1:26379", "10.0.0.2:26379"]);
+
+        let config =
+            from_env(&[("REDIS_TOPOLOGY", "cluster"), ("REDIS_HOSTS", "10.0.0.1:7000,10.0.0.2:7001")]).unwrap();
+        assert!(config.config.server.is_clustered());
+        assert_eq!(hosts(&config), ["10.0.0.1:7000", "10.0.0.2:7001"]);
+
+        // A single centralized host, with its port.
+        assert!(from_env(&[("REDIS_HOSTS", "10.0.0.1:6379,10.0.0.2:6379")]).is_err());
+        assert!(from_env(&[("REDIS_HOSTS", "10.0.0.1")]).is_err());
+        assert!(from_env(&[("REDIS_TOPOLOGY", "ring")]).is_err());
+    }
+
+    #[test]
+    fn sentinel_requires_service_name() {
+        let _guard = MUTEX.lock().unwrap();
+
+        let result = from_env(&[("REDIS_TOPOLOGY", "sentinel"), ("REDIS_HOSTS", "10.0.0.1:26379")]);
+        assert!(result.unwrap_err().to_string().contains("REDIS_SENTINEL_SERVICE_NAME"));
+    }
+
+    #[test]
+    fn reconnect_policies() {
+        let _guard = MUTEX.lock().unwrap();
+
+        let config = from_env(&[("REDIS_URI", "redis://127.0.0.1:6379")]).unwrap();
+        assert!(matches!(config.reconnect_policy, ReconnectPolicy::Constant { delay: 8000, .. }));
+        assert_eq!((config.pool_size, config.command_timeout), (1, Duration::from_secs(10)));
+
+        let config = from_env(&[
+            ("REDIS_URI", "redis://127.0.0.1:6379"),
+            ("REDIS_RECONNECT_POLICY", "exponential"),
+            ("REDIS_RECONNECT_MAX_ATTEMPTS", "5"),
+            ("REDIS_RECONNECT_DELAY", "100ms"),
+            ("REDIS_RECONNECT_MAX_DELAY", "10s"),
+            ("REDIS_POOL_SIZE", "4"),
+            ("REDIS_COMMAND_TIMEOUT", "2s"),
+        ])
+        .unwrap();
+        assert!(matches!(
+            config.reconnect_policy,
+            ReconnectPolicy::Exponential { max_attempts: 5, min_delay: 100, max_delay: 10_000, .. }
+        ));
+        assert_eq!((config.pool_size, config.command_timeout), (4, Duration::from_secs(2)));
+
+        let config =
+            from_env(&[("REDIS_URI", "redis://127.0.0.1:6379"), ("REDIS_RECONNECT_POLICY", "linear")]).unwrap();
+        assert!(matches!(config.reconnect_policy, ReconnectPolicy::Linear { .. }));
+
+        let result = from_env(&[("REDIS_URI", "redis://127.0.0.1:6379"), ("REDIS_RECONNECT_POLICY", "random")]);
+        assert!(result.unwrap_err().to_string().contains("REDIS_RECONNECT_POLICY"));
+        assert!(from_env(&[("REDIS_URI", "redis://127.0.0.1:6379"), ("REDIS_POOL_SIZE", "0")]).is_err());
+    }
+}
@@ -0,0 +1,254 @@
+/// Event bus - Typed notifications across instances, on the publish-subscribe interface of the distribute cache.
+/// The redis subscriptions are restored by the client after reconnecting,
+/// events published while being disconnected are lost.
+use crate::error::ExtensionError;
+use crate::impls::codec::CacheCodecConfig;
+use crate::impls::distribute::{DistributeCacheConfig, DistributeCacheGlobal, DistributeCacheMessage};
+use ntex::{
+    http::{Payload, RequestHead},
+    util::Extensions,
+    web::{FromRequest, HttpRequest, WebRequest},
+};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::{marker::PhantomData, ops::Deref, sync::Arc};
+use tokio::sync::broadcast::{self, error::RecvError};
+use web_core::prelude::*;
+
+pub type EventBusGlobal = Arc<EventBus>;
+
+#[inline]
+fn channel_name(name: &str) -> String {
+    format!("web_cache:event:{name}")
+}
+
+/// A typed channel, implemented on a marker type or the event itself.
+pub trait EventChannel: 'static {
+    /// Unique among the channels.
+    const NAME: &'static str;
+    type Event: Serialize + DeserializeOwned + Send + 'static;
+}
+
+/// Where the events go, read from `EVENT_BUS_BACKEND`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum EventBusConfig {
+    /// Shared by every instance on the distribute cache.
+    #[default]
+    Distribute,
+    /// In-process, for a single instance or tests.
+    Memory,
+}
+
+impl EventBusConfig {
+    /// `distribute` (default) or `memory`.
+    pub fn from_env() -> Result<Self> {
+        match web_env::var("EVENT_BUS_BACKEND")?.as_deref() {
+            None | Some("distribute") => Ok(EventBusConfig::Distribute),
+            Some("memory") => Ok(EventBusConfig::Memory),
+            Some(backend) => anyhow::bail!("Unknown EVENT_BUS_BACKEND `{backend}`."),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct OutgoingEvent<'a, T> {
+    origin: &'a str,
+    event: &'a T,
+}
+
+/// An event received, with the instance it comes from.
+#[derive(Deserialize, Debug)]
+pub struct EventBusMessage<T> {
+    pub origin: String,
+    pub event: T,
+}
+
+pub struct EventBus {
+    transport: DistributeCacheGlobal,
+    codec: CacheCodecConfig,
+}
+
+impl EventBus {
+    /// Unique on each instance, compare it with the `origin` of the messages to skip the local ones.
+    #[inline]
+    pub fn id(&self) -> &str {
+        self.transport.id()
+    }
+
+    /// Sent to the subscribers of every instance, this one included.
+    pub async fn publish<C: EventChannel>(&self, event: &C::Event) -> Result<()> {
+        let payload = self.codec.encode(&OutgoingEvent { origin: self.id(), event })?;
+
+        self.transport.publish(&channel_name(C::NAME), payload).await
+    }
+
+    /// Only the events published after subscribing are received.
+    pub async fn subscribe<C: EventChannel>(&self) -> Result<EventSubscription<C>> {
+        let channel = channel_name(C::NAME);
+        let message_rx = self.transport.subscribe(&channel).await?;
+
+        Ok(EventSubscription { channel, message_rx, codec: self.codec, _channel: PhantomData })
+    }
+}
+
+pub struct EventSubscription<C: EventChannel> {
+    channel: String,
+    message_rx: broadcast::Receiver<DistributeCacheMessage>,
+    codec: CacheCodecConfig,
+    _channel: PhantomData<C>,
+}
+
+impl<C: EventChannel> EventSubscription<C> {
+    /// `None` once the bus is dropped.
+    /// Undecodable events and the ones missed by a lagging receiver are skipped with a warning.
+    pub async fn recv(&mut self) -> Option<EventBusMessage<C::Event>> {
+        loop {
+            let message = match self.message_rx.recv().await {
+                Ok(message) => message,
+                Err(RecvError::Lagged(count)) => {
+                    warn!(channel = C::NAME, count, "Event bus subscription lagged, events skipped.");
+                    continue;
+                }
+                Err(RecvError::Closed) => return None,
+            };
+
+            if *message.channel != *self.channel {
+                continue;
+            }
+
+            match self.codec.decode::<EventBusMessage<C::Event>>(&message.payload) {
+                Ok(message) => return Some(message),
+                Err(error) => warn!(channel = C::NAME, %error, "Undecodable event skipped."),
+            }
+        }
+    }
+}
+
+pub struct EventBusExtension(EventBusGlobal);
+
+impl Deref for EventBusExtension {
+    type Target = EventBusGlobal;
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl EventBusExtension {
+    #[inline]
+    pub fn set_into_req(extensions: &mut Extensions, global: EventBusGlobal) {
+        if !extensions.contains::<EventBusExtension>() {
+            extensions.insert(EventBusExtension(global))
+        }
+    }
+
+    #[inline]
+    fn get_from_req(extensions: &mut Extensions) -> std::result::Result<EventBusExtension, ExtensionError> {
+        extensions
+            .get::<EventBusExtension>()
+            .ok_or(ExtensionError::EventBusMissing)
+            .map(|ext| EventBusExtension(Arc::clone(&ext.0)))
+    }
+}
+
+impl<Err> FromRequest<Err> for EventBusExtension {
+    type Error = ExtensionError;
+
+    #[inline]
+    async fn from_request(req: &HttpRequest, _payload: &mut Payload) -> std::result::Result<Self, Self::Error> {
+        EventBusExtension::get_from_req(&mut req.extensions_mut())
+    }
+}
+
+pub trait EventBusExt {
+    fn event_bus(&self) -> std::result::Result<EventBusExtension, ExtensionError>;
+}
+
+/// The distribute backend shares the connections of `distribute_cache`.
+pub async fn generate(config: EventBusConfig, distribute_cache: DistributeCacheGlobal) -> Result<EventBusGlobal> {
+    let codec = distribute_cache.codec();
+    let transport = match config {
+        EventBusConfig::Distribute => distribute_cache,
+        EventBusConfig::Memory => crate::impls::distribute::generate(DistributeCacheConfig::Memory, codec).await?,
+    };
+
+    Ok(Arc::new(EventBus { transport, codec }))
+}
+
+macro_rules! impl_ext {
+    ($ident: ident) => {
+        impl EventBusExt for $ident {
+            #[inline]
+            fn event_bus(&self) -> std::result::Result<EventBusExtension, ExtensionError> {
+                EventBusExtension::get_from_req(&mut self.extensions_mut())
+            }
+        }
+    };
+
+    ($ident: ident<$($genetic: tt),+>) => {
+        impl<$($genetic)+> EventBusExt for $ident<$($genetic)+> {
+            #[inline]
+            fn event_bus(&self) -> std::result::Result<EventBusExtension, ExtensionError> {
+                EventBusExtension::get_from_req(&mut self.extensions_mut())
+            }
+        }
+    }
+}
+
+impl_ext!(HttpRequest);
+impl_ext!(WebRequest<Err>);
+impl_ext!(RequestHead);
+
+#[cfg(test)]
+mod tests {
+    use super::{channel_name, generate, EventBusConfig, EventChannel};
+    use crate::impls::distribute::{self, DistributeCacheConfig};
+    use serde::{Deserialize, Serialize};
+    use std::time::Duration;
+
+    #[derive(Serialize, Deserialize, PartialEq, Debug)]
+    struct UserLoggedOut {
+        user_id: u64,
+    }
+
+    impl EventChannel for UserLoggedOut {
+        const NAME: &'static str = "tests:user_logged_out";
+        type Event = Self;
+    }
+
+    struct ConfigChanged;
+
+    impl EventChannel for ConfigChanged {
+        const NAME: &'static str = "tests:config_changed";
+        type Event = String;
+    }
+
+    #[ntex::test]
+    async fn publish_and_subscribe() {
+        let distribute_cache = distribute::generate(DistributeCacheConfig::Memory, Default::default()).await.unwrap();
+        let event_bus = generate(EventBusConfig::Distribute, distribute_cache.clone()).await.unwrap();
+        let mut subscription = event_bus.subscribe::<UserLoggedOut>().await.unwrap();
+
+        // Other channels and undecodable events are skipped.
+        event_bus.publish::<ConfigChanged>(&"log_level".to_string()).await.unwrap();
+        distribute_cache.publish(&channel_name(UserLoggedOut::NAME), b"not an event".to_vec()).await.unwrap();
+        event_bus.publish::<UserLoggedOut>(&UserLoggedOut { user_id: 42 }).await.unwrap();
+
+        let message = tokio::time::timeout(Duration::from_secs(1), subscription.recv()).await.unwrap().unwrap();
+        assert_eq!(message.event, UserLoggedOut { user_id: 42 });
+        assert_eq!(message.origin, event_bus.id());
+    }
+
+    #[ntex::test]
+    async fn in_process_fallback() {
+        let distribute_cache = distribute::generate(DistributeCacheConfig::Memory, Default::default()).await.unwrap();
+        let event_bus = generate(EventBusConfig::Memory, distribute_cache.clone()).await.unwrap();
+
+        // Nothing goes through the distribute cache.
+        let mut message_rx = distribute_cache.subscribe(&channel_name(ConfigChanged::NAME)).await.unwrap();
+        let mut subscription = event_bus.subscribe::<ConfigChanged>().await.unwrap();
+        event_bus.publish::<ConfigChanged>(&"log_level".to_string()).await.unwrap();
+
+        let message = tokio::time::timeout(Duration::from_secs(1), subscription.recv()).await.unwrap().unwrap();
+        assert_eq!(message.event, "log_level");
+        assert!(message_rx.try_recv().is_err());
+    }
+}
@@ -0,0 +1,648 @@
+/// Background jobs - Typed payloads queued on redis streams, run by the workers of a consumer group.
+/// Failed jobs are retried with an exponential backoff, then dead-lettered after the last attempt.
+/// Jobs not acknowledged within the visibility timeout are redelivered, e.g. when the worker crashed,
+/// so every job runs at least once and may run more than once.
+use crate::impls::distribute::redis::RedisDistributeCacheConfig;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::collections::HashMap;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Semaphore;
+use web_core::prelude::*;
+use web_core::utils::unix_millis;
+use web_guard::async_op::CancellationToken;
+
+pub mod memory;
+pub mod redis;
+
+pub type JobQueueGlobal = Arc<JobQueue>;
+
+const DEFAULT_WORKERS: usize = 4;
+const DEFAULT_MAX_ATTEMPTS: u32 = 5;
+const DEFAULT_RETRY_DELAY: Duration = Duration::from_secs(1);
+const DEFAULT_RETRY_MAX_DELAY: Duration = Duration::from_secs(300);
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(240);
+const DEFAULT_VISIBILITY_TIMEOUT: Duration = Duration::from_secs(300);
+const DEFAULT_POLL_INTERVAL: Duration = Duration::from_secs(1);
+/// Before fetching again, after the backend failed.
+const ERROR_DELAY: Duration = Duration::from_secs(1);
+
+static JOB_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+/// A typed job, its `NAME` is the queue it goes through.
+pub trait Job: Serialize + DeserializeOwned + Send + 'static {
+    /// Unique among the jobs.
+    const NAME: &'static str;
+}
+
+#[derive(Clone, Debug)]
+pub enum JobQueueBackendConfig {
+    Redis(RedisDistributeCacheConfig),
+    /// In-process, jobs are lost on restart.
+    /// For local development and tests without a Redis server.
+    Memory,
+}
+
+/// Backend, workers and retries of the jobs, read from the env:
+/// - `JOB_QUEUE_BACKEND`, `redis` (default) with the `REDIS_*` connection, or `memory`.
+/// - `JOB_WORKERS`, `4` by default, jobs run concurrently on each queue of each instance.
+/// - `JOB_MAX_ATTEMPTS`, `5` by default, the first run included.
+/// - `JOB_RETRY_DELAY`, `1s` by default, doubled on every failed attempt.
+/// - `JOB_RETRY_MAX_DELAY`, `5m` by default.
+/// - `JOB_TIMEOUT`, `4m` by default, a job running longer is failed.
+/// - `JOB_VISIBILITY_TIMEOUT`, `5m` by default, longer than `JOB_TIMEOUT`, an unacknowledged job is redelivered after it.
+/// - `JOB_POLL_INTERVAL`, `1s` by default, how long an idle worker waits for new or delayed jobs.
+#[derive(Clone, Debug)]
+pub struct JobQueueConfig {
+    pub backend: JobQueueBackendConfig,
+    pub workers: usize,
+    pub max_attempts: u32,
+    pub retry_delay: Duration,
+    pub retry_max_delay: Duration,
+    /// Strictly shorter than the `visibility_timeout`, so a job is failed before being redelivered.
+    pub timeout: Duration,
+    pub visibility_timeout: Duration,
+    pub poll_interval: Duration,
+}
+
+impl Default for JobQueueConfig {
+    /// In-process.
+    fn default() -> Self {
+        JobQueueConfig {
+            backend: JobQueueBackendConfig::Memory,
+            workers: DEFAULT_WORKERS,
+            max_attempts: DEFAULT_MAX_ATTEMPTS,
+            retry_delay: DEFAULT_RETRY_DELAY,
+            retry_max_delay: DEFAULT_RETRY_MAX_DELAY,
+            timeout: DEFAULT_TIMEOUT,
+            visibility_timeout: DEFAULT_VISIBILITY_TIMEOUT,
+            poll_interval: DEFAULT_POLL_INTERVAL,
+        }
+    }
+}
+
+#[inline]
+fn duration_var(key: &str) -> Result<Option<Duration>> {
+    Ok(web_env::var_parsed::<humantime::Duration>(key)?.map(Duration::from))
+}
+
+impl JobQueueConfig {
+    pub fn from_env() -> Result<Self> {
+        let backend = match web_env::var("JOB_QUEUE_BACKEND")?.as_deref() {
+            None | Some("redis") => JobQueueBackendConfig::Redis(RedisDistributeCacheConfig::from_env()?),
+            Some("memory") => JobQueueBackendConfig::Memory,
+            Some(backend) => anyhow::bail!("Unknown JOB_QUEUE_BACKEND `{backend}`."),
+        };
+
+        let workers = web_env::var_parsed::<usize>("JOB_WORKERS")?.unwrap_or(DEFAULT_WORKERS);
+        if workers == 0 {
+            anyhow::bail!("JOB_WORKERS must be greater than 0.");
+        }
+
+        let max_attempts = web_env::var_parsed::<u32>("JOB_MAX_ATTEMPTS")?.unwrap_or(DEFAULT_MAX_ATTEMPTS);
+        if max_attempts == 0 {
+            anyhow::bail!("JOB_MAX_ATTEMPTS must be greater than 0.");
+        }
+
+        let config = JobQueueConfig {
+            backend,
+            workers,
+            max_attempts,
+            retry_delay: duration_var("JOB_RETRY_DELAY")?.unwrap_or(DEFAULT_RETRY_DELAY),
+            retry_max_delay: duration_var("JOB_RETRY_MAX_DELAY")?.unwrap_or(DEFAULT_RETRY_MAX_DELAY),
+            timeout: duration_var("JOB_TIMEOUT")?.unwrap_or(DEFAULT_TIMEOUT),
+            visibility_timeout: duration_var("JOB_VISIBILITY_TIMEOUT")?.unwrap_or(DEFAULT_VISIBILITY_TIMEOUT),
+            poll_interval: duration_var("JOB_POLL_INTERVAL")?.unwrap_or(DEFAULT_POLL_INTERVAL),
+        };
+        config.validate()?;
+
+        Ok(config)
+    }
+
+    /// A job running past the `visibility_timeout` would be redelivered while still running.
+    fn validate(&self) -> Result<()> {
+        if self.timeout >= self.visibility_timeout {
+            anyhow::bail!(
+                "JOB_TIMEOUT ({:?}) must be shorter than JOB_VISIBILITY_TIMEOUT ({:?}).",
+                self.timeout,
+                self.visibility_timeout
+            );
+        }
+
+        Ok(())
+    }
+
+    /// Before the `attempt` following a failed one.
+    fn backoff(&self, attempt: u32) -> Duration {
+        self.retry_delay.saturating_mul(2u32.saturating_pow(attempt.saturating_sub(2))).min(self.retry_max_delay)
+    }
+}
+
+/// A job fetched by a worker, to be acknowledged once handled.
+#[derive(Clone, Debug)]
+pub struct JobDelivery {
+    /// Assigned by the backend, differs between attempts.
+    pub id: String,
+    pub payload: Vec<u8>,
+}
+
+/// The operations every job queue backend supports, `queue` is the name of the job.
+#[async_trait::async_trait]
+pub trait JobQueueBackend: Send + Sync {
+    /// Unique on each instance, names the consumer of the workers.
+    fn id(&self) -> &str;
+
+    /// Create the queue and its consumer group if missing.
+    async fn prepare(&self, queue: &str) -> Result<()>;
+
+    /// Deliverable after the `delay` if any.
+    async fn push(&self, queue: &str, payload: Vec<u8>, delay: Option<Duration>) -> Result<()>;
+
+    /// At most `count` jobs, including the due delayed ones
+    /// and the ones not acknowledged for the `visibility_timeout`.
+    /// Waits at most `block` when there's nothing to fetch.
+    async fn fetch(
+        &self,
+        queue: &str,
+        consumer: &str,
+        count: usize,
+        visibility_timeout: Duration,
+        block: Duration,
+    ) -> Result<Vec<JobDelivery>>;
+
+    /// Still being handled by the `consumer`, so not redelivered for another `visibility_timeout`.
+    async fn touch(&self, queue: &str, consumer: &str, id: &str) -> Result<()>;
+
+    /// Done with the delivery, whether it succeeded or not.
+    async fn ack(&self, queue: &str, id: &str) -> Result<()>;
+
+    async fn dead_letter(&self, queue: &str, payload: Vec<u8>) -> Result<()>;
+
+    /// At most `count`, the oldest first.
+    async fn dead_letters(&self, queue: &str, count: usize) -> Result<Vec<Vec<u8>>>;
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct JobEnvelope {
+    id: String,
+    /// Starts from `1`.
+    attempt: u32,
+    /// Unix time in milliseconds.
+    enqueued_at: u64,
+    /// Of the last failed attempt.
+    error: Option<String>,
+    payload: serde_json::Value,
+}
+
+/// A job failed on every attempt.
+#[derive(Debug)]
+pub struct JobDeadLetter<J> {
+    pub id: String,
+    pub attempts: u32,
+    pub error: Option<String>,
+    pub job: J,
+}
+
+pub struct JobQueue {
+    backend: Box<dyn JobQueueBackend>,
+    config: JobQueueConfig,
+}
+
+impl JobQueue {
+    /// Returns the id of the job.
+    #[inline]
+    pub async fn enqueue<J: Job>(&self, job: &J) -> Result<String> {
+        self.push::<J>(job, None).await
+    }
+
+    /// Runs after the `delay`, returns the id of the job.
+    #[inline]
+    pub async fn enqueue_in<J: Job>(&self, job: &J, delay: Duration) -> Result<String> {
+        self.push::<J>(job, Some(delay)).await
+    }
+
+    async fn push<J: Job>(&self, job: &J, delay: Option<Duration>) -> Result<String> {
+        let id = format!("{}-{}", self.backend.id(), JOB_COUNTER.fetch_add(1, Ordering::Relaxed));
+        let envelope = JobEnvelope {
+            id: id.clone(),
+            attempt: 1,
+            enqueued_at: unix_millis(),
+            error: None,
+            payload: serde_json::to_value(job)?,
+        };
+        self.backend.push(J::NAME, serde_json::to_vec(&envelope)?, delay).await?;
+
+        Ok(id)
+    }
+
+    /// At most `count`, the oldest first.
+    pub async fn dead_letters<J: Job>(&self, count: usize) -> Result<Vec<JobDeadLetter<J>>> {
+        self.backend
+            .dead_letters(J::NAME, count)
+            .await?
+            .iter()
+            .map(|payload| {
+                let envelope = serde_json::from_slice::<JobEnvelope>(payload)?;
+
+                Ok(JobDeadLetter {
+                    id: envelope.id,
+                    attempts: envelope.attempt,
+                    error: envelope.error,
+                    job: serde_json::from_value(envelope.payload)?,
+                })
+            })
+            .collect()
+    }
+
+    /// Start the workers of every queue the `handlers` registered.
+    pub fn start(self: &Arc<Self>, handlers: JobHandlers) -> JobWorkers {
+        let stopped = CancellationToken::new();
+        let tasks = handlers
+            .handlers
+            .into_iter()
+            .map(|(queue, handler)| {
+                debug!(queue, workers = self.config.workers, "Starting the job workers.");
+
+                tokio::spawn(run(Arc::clone(self), queue, Arc::new(handler), stopped.clone()))
+            })
+            .collect();
+
+        JobWorkers { stopped, tasks }
+    }
+
+    /// Run the job of the `delivery`, then retry, dead-letter or acknowledge it.
+    async fn handle(&self, queue: &str, handler: &JobHandler, delivery: JobDelivery) {
+        let mut envelope = match serde_json::from_slice::<JobEnvelope>(&delivery.payload) {
+            Ok(envelope) => envelope,
+            Err(error) => {
+                warn!(queue, %error, "Undecodable job dead-lettered.");
+                if let Err(error) = self.backend.dead_letter(queue, delivery.payload).await {
+                    warn!(queue, %error, "Failed to dead-letter the job.");
+                }
+                self.ack(queue, &delivery.id).await;
+                return;
+            }
+        };
+
+        let timeout = self.config.timeout;
+        let mut running = std::pin::pin!(tokio::time::timeout(timeout, handler(envelope.payload.clone())));
+
+        // Kept pending while running, so it's redelivered only once the worker is gone.
+        let period = self.config.visibility_timeout / 3;
+        let mut refresh = tokio::time::interval_at(tokio::time::Instant::now() + period, period);
+        let result = loop {
+            tokio::select! {
+                result = running.as_mut() => break match result {
+                    Ok(result) => result,
+                    Err(_) => Err(anyhow::anyhow!("Timed out after {timeout:?}.")),
+                },
+                _ = refresh.tick() => {
+                    if let Err(error) = self.backend.touch(queue, self.backend.id(), &delivery.id).await {
+                        warn!(queue, id = envelope.id, %error, "Failed to refresh the job.");
+                    }
+                }
+            }
+        };
+
+        match result {
+            Ok(()) => debug!(queue, id = envelope.id, attempt = envelope.attempt, "Job done."),
+            Err(error) if envelope.attempt < self.config.max_attempts => {
+                let delay = self.config.backoff(envelope.attempt + 1);
+                warn!(queue, id = envelope.id, attempt = envelope.attempt, ?delay, %error, "Job failed, retrying.");
+
+                envelope.attempt += 1;
+                envelope.error = Some(error.to_string());
+                let retried = match serde_json::to_vec(&envelope) {
+                    Ok(payload) => self.backend.push(queue, payload, Some(delay)).await,
+                    Err(error) => Err(error.into()),
+                };
+                if let Err(error) = retried {
+                    // Redelivered after the visibility timeout.
+                    warn!(queue, id = envelope.id, %error, "Failed to retry the job.");
+                    return;
+                }
+            }
+            Err(error) => {
+                warn!(queue, id = envelope.id, attempt = envelope.attempt, %error, "Job failed, dead-lettered.");
+
+                envelope.error = Some(error.to_string());
+                let dead_lettered = match serde_json::to_vec(&envelope) {
+                    Ok(payload) => self.backend.dead_letter(queue, payload).await,
+                    Err(error) => Err(error.into()),
+                };
+                if let Err(error) = dead_lettered {
+                    warn!(queue, id = envelope.id, %error, "Failed to dead-letter the job.");
+                    return;
+                }
+            }
+        }
+
+        self.ack(queue, &delivery.id).await;
+    }
+
+    async fn ack(&self, queue: &str, id: &str) {
+        if let Err(error) = self.backend.ack(queue, id).await {
+            // Redelivered after the visibility timeout.
+            warn!(queue, id, %error, "Failed to acknowledge the job.");
+        }
+    }
+}
+
+/// Fetch the jobs of `queue` while any worker is idle, until being stopped.
+async fn run(job_queue: JobQueueGlobal, queue: &'static str, handler: Arc<JobHandler>, stopped: CancellationToken) {
+    let workers = job_queue.config.workers;
+    let consumer = job_queue.backend.id().to_string();
+    let idle = Arc::new(Semaphore::new(workers));
+    let mut prepared = false;
+
+    while !stopped.is_cancelled() {
+        if !prepared {
+            match job_queue.backend.prepare(queue).await {
+                Ok(()) => prepared = true,
+                Err(error) => {
+                    warn!(queue, %error, "Failed to prepare the job queue.");
+                    tokio::select! {
+                        _ = stopped.cancelled() => break,
+                        _ = tokio::time::sleep(ERROR_DELAY) => continue,
+                    }
+                }
+            }
+        }
+
+        let first = tokio::select! {
+            _ = stopped.cancelled() => break,
+            // UNWRAP: The semaphore is never closed.
+            permit = Arc::clone(&idle).acquire_owned() => permit.unwrap(),
+        };
+        let count = 1 + idle.available_permits();
+
+        // A job fetched but dropped here is redelivered after the visibility timeout.
+        let fetched = tokio::select! {
+            _ = stopped.cancelled() => break,
+            fetched = job_queue.backend.fetch(
+                queue,
+                &consumer,
+                count,
+                job_queue.config.visibility_timeout,
+                job_queue.config.poll_interval,
+            ) => fetched,
+        };
+        let deliveries = match fetched {
+            Ok(deliveries) => deliveries,
+            Err(error) => {
+                warn!(queue, %error, "Failed to fetch the jobs.");
+                prepared = false;
+                drop(first);
+                tokio::select! {
+                    _ = stopped.cancelled() => break,
+                    _ = tokio::time::sleep(ERROR_DELAY) => continue,
+                }
+            }
+        };
+
+        let mut first = Some(first);
+        for delivery in deliveries {
+            // Only this loop acquires, at most `count` permits are taken.
+            let Some(permit) = first.take().or_else(|| Arc::clone(&idle).try_acquire_owned().ok()) else {
+                break;
+            };
+
+            let job_queue = Arc::clone(&job_queue);
+            let handler = Arc::clone(&handler);
+            tokio::spawn(async move {
+                job_queue.handle(queue, &handler, delivery).await;
+                drop(permit);
+            });
+        }
+    }
+
+    // Wait for the running jobs.
+    let _ = idle.acquire_many(workers as u32).await;
+}
+
+pub type JobHandler = Box<dyn Fn(serde_json::Value) -> Pin<Box<dyn Future<Output = Result<()>> + Send>> + Send + Sync>;
+
+/// Handlers of the jobs, one for each queue.
+#[derive(Default)]
+pub struct JobHandlers {
+    handlers: HashMap<&'static str, JobHandler>,
+}
+
+impl JobHandlers {
+    pub fn register<J, F, Fut>(mut self, handler: F) -> Self
+    where
+        J: Job,
+        F: Fn(J) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<()>> + Send + 'static,
+    {
+        self.handlers.insert(
+            J::NAME,
+            Box::new(move |payload| match serde_json::from_value::<J>(payload) {
+                Ok(job) => Box::pin(handler(job)),
+                Err(error) => Box::pin(async move { Err(error.into()) }),
+            }),
+        );
+        self
+    }
+}
+
+/// The running workers, stopped by `shutdown`.
+pub struct JobWorkers {
+    stopped: CancellationToken,
+    tasks: Vec<tokio::task::JoinHandle<()>>,
+}
+
+impl JobWorkers {
+    /// No more jobs are fetched, the running ones are waited for.
+    pub async fn shutdown(self) {
+        self.stopped.cancel();
+
+        for task in self.tasks {
+            let _ = task.await;
+        }
+    }
+}
+
+pub async fn generate(config: JobQueueConfig) -> Result<JobQueueGlobal> {
+    config.validate()?;
+
+    let backend: Box<dyn JobQueueBackend> = match &config.backend {
+        JobQueueBackendConfig::Redis(redis_config) => Box::new(redis::generate(redis_config.clone()).await?),
+        JobQueueBackendConfig::Memory => Box::new(memory::InMemoryJobQueue::default()),
+    };
+
+    Ok(Arc::new(JobQueue { backend, config }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{generate, Job, JobHandlers, JobQueueConfig};
+    use serde::{Deserialize, Serialize};
+    use std::sync::atomic::{AtomicU32, Ordering};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    #[derive(Serialize, Deserialize, PartialEq, Debug)]
+    struct SendEmail {
+        to: String,
+    }
+
+    impl Job for SendEmail {
+        const NAME: &'static str = "tests:send_email";
+    }
+
+    fn config() -> JobQueueConfig {
+        JobQueueConfig {
+            max_attempts: 3,
+            retry_delay: Duration::from_millis(10),
+            poll_interval: Duration::from_millis(10),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn backoff() {
+        let config = JobQueueConfig {
+            retry_delay: Duration::from_secs(1),
+            retry_max_delay: Duration::from_secs(5),
+            ..Default::default()
+        };
+
+        assert_eq!(config.backoff(2), Duration::from_secs(1));
+        assert_eq!(config.backoff(3), Duration::from_secs(2));
+        assert_eq!(config.backoff(4), Duration::from_secs(4));
+        assert_eq!(config.backoff(5), Duration::from_secs(5));
+        assert_eq!(config.backoff(100), Duration::from_secs(5));
+    }
+
+    #[ntex::test]
+    async fn run_jobs() {
+        let job_queue = generate(config()).await.unwrap();
+        let sent = Arc::new(AtomicU32::new(0));
+
+        let counter = Arc::clone(&sent);
+        let workers = job_queue.start(JobHandlers::default().register(move |job: SendEmail| {
+            let counter = Arc::clone(&counter);
+            async move {
+                assert_eq!(job.to, "a@example.com");
+                counter.fetch_add(1, Ordering::Relaxed);
+                Ok(())
+            }
+        }));
+
+        for _ in 0..10 {
+            job_queue.enqueue(&SendEmail { to: "a@example.com".into() }).await.unwrap();
+        }
+        job_queue.enqueue_in(&SendEmail { to: "a@example.com".into() }, Duration::from_millis(50)).await.unwrap();
+
+        tokio::time::sleep(Duration::from_millis(300)).await;
+        workers.shutdown().await;
+        assert_eq!(sent.load(Ordering::Relaxed), 11);
+    }
+
+    #[ntex::test]
+    async fn shutdown_while_busy() {
+        let job_queue = generate(JobQueueConfig { workers: 1, ..config() }).await.unwrap();
+        let sent = Arc::new(AtomicU32::new(0));
+        let started = Arc::new(tokio::sync::Notify::new());
+
+        let (counter, notify) = (Arc::clone(&sent), Arc::clone(&started));
+        let workers = job_queue.start(JobHandlers::default().register(move |_: SendEmail| {
+            counter.fetch_add(1, Ordering::Relaxed);
+            notify.notify_one();
+            async move {
+                tokio::time::sleep(Duration::from_millis(100)).await;
+                Ok(())
+            }
+        }));
+
+        for _ in 0..2 {
+            job_queue.enqueue(&SendEmail { to: "e@example.com".into() }).await.unwrap();
+        }
+
+        // Stopped while waiting for the busy worker, the other job is left queued.
+        started.notified().await;
+        workers.shutdown().await;
+        assert_eq!(sent.load(Ordering::Relaxed), 1);
+
+        let block = Duration::from_millis(10);
+        let fetched = job_queue.backend.fetch(SendEmail::NAME, "alive", 2, Duration::from_secs(1), block).await;
+        assert_eq!(fetched.unwrap().len(), 1);
+    }
+
+    #[ntex::test]
+    async fn retry_then_dead_letter() {
+        let job_queue = generate(config()).await.unwrap();
+        let attempts = Arc::new(AtomicU32::new(0));
+
+        let counter = Arc::clone(&attempts);
+        let workers = job_queue.start(JobHandlers::default().register(move |_: SendEmail| {
+            let attempt = counter.fetch_add(1, Ordering::Relaxed) + 1;
+            async move { anyhow::bail!("Attempt {attempt} failed.") }
+        }));
+
+        let id = job_queue.enqueue(&SendEmail { to: "b@example.com".into() }).await.unwrap();
+
+        tokio::time::sleep(Duration::from_millis(300)).await;
+        workers.shutdown().await;
+        assert_eq!(attempts.load(Ordering::Relaxed), 3);
+
+        let dead_letters = job_queue.dead_letters::<SendEmail>(10).await.unwrap();
+        assert_eq!(dead_letters.len(), 1);
+        assert_eq!(dead_letters[0].id, id);
+        assert_eq!(dead_letters[0].attempts, 3);
+        assert_eq!(dead_letters[0].error.as_deref(), Some("Attempt 3 failed."));
+        assert_eq!(dead_letters[0].job, SendEmail { to: "b@example.com".into() });
+    }
+
+    #[ntex::test]
+    async fn redeliver_after_visibility_timeout() {
+        let job_queue = generate(config()).await.unwrap();
+        job_queue.enqueue(&SendEmail { to: "c@example.com".into() }).await.unwrap();
+
+        // Fetched by a crashed worker, never acknowledged.
+        let backend = &job_queue.backend;
+        let timeout = Duration::from_millis(50);
+        let block = Duration::from_millis(10);
+        assert_eq!(backend.fetch(SendEmail::NAME, "crashed", 1, timeout, block).await.unwrap().len(), 1);
+        assert!(backend.fetch(SendEmail::NAME, "alive", 1, timeout, block).await.unwrap().is_empty());
+
+        tokio::time::sleep(timeout).await;
+        assert_eq!(backend.fetch(SendEmail::NAME, "alive", 1, timeout, block).await.unwrap().len(), 1);
+    }
+
+    #[ntex::test]
+    async fn touch_keeps_job_pending() {
+        let job_queue = generate(config()).await.unwrap();
+        job_queue.enqueue(&SendEmail { to: "d@example.com".into() }).await.unwrap();
+
+        let backend = &job_queue.backend;
+        let timeout = Duration::from_millis(500);
+        let block = Duration::from_millis(10);
+        let delivery = backend.fetch(SendEmail::NAME, "running", 1, timeout, block).await.unwrap().remove(0);
+
+        // Refreshed while running, not redelivered past the visibility timeout.
+        tokio::time::sleep(timeout / 2).await;
+        backend.touch(SendEmail::NAME, "running", &delivery.id).await.unwrap();
+        tokio::time::sleep(timeout / 2 + block).await;
+        assert!(backend.fetch(SendEmail::NAME, "alive", 1, timeout, block).await.unwrap().is_empty());
+
+        tokio::time::sleep(timeout).await;
+        assert_eq!(backend.fetch(SendEmail::NAME, "alive", 1, timeout, block).await.unwrap().len(), 1);
+    }
+
+    #[ntex::test]
+    async fn reject_timeout_not_shorter_than_visibility_timeout() {
+        let visibility_timeout = Duration::from_secs(60);
+
+        assert!(generate(JobQueueConfig { timeout: visibility_timeout, visibility_timeout, ..config() })
+            .await
+            .is_err());
+        assert!(generate(JobQueueConfig { timeout: visibility_timeout / 2, visibility_timeout, ..config() })
+            .await
+            .is_ok());
+    }
+}
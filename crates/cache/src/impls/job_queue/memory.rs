@@ -0,0 +1,155 @@
+/// In-process backend of the job queue.
+/// Behaves like the redis one, but nothing is shared with other instances.
+use crate::impls::job_queue::{JobDelivery, JobQueueBackend};
+use std::collections::{HashMap, VecDeque};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tokio::sync::Notify;
+use web_core::prelude::*;
+
+static INSTANCE_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+#[derive(Default)]
+struct Queue {
+    ready: VecDeque<Vec<u8>>,
+    delayed: Vec<(Instant, Vec<u8>)>,
+    /// Delivered but not acknowledged, by the delivery id.
+    pending: HashMap<String, (Instant, Vec<u8>)>,
+    dead: Vec<Vec<u8>>,
+    deliveries: u64,
+}
+
+impl Queue {
+    fn take(&mut self, count: usize, visibility_timeout: Duration) -> Vec<JobDelivery> {
+        let now = Instant::now();
+
+        let (due, delayed) = std::mem::take(&mut self.delayed).into_iter().partition(|(at, _)| *at <= now);
+        self.delayed = delayed;
+        self.ready.extend(due.into_iter().map(|(_, payload)| payload));
+
+        // Redelivered under a new id, like the ones claimed from redis.
+        let expired = self
+            .pending
+            .iter()
+            .filter(|(_, (delivered_at, _))| now.saturating_duration_since(*delivered_at) >= visibility_timeout)
+            .map(|(id, _)| id.clone())
+            .collect::<Vec<_>>();
+        for id in expired {
+            if let Some((_, payload)) = self.pending.remove(&id) {
+                self.ready.push_front(payload);
+            }
+        }
+
+        let mut deliveries = vec![];
+        while deliveries.len() < count {
+            let Some(payload) = self.ready.pop_front() else {
+                break;
+            };
+
+            self.deliveries += 1;
+            let id = self.deliveries.to_string();
+            self.pending.insert(id.clone(), (now, payload.clone()));
+            deliveries.push(JobDelivery { id, payload });
+        }
+
+        deliveries
+    }
+}
+
+pub struct InMemoryJobQueue {
+    id: String,
+    queues: Mutex<HashMap<String, Queue>>,
+    pushed: Notify,
+}
+
+impl Default for InMemoryJobQueue {
+    fn default() -> Self {
+        InMemoryJobQueue {
+            id: format!("in-memory-{}", INSTANCE_COUNTER.fetch_add(1, Ordering::Relaxed)),
+            queues: Default::default(),
+            pushed: Notify::new(),
+        }
+    }
+}
+
+impl InMemoryJobQueue {
+    fn with_queue<R>(&self, queue: &str, f: impl FnOnce(&mut Queue) -> R) -> R {
+        // UNWRAP: Nothing panics while holding the lock.
+        let mut queues = self.queues.lock().unwrap();
+
+        f(queues.entry(queue.to_string()).or_default())
+    }
+}
+
+#[async_trait::async_trait]
+impl JobQueueBackend for InMemoryJobQueue {
+    #[inline]
+    fn id(&self) -> &str {
+        &self.id
+    }
+
+    async fn prepare(&self, queue: &str) -> Result<()> {
+        self.with_queue(queue, |_| ());
+
+        Ok(())
+    }
+
+    async fn push(&self, queue: &str, payload: Vec<u8>, delay: Option<Duration>) -> Result<()> {
+        self.with_queue(queue, |queue| match delay {
+            Some(delay) => queue.delayed.push((Instant::now() + delay, payload)),
+            None => queue.ready.push_back(payload),
+        });
+        self.pushed.notify_waiters();
+
+        Ok(())
+    }
+
+    async fn fetch(
+        &self,
+        queue: &str,
+        _consumer: &str,
+        count: usize,
+        visibility_timeout: Duration,
+        block: Duration,
+    ) -> Result<Vec<JobDelivery>> {
+        // Registered before taking, so no push in between is missed.
+        let mut pushed = std::pin::pin!(self.pushed.notified());
+        pushed.as_mut().enable();
+
+        let deliveries = self.with_queue(queue, |queue| queue.take(count, visibility_timeout));
+        if !deliveries.is_empty() {
+            return Ok(deliveries);
+        }
+
+        let _ = tokio::time::timeout(block, pushed).await;
+
+        Ok(self.with_queue(queue, |queue| queue.take(count, visibility_timeout)))
+    }
+
+    async fn ack(&self, queue: &str, id: &str) -> Result<()> {
+        self.with_queue(queue, |queue| queue.pending.remove(id));
+
+        Ok(())
+    }
+
+    async fn touch(&self, queue: &str, _consumer: &str, id: &str) -> Result<()> {
+        self.with_queue(queue, |queue| {
+            if let Some((delivered_at, _)) = queue.pending.get_mut(id) {
+                *delivered_at = Instant::now();
+            }
+        });
+
+        Ok(())
+    }
+
+    async fn dead_letter(&self, queue: &str, payload: Vec<u8>) -> Result<()> {
+        self.with_queue(queue, |queue| queue.dead.push(payload));
+
+        Ok(())
+    }
+
+    async fn dead_letters(&self, queue: &str, count: usize) -> Result<Vec<Vec<u8>>> {
+        Ok(self.with_queue(queue, |queue| queue.dead.iter().take(count).cloned().collect()))
+    }
+}
@@ -0,0 +1,253 @@
+/// Redis backend of the job queue.
+/// Each queue is a stream read by the `workers` consumer group, each instance is a consumer.
+/// Delayed jobs wait in a sorted set, scored by when they are due.
+use crate::impls::distribute::redis::{connect, RedisDistributeCacheConfig};
+use crate::impls::job_queue::{JobDelivery, JobQueueBackend};
+use fred::prelude::*;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::OnceCell;
+use web_core::prelude::*;
+use web_core::utils::unix_millis;
+
+const JOB_GROUP: &str = "workers";
+const PAYLOAD_FIELD: &str = "payload";
+/// The older dead letters are trimmed.
+const DEAD_LETTER_CAPACITY: i64 = 10_000;
+
+/// KEYS: the delayed jobs and the stream. ARGV: now, the count and the payload field.
+/// Each due job is removed and added in one go, so it's neither lost nor moved twice.
+const PROMOTE: &str = r#"
+local due = redis.call('ZRANGEBYSCORE', KEYS[1], '-inf', ARGV[1], 'LIMIT', 0, ARGV[2])
+for _, payload in ipairs(due) do
+    redis.call('ZREM', KEYS[1], payload)
+    redis.call('XADD', KEYS[2], '*', ARGV[3], payload)
+end
+return #due
+"#;
+
+/// The hash tag keeps the keys of a queue on the same cluster slot.
+#[inline]
+fn stream_key(queue: &str) -> String {
+    format!("web_job:{{{queue}}}")
+}
+
+#[inline]
+fn delayed_key(queue: &str) -> String {
+    format!("web_job:{{{queue}}}:delayed")
+}
+
+#[inline]
+fn dead_letter_key(queue: &str) -> String {
+    format!("web_job:{{{queue}}}:dead")
+}
+
+#[inline]
+fn payload(fields: &HashMap<String, RedisValue>) -> Option<Vec<u8>> {
+    fields.get(PAYLOAD_FIELD).and_then(|value| value.as_bytes()).map(<[u8]>::to_vec)
+}
+
+pub struct RedisJobQueue {
+    id: String,
+    pool: RedisPool,
+    /// `XREADGROUP` blocks the connection, so each queue reads on its own one.
+    readers: Mutex<HashMap<String, Arc<OnceCell<RedisClient>>>>,
+}
+
+impl RedisJobQueue {
+    async fn reader(&self, queue: &str) -> RedisClient {
+        // UNWRAP: Nothing panics while holding the lock.
+        let reader = Arc::clone(self.readers.lock().unwrap().entry(queue.to_string()).or_default());
+
+        reader
+            .get_or_init(|| async {
+                let client = self.pool.next().clone_new();
+
+                debug!(queue, "Connecting to the redis job reader.");
+
+                // No need to wait for being connected.
+                #[allow(clippy::let_underscore_future)]
+                let _ = client.connect();
+                let _ = client.wait_for_connect().await;
+
+                client
+            })
+            .await
+            .clone()
+    }
+
+    /// Move the due delayed jobs into the stream.
+    async fn promote(&self, queue: &str, count: usize) -> Result<()> {
+        self.pool
+            .eval::<(), _, _, _>(
+                PROMOTE,
+                vec![delayed_key(queue), stream_key(queue)],
+                vec![unix_millis().to_string(), count.to_string(), PAYLOAD_FIELD.to_string()],
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    /// Take over the jobs idle for the `visibility_timeout`.
+    async fn claim(
+        &self,
+        queue: &str,
+        consumer: &str,
+        count: usize,
+        visibility_timeout: Duration,
+    ) -> Result<Vec<JobDelivery>> {
+        let response = self
+            .pool
+            .xautoclaim::<RedisValue, _, _, _, _>(
+                stream_key(queue),
+                JOB_GROUP,
+                consumer,
+                visibility_timeout.as_millis() as u64,
+                "0-0",
+                Some(count as u64),
+                false,
+            )
+            .await?;
+
+        // `[next, entries]`, and the deleted ids since redis 7.
+        let entries = match response {
+            RedisValue::Array(mut parts) if parts.len() >= 2 => parts.swap_remove(1),
+            _ => return Ok(vec![]),
+        };
+
+        let mut deliveries = vec![];
+        for entry in entries.into_array() {
+            // The deleted entries are `nil` before redis 7.
+            let Ok((id, fields)) = entry.convert::<(String, HashMap<String, RedisValue>)>() else {
+                continue;
+            };
+
+            match payload(&fields) {
+                Some(payload) => deliveries.push(JobDelivery { id, payload }),
+                None => warn!(queue, id, "Job without payload skipped."),
+            }
+        }
+
+        Ok(deliveries)
+    }
+}
+
+#[async_trait::async_trait]
+impl JobQueueBackend for RedisJobQueue {
+    #[inline]
+    fn id(&self) -> &str {
+        &self.id
+    }
+
+    async fn prepare(&self, queue: &str) -> Result<()> {
+        match self.pool.xgroup_create::<(), _, _, _>(stream_key(queue), JOB_GROUP, "0", true).await {
+            Ok(()) => Ok(()),
+            Err(error) if error.details().starts_with("BUSYGROUP") => Ok(()),
+            Err(error) => Err(error.into()),
+        }
+    }
+
+    async fn push(&self, queue: &str, payload: Vec<u8>, delay: Option<Duration>) -> Result<()> {
+        match delay {
+            Some(delay) => {
+                let due = (unix_millis() + delay.as_millis() as u64) as f64;
+                self.pool.zadd::<(), _, _>(delayed_key(queue), None, None, false, false, (due, payload)).await?;
+            }
+            None => {
+                self.pool
+                    .xadd::<(), _, _, _, _>(stream_key(queue), false, None::<()>, "*", vec![(PAYLOAD_FIELD, payload)])
+                    .await?;
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn fetch(
+        &self,
+        queue: &str,
+        consumer: &str,
+        count: usize,
+        visibility_timeout: Duration,
+        block: Duration,
+    ) -> Result<Vec<JobDelivery>> {
+        self.promote(queue, count).await?;
+
+        let deliveries = self.claim(queue, consumer, count, visibility_timeout).await?;
+        if !deliveries.is_empty() {
+            return Ok(deliveries);
+        }
+
+        let response = self
+            .reader(queue)
+            .await
+            .xreadgroup_map::<String, String, String, RedisValue, _, _, _, _>(
+                JOB_GROUP,
+                consumer,
+                Some(count as u64),
+                Some(block.as_millis() as u64),
+                false,
+                stream_key(queue),
+                ">",
+            )
+            .await?;
+
+        let mut deliveries = vec![];
+        for (id, fields) in response.into_values().flatten() {
+            match payload(&fields) {
+                Some(payload) => deliveries.push(JobDelivery { id, payload }),
+                None => warn!(queue, id, "Job without payload skipped."),
+            }
+        }
+
+        Ok(deliveries)
+    }
+
+    async fn ack(&self, queue: &str, id: &str) -> Result<()> {
+        let stream = stream_key(queue);
+        self.pool.xack::<(), _, _, _>(&stream, JOB_GROUP, id).await?;
+        self.pool.xdel::<(), _, _>(&stream, id).await?;
+
+        Ok(())
+    }
+
+    async fn touch(&self, queue: &str, consumer: &str, id: &str) -> Result<()> {
+        // Claimed again by the same consumer, which resets how long it has been idle.
+        self.pool
+            .xclaim::<(), _, _, _, _>(stream_key(queue), JOB_GROUP, consumer, 0, id, None, None, None, false, true)
+            .await?;
+
+        Ok(())
+    }
+
+    async fn dead_letter(&self, queue: &str, payload: Vec<u8>) -> Result<()> {
+        self.pool
+            .xadd::<(), _, _, _, _>(
+                dead_letter_key(queue),
+                false,
+                ("MAXLEN", "~", DEAD_LETTER_CAPACITY),
+                "*",
+                vec![(PAYLOAD_FIELD, payload)],
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    async fn dead_letters(&self, queue: &str, count: usize) -> Result<Vec<Vec<u8>>> {
+        let entries = self
+            .pool
+            .xrange_values::<String, String, RedisValue, _, _, _>(dead_letter_key(queue), "-", "+", Some(count as u64))
+            .await?;
+
+        Ok(entries.iter().filter_map(|(_, fields)| payload(fields)).collect())
+    }
+}
+
+pub async fn generate(config: RedisDistributeCacheConfig) -> Result<RedisJobQueue> {
+    let pool = connect(config).await?;
+
+    Ok(RedisJobQueue { id: pool.next().id().to_string(), pool, readers: Default::default() })
+}
@@ -0,0 +1,56 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::{Mutex as AsyncMutex, OwnedMutexGuard};
+
+/// Ms. How long a loader can hold the cross-instance lock.
+pub const LOADING_LOCK_TTL: usize = 10_000;
+
+/// How long a loader waits for the cross-instance lock, then it loads without it.
+pub const LOADING_LOCK_MAX_WAIT: Duration = Duration::from_millis(LOADING_LOCK_TTL as u64);
+
+#[inline]
+pub(crate) fn loading_lock_resource(key: &str) -> String {
+    format!("web_cache:loading:{key}")
+}
+
+type KeyedLocksMap = HashMap<String, Arc<AsyncMutex<()>>>;
+
+/// In-process locks, one per loading key.
+/// Late arrivals wait for the winner and then read what it has stored.
+#[derive(Default)]
+pub(crate) struct KeyedLocks {
+    locks: Arc<Mutex<KeyedLocksMap>>,
+}
+
+impl KeyedLocks {
+    pub(crate) async fn lock(&self, key: &str) -> KeyedLockGuard {
+        let lock = {
+            // UNWRAP: Nothing panics while holding the lock.
+            let mut locks = self.locks.lock().unwrap();
+            Arc::clone(locks.entry(key.to_string()).or_default())
+        };
+
+        KeyedLockGuard { key: key.to_string(), locks: Arc::clone(&self.locks), guard: Some(lock.lock_owned().await) }
+    }
+}
+
+pub(crate) struct KeyedLockGuard {
+    key: String,
+    locks: Arc<Mutex<KeyedLocksMap>>,
+    guard: Option<OwnedMutexGuard<()>>,
+}
+
+impl Drop for KeyedLockGuard {
+    fn drop(&mut self) {
+        // UNWRAP: Nothing panics while holding the lock.
+        let mut locks = self.locks.lock().unwrap();
+
+        // Release first, then the entry can be removed if nobody else is waiting.
+        // Waiters clone the `Arc` under the map lock, so the count is reliable here.
+        drop(self.guard.take());
+        if locks.get(&self.key).is_some_and(|lock| Arc::strong_count(lock) == 1) {
+            locks.remove(&self.key);
+        }
+    }
+}
@@ -1,69 +1,553 @@
-use crate::error::ExtensionError;
+use crate::error::{ExtensionError, SharedLoadError};
+use crate::impls::memory::registry::MemoryCacheRegistryGlobal;
+use crate::impls::stats::{CacheStats, CacheStatsSnapshot};
 use moka::future::Cache;
+use moka::notification::RemovalCause;
+use moka::Expiry;
 use ntex::http::{Payload, RequestHead};
 use ntex::util::Extensions;
 use ntex::web::{FromRequest, HttpRequest, WebRequest};
-use std::ops::{Deref, DerefMut};
-use std::sync::Arc;
-use std::time::Duration;
-use tokio::sync::RwLock;
+use serde::Serialize;
+use std::borrow::Borrow;
+use std::fmt::Display;
+use std::future::Future;
+use std::hash::Hash;
+use std::marker::PhantomData;
+use std::ops::Deref;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, RwLock as SyncRwLock};
+use std::time::{Duration, Instant};
+use web_core::prelude::*;
+
+pub mod registry;
+pub mod snapshot;
 
 pub mod prelude {
     pub use crate::impls::memory::MemoryCacheExt;
 }
 
-pub type MemoryCacheKey = &'static str;
+pub const MEMORY_CACHE_DEFAULT_NAMESPACE: &str = "default";
+
+pub type MemoryCacheKey = Arc<str>;
 pub type MemoryCacheValue = serde_json::Value;
-pub type MemoryCacheGlobal = Arc<RwLock<MemoryCache>>;
+/// Moka is concurrent already, no need to be locked.
+pub type MemoryCacheGlobal<K = MemoryCacheKey, V = MemoryCacheValue> = Arc<MemoryCache<K, V>>;
+
+/// Bounds required by `moka` and the weigher for the cache keys.
+/// Keys are displayed when being looked up by the admin.
+pub trait MemoryCacheKeyBound: Hash + Eq + Display + Send + Sync + MemoryCacheWeigh + 'static {}
+
+impl<T: Hash + Eq + Display + Send + Sync + MemoryCacheWeigh + 'static> MemoryCacheKeyBound for T {}
+
+/// Bounds required by `moka` and the weigher for the cache values.
+pub trait MemoryCacheValueBound: Clone + Send + Sync + MemoryCacheWeigh + 'static {}
+
+impl<T: Clone + Send + Sync + MemoryCacheWeigh + 'static> MemoryCacheValueBound for T {}
+
+/// Estimated size in bytes, including what's on the heap.
+/// The capacity of the memory cache is counted with it.
+pub trait MemoryCacheWeigh {
+    fn weigh(&self) -> usize;
+}
+
+macro_rules! impl_weigh_sized {
+    ($($ty: ty),+) => {
+        $(
+            impl MemoryCacheWeigh for $ty {
+                #[inline]
+                fn weigh(&self) -> usize {
+                    std::mem::size_of::<$ty>()
+                }
+            }
+        )+
+    };
+}
+
+impl_weigh_sized!(bool, char, u8, u16, u32, u64, u128, usize, i8, i16, i32, i64, i128, isize, f32, f64, ());
+
+impl MemoryCacheWeigh for str {
+    #[inline]
+    fn weigh(&self) -> usize {
+        self.len()
+    }
+}
+
+impl MemoryCacheWeigh for String {
+    #[inline]
+    fn weigh(&self) -> usize {
+        std::mem::size_of::<String>() + self.capacity()
+    }
+}
+
+impl<T: MemoryCacheWeigh + ?Sized> MemoryCacheWeigh for Arc<T> {
+    #[inline]
+    fn weigh(&self) -> usize {
+        std::mem::size_of::<Arc<T>>() + T::weigh(self)
+    }
+}
+
+impl<T: MemoryCacheWeigh + ?Sized> MemoryCacheWeigh for Box<T> {
+    #[inline]
+    fn weigh(&self) -> usize {
+        std::mem::size_of::<Box<T>>() + T::weigh(self)
+    }
+}
+
+impl<T: MemoryCacheWeigh> MemoryCacheWeigh for Vec<T> {
+    #[inline]
+    fn weigh(&self) -> usize {
+        std::mem::size_of::<Vec<T>>()
+            + (self.capacity() - self.len()) * std::mem::size_of::<T>()
+            + self.iter().map(MemoryCacheWeigh::weigh).sum::<usize>()
+    }
+}
+
+impl<T: MemoryCacheWeigh> MemoryCacheWeigh for Option<T> {
+    #[inline]
+    fn weigh(&self) -> usize {
+        std::mem::size_of::<Option<T>>()
+            + self.as_ref().map_or(0, |value| value.weigh().saturating_sub(std::mem::size_of::<T>()))
+    }
+}
+
+impl<A: MemoryCacheWeigh, B: MemoryCacheWeigh> MemoryCacheWeigh for (A, B) {
+    #[inline]
+    fn weigh(&self) -> usize {
+        self.0.weigh() + self.1.weigh()
+    }
+}
+
+impl MemoryCacheWeigh for serde_json::Value {
+    fn weigh(&self) -> usize {
+        use serde_json::Value;
+
+        std::mem::size_of::<Value>()
+            + match self {
+                Value::Null | Value::Bool(_) | Value::Number(_) => 0,
+                Value::String(content) => content.capacity(),
+                Value::Array(values) => {
+                    values.capacity() * std::mem::size_of::<Value>()
+                        + values
+                            .iter()
+                            .map(|value| value.weigh().saturating_sub(std::mem::size_of::<Value>()))
+                            .sum::<usize>()
+                }
+                Value::Object(map) => map.iter().map(|(key, value)| key.weigh() + value.weigh()).sum::<usize>(),
+            }
+    }
+}
+
+/// Limits of a memory cache, the default ones of the registry are read from the env:
+/// - `MEMORY_CACHE_TTL`, e.g. `30m`, `0` to disable.
+/// - `MEMORY_CACHE_TTI`, e.g. `5m`, `0` to disable.
+/// - `MEMORY_CACHE_MAX_BYTES`, e.g. `64MiB`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MemoryCachePolicy {
+    pub ttl: Option<Duration>,
+    pub tti: Option<Duration>,
+    pub max_bytes: u64,
+}
+
+impl Default for MemoryCachePolicy {
+    fn default() -> Self {
+        MemoryCachePolicy {
+            // Time to live (TTL): 30 minutes
+            ttl: Some(Duration::from_secs(30 * 60)),
+            // Time to idle (TTI):  5 minutes
+            tti: Some(Duration::from_secs(5 * 60)),
+            // This cache will hold up to 32MiB of values.
+            max_bytes: 32 * 1024 * 1024,
+        }
+    }
+}
+
+impl MemoryCachePolicy {
+    pub fn from_env() -> Result<Self> {
+        let default = MemoryCachePolicy::default();
+
+        Ok(MemoryCachePolicy {
+            ttl: duration_var("MEMORY_CACHE_TTL")?.unwrap_or(default.ttl),
+            tti: duration_var("MEMORY_CACHE_TTI")?.unwrap_or(default.tti),
+            max_bytes: match web_env::var("MEMORY_CACHE_MAX_BYTES")? {
+                Some(content) => content
+                    .parse::<bytesize::ByteSize>()
+                    .map_err(|error| {
+                        anyhow::anyhow!("Failed to parse MEMORY_CACHE_MAX_BYTES environment variable: {error}")
+                    })?
+                    .as_u64(),
+                None => default.max_bytes,
+            },
+        })
+    }
+}
+
+/// `0` means disabled.
+fn duration_var(key: &str) -> Result<Option<Option<Duration>>> {
+    Ok(web_env::var_parsed::<humantime::Duration>(key)?
+        .map(Duration::from)
+        .map(|duration| (!duration.is_zero()).then_some(duration)))
+}
+
+pub type MemoryCacheEvictionHook<K, V> = Box<dyn Fn(&K, &V, RemovalCause) + Send + Sync>;
+
+/// Type-erased view of a namespace, for the admin.
+#[async_trait::async_trait]
+pub trait MemoryCacheNamespace: Send + Sync {
+    async fn summary(&self) -> MemoryCacheSummary;
+
+    /// Keys starting with `prefix`, at most `limit` ones.
+    async fn keys(&self, prefix: &str, limit: usize) -> Vec<String>;
+
+    /// Whether the key existed.
+    async fn invalidate_key(&self, key: &str) -> bool;
+
+    async fn invalidate_all(&self);
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct MemoryCacheSummary {
+    pub entry_count: u64,
+    /// Estimated bytes in use.
+    pub weighted_size: u64,
+    pub stats: CacheStatsSnapshot,
+}
+
+/// The value with its own ttl, which overrides the one of the policy.
+struct MemoryCacheEntry<V> {
+    value: V,
+    ttl: Option<Duration>,
+    /// Invalidated together by `invalidate_tag`.
+    tags: Option<Arc<[Arc<str>]>>,
+    /// The remaining ttl is counted from it by the snapshots.
+    created_at: Instant,
+    /// Milliseconds after `created_at` of the last read, the remaining tti is counted from it by the snapshots.
+    read_after: AtomicU64,
+}
+
+impl<V: Clone> Clone for MemoryCacheEntry<V> {
+    fn clone(&self) -> Self {
+        MemoryCacheEntry {
+            value: self.value.clone(),
+            ttl: self.ttl,
+            tags: self.tags.clone(),
+            created_at: self.created_at,
+            read_after: AtomicU64::new(self.read_after.load(Ordering::Relaxed)),
+        }
+    }
+}
+
+impl<V> MemoryCacheEntry<V> {
+    #[inline]
+    fn new(value: V, ttl: Option<Duration>) -> Self {
+        Self::tagged(value, ttl, None)
+    }
+
+    #[inline]
+    fn tagged(value: V, ttl: Option<Duration>, tags: Option<Arc<[Arc<str>]>>) -> Self {
+        MemoryCacheEntry { value, ttl, tags, created_at: Instant::now(), read_after: AtomicU64::new(0) }
+    }
+
+    #[inline]
+    fn record_read(&self, read_at: Instant) {
+        let read_after = read_at.saturating_duration_since(self.created_at).as_millis() as u64;
+        self.read_after.fetch_max(read_after, Ordering::Relaxed);
+    }
+
+    /// How long it has not been read, or since created if never.
+    #[inline]
+    fn idle(&self, now: Instant) -> Duration {
+        now.saturating_duration_since(self.created_at)
+            .saturating_sub(Duration::from_millis(self.read_after.load(Ordering::Relaxed)))
+    }
+
+    #[inline]
+    fn has_tag(&self, tag: &str) -> bool {
+        self.tags.as_ref().is_some_and(|tags| tags.iter().any(|found| &**found == tag))
+    }
+}
+
+/// TTL and TTI of the policy, TTL can be overridden by each entry.
+struct MemoryCacheExpiry {
+    ttl: Option<Duration>,
+    tti: Option<Duration>,
+}
+
+impl MemoryCacheExpiry {
+    #[inline]
+    fn remaining(&self, ttl: Option<Duration>, elapsed: Duration) -> Option<Duration> {
+        match (ttl.map(|ttl| ttl.saturating_sub(elapsed)), self.tti) {
+            (Some(ttl), Some(tti)) => Some(ttl.min(tti)),
+            (ttl, tti) => ttl.or(tti),
+        }
+    }
+}
+
+impl<K, V> Expiry<K, MemoryCacheEntry<V>> for MemoryCacheExpiry {
+    fn expire_after_create(&self, _key: &K, entry: &MemoryCacheEntry<V>, _created_at: Instant) -> Option<Duration> {
+        self.remaining(entry.ttl.or(self.ttl), Duration::ZERO)
+    }
+
+    fn expire_after_read(
+        &self,
+        _key: &K,
+        entry: &MemoryCacheEntry<V>,
+        read_at: Instant,
+        _duration_until_expiry: Option<Duration>,
+        last_modified_at: Instant,
+    ) -> Option<Duration> {
+        entry.record_read(read_at);
+
+        self.remaining(entry.ttl.or(self.ttl), read_at.saturating_duration_since(last_modified_at))
+    }
+
+    fn expire_after_update(
+        &self,
+        _key: &K,
+        entry: &MemoryCacheEntry<V>,
+        _updated_at: Instant,
+        _duration_until_expiry: Option<Duration>,
+    ) -> Option<Duration> {
+        self.remaining(entry.ttl.or(self.ttl), Duration::ZERO)
+    }
+}
 
 pub trait MemoryCacheExt {
-    fn memory_cache(&self) -> std::result::Result<MemoryCacheExtension, ExtensionError>;
+    fn memory_caches(&self) -> std::result::Result<MemoryCacheRegistryGlobal, ExtensionError>;
+
+    fn memory_cache<N: MemoryCacheName>(&self) -> std::result::Result<MemoryCacheExtension<N>, ExtensionError>;
 }
 
-pub struct MemoryCache {
-    client: Cache<MemoryCacheKey, MemoryCacheValue>,
+/// Typed memory cache of a namespace, on top of moka.
+/// It doesn't deref to the moka cache any more, so `cache.get(..)` and the like only resolve to the methods below:
+/// the entries carry their own ttl and tags, which moka would skip together with the stats.
+pub struct MemoryCache<K = MemoryCacheKey, V = MemoryCacheValue> {
+    client: Cache<K, MemoryCacheEntry<V>>,
+    policy: MemoryCachePolicy,
+    stats: Arc<CacheStats>,
+    eviction_hooks: Arc<SyncRwLock<Vec<MemoryCacheEvictionHook<K, V>>>>,
 }
 
-impl Deref for MemoryCache {
-    type Target = Cache<MemoryCacheKey, MemoryCacheValue>;
-    fn deref(&self) -> &Self::Target {
-        &self.client
+impl<K, V> MemoryCache<K, V>
+where
+    K: MemoryCacheKeyBound,
+    V: MemoryCacheValueBound,
+{
+    pub async fn get<Q>(&self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let value = self.client.get(key).await.map(|entry| entry.value);
+        self.stats.record_get(value.is_some());
+
+        value
+    }
+
+    #[inline]
+    pub fn contains_key<Q>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.client.contains_key(key)
+    }
+
+    /// Expires with the ttl of the policy.
+    pub async fn insert(&self, key: K, value: V) {
+        self.stats.record_insert();
+        self.client.insert(key, MemoryCacheEntry::new(value, None)).await
+    }
+
+    /// Expires with its own `ttl` instead of the one of the policy.
+    pub async fn insert_with_ttl(&self, key: K, value: V, ttl: Duration) {
+        self.stats.record_insert();
+        self.client.insert(key, MemoryCacheEntry::new(value, Some(ttl))).await
+    }
+
+    /// Invalidated by `invalidate_tag` with any of the `tags`, e.g. `&["user:42"]`.
+    /// Expires with `ttl`, or the one of the policy.
+    pub async fn insert_tagged(&self, key: K, value: V, ttl: Option<Duration>, tags: &[&str]) {
+        self.stats.record_insert();
+
+        let tags = tags.iter().map(|&tag| Arc::from(tag)).collect();
+        self.client.insert(key, MemoryCacheEntry::tagged(value, ttl, Some(tags))).await
+    }
+
+    /// Invalidate all the entries tagged with `tag`.
+    /// They are removed lazily, but never returned after this call.
+    pub fn invalidate_tag(&self, tag: &str) -> std::result::Result<(), moka::PredicateError> {
+        let tag = tag.to_string();
+        self.client.invalidate_entries_if(move |_, entry| entry.has_tag(&tag))?;
+
+        Ok(())
+    }
+
+    /// Cache-aside, concurrent calls on the same missing key are coalesced,
+    /// only one `loader` will be evaluated and others wait for its value.
+    /// The loaded value expires with `ttl`, or the one of the policy.
+    pub async fn get_or_load<F>(&self, key: K, ttl: Option<Duration>, loader: F) -> Result<V>
+    where
+        F: Future<Output = Result<V>>,
+    {
+        let entry = self
+            .client
+            .entry(key)
+            .or_try_insert_with(async { Ok::<_, anyhow::Error>(MemoryCacheEntry::new(loader.await?, ttl)) })
+            .await
+            .map_err(|error| Arc::try_unwrap(error).unwrap_or_else(|error| SharedLoadError(error).into()))?;
+
+        // Only the caller evaluated the `loader` gets a fresh one.
+        self.stats.record_get(!entry.is_fresh());
+        if entry.is_fresh() {
+            self.stats.record_insert();
+        }
+
+        Ok(entry.into_value().value)
+    }
+
+    pub async fn invalidate<Q>(&self, key: &Q)
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.client.invalidate(key).await
+    }
+
+    #[inline]
+    pub fn invalidate_all(&self) {
+        self.client.invalidate_all()
+    }
+
+    #[inline]
+    pub fn entry_count(&self) -> u64 {
+        self.client.entry_count()
+    }
+
+    /// Estimated bytes in use.
+    #[inline]
+    pub fn weighted_size(&self) -> u64 {
+        self.client.weighted_size()
+    }
+
+    #[inline]
+    pub async fn run_pending_tasks(&self) {
+        self.client.run_pending_tasks().await
+    }
+
+    #[inline]
+    pub fn policy(&self) -> &MemoryCachePolicy {
+        &self.policy
+    }
+
+    #[inline]
+    pub fn stats(&self) -> CacheStatsSnapshot {
+        self.stats.snapshot()
+    }
+
+    /// Called once an entry is removed, except being replaced.
+    /// Keep it cheap, it runs on the cache's maintenance path.
+    pub fn on_eviction(&self, hook: impl Fn(&K, &V, RemovalCause) + Send + Sync + 'static) {
+        // UNWRAP: Nothing panics while holding the lock.
+        self.eviction_hooks.write().unwrap().push(Box::new(hook));
     }
 }
 
-impl DerefMut for MemoryCache {
-    fn deref_mut(&mut self) -> &mut Self::Target {
-        &mut self.client
+#[async_trait::async_trait]
+impl<K, V> MemoryCacheNamespace for MemoryCache<K, V>
+where
+    K: MemoryCacheKeyBound,
+    V: MemoryCacheValueBound,
+{
+    async fn summary(&self) -> MemoryCacheSummary {
+        MemoryCacheSummary { entry_count: self.entry_count(), weighted_size: self.weighted_size(), stats: self.stats() }
+    }
+
+    async fn keys(&self, prefix: &str, limit: usize) -> Vec<String> {
+        self.client.iter().map(|(key, _)| key.to_string()).filter(|key| key.starts_with(prefix)).take(limit).collect()
+    }
+
+    async fn invalidate_key(&self, key: &str) -> bool {
+        // Keys are only known by their display forms here.
+        let found = self.client.iter().map(|(found, _)| found).find(|found| found.to_string() == key);
+        match found {
+            Some(found) => {
+                self.invalidate(&*found).await;
+                true
+            }
+            None => false,
+        }
+    }
+
+    async fn invalidate_all(&self) {
+        MemoryCache::invalidate_all(self)
     }
 }
 
-pub struct MemoryCacheExtension(MemoryCacheGlobal);
+/// A named cache, declared with its key/value types and policy.
+/// Handlers extract it by `MemoryCacheExtension<N>`, it's registered on first use.
+pub trait MemoryCacheName: 'static {
+    const NAME: &'static str;
+    type Key: MemoryCacheKeyBound;
+    type Value: MemoryCacheValueBound;
 
-impl Deref for MemoryCacheExtension {
-    type Target = MemoryCacheGlobal;
-    fn deref(&self) -> &Self::Target {
-        &self.0
+    /// The default one of the registry if missing.
+    #[inline]
+    fn policy() -> Option<MemoryCachePolicy> {
+        None
     }
 }
 
-impl MemoryCacheExtension {
+/// The default namespace.
+pub struct MemoryCacheDefault;
+
+impl MemoryCacheName for MemoryCacheDefault {
+    const NAME: &'static str = MEMORY_CACHE_DEFAULT_NAMESPACE;
+    type Key = MemoryCacheKey;
+    type Value = MemoryCacheValue;
+}
+
+pub struct MemoryCacheRegistryExtension(MemoryCacheRegistryGlobal);
+
+impl MemoryCacheRegistryExtension {
     #[inline]
-    pub fn set_into_req(extensions: &mut Extensions, global: MemoryCacheGlobal) {
-        if !extensions.contains::<MemoryCacheExtension>() {
-            extensions.insert(MemoryCacheExtension(global))
+    pub fn set_into_req(extensions: &mut Extensions, global: MemoryCacheRegistryGlobal) {
+        if !extensions.contains::<MemoryCacheRegistryExtension>() {
+            extensions.insert(MemoryCacheRegistryExtension(global))
         }
     }
 
     #[inline]
-    fn get_from_req(extensions: &mut Extensions) -> std::result::Result<Self, ExtensionError> {
+    fn get_from_req(extensions: &mut Extensions) -> std::result::Result<MemoryCacheRegistryGlobal, ExtensionError> {
         extensions
-            .get::<MemoryCacheExtension>()
+            .get::<MemoryCacheRegistryExtension>()
             .ok_or(ExtensionError::MemoryCacheMissing)
-            .map(|ext| MemoryCacheExtension(Arc::clone(&ext.0)))
+            .map(|ext| Arc::clone(&ext.0))
+    }
+}
+
+/// The cache named `N` of the registry in the request, registered on first use.
+pub struct MemoryCacheExtension<N: MemoryCacheName = MemoryCacheDefault>(
+    MemoryCacheGlobal<N::Key, N::Value>,
+    PhantomData<N>,
+);
+
+impl<N: MemoryCacheName> Deref for MemoryCacheExtension<N> {
+    type Target = MemoryCacheGlobal<N::Key, N::Value>;
+    fn deref(&self) -> &Self::Target {
+        &self.0
     }
 }
 
-impl<Err> FromRequest<Err> for MemoryCacheExtension {
+impl<N: MemoryCacheName> MemoryCacheExtension<N> {
+    #[inline]
+    fn get_from_req(extensions: &mut Extensions) -> std::result::Result<Self, ExtensionError> {
+        let cache = MemoryCacheRegistryExtension::get_from_req(extensions)?.cache::<N>()?;
+
+        Ok(MemoryCacheExtension(cache, PhantomData))
+    }
+}
+
+impl<N: MemoryCacheName, Err> FromRequest<Err> for MemoryCacheExtension<N> {
     type Error = ExtensionError;
 
     #[inline]
@@ -72,26 +556,59 @@ impl<Err> FromRequest<Err> for MemoryCacheExtension {
     }
 }
 
-pub fn generate() -> MemoryCacheGlobal {
-    debug!("Generating the memory cache.");
+fn build<K, V>(policy: MemoryCachePolicy) -> MemoryCache<K, V>
+where
+    K: MemoryCacheKeyBound,
+    V: MemoryCacheValueBound,
+{
+    let stats = Arc::new(CacheStats::default());
+    let eviction_hooks = Arc::new(SyncRwLock::new(Vec::<MemoryCacheEvictionHook<K, V>>::new()));
+
+    let listener = {
+        let stats = Arc::clone(&stats);
+        let eviction_hooks = Arc::clone(&eviction_hooks);
+
+        move |key: Arc<K>, entry: MemoryCacheEntry<V>, cause: RemovalCause| {
+            match cause {
+                RemovalCause::Replaced => return,
+                RemovalCause::Explicit => stats.record_invalidation(),
+                RemovalCause::Expired | RemovalCause::Size => stats.record_eviction(),
+            }
+
+            // UNWRAP: Nothing panics while holding the lock.
+            for hook in eviction_hooks.read().unwrap().iter() {
+                hook(&key, &entry.value, cause);
+            }
+        }
+    };
 
-    Arc::new(RwLock::new(MemoryCache {
+    MemoryCache {
+        stats,
+        eviction_hooks,
         client: Cache::builder()
-            // Time to live (TTL): 30 minutes
-            .time_to_live(Duration::from_secs(30 * 60))
-            // Time to idle (TTI):  5 minutes
-            .time_to_idle(Duration::from_secs(5 * 60))
-            // This cache will hold up to 32MiB of values.
-            .max_capacity(32 * 1024 * 1024)
+            .eviction_listener(listener)
+            .expire_after(MemoryCacheExpiry { ttl: policy.ttl, tti: policy.tti })
+            .weigher(|key: &K, entry: &MemoryCacheEntry<V>| {
+                let tags = entry.tags.as_ref().map_or(0, |tags| tags.iter().map(|tag| tag.weigh()).sum());
+                (key.weigh() + entry.value.weigh() + tags).try_into().unwrap_or(u32::MAX)
+            })
+            .max_capacity(policy.max_bytes)
+            .support_invalidation_closures()
             .build(),
-    }))
+        policy,
+    }
 }
 
 macro_rules! impl_ext {
     ($ident: ident) => {
         impl MemoryCacheExt for $ident {
             #[inline]
-            fn memory_cache(&self) -> std::result::Result<MemoryCacheExtension, ExtensionError> {
+            fn memory_caches(&self) -> std::result::Result<MemoryCacheRegistryGlobal, ExtensionError> {
+                MemoryCacheRegistryExtension::get_from_req(&mut self.extensions_mut())
+            }
+
+            #[inline]
+            fn memory_cache<N: MemoryCacheName>(&self) -> std::result::Result<MemoryCacheExtension<N>, ExtensionError> {
                 MemoryCacheExtension::get_from_req(&mut self.extensions_mut())
             }
         }
@@ -100,7 +617,12 @@ macro_rules! impl_ext {
     ($ident: ident<$($genetic: tt),+>) => {
         impl<$($genetic)+> MemoryCacheExt for $ident<$($genetic)+> {
             #[inline]
-            fn memory_cache(&self) -> std::result::Result<MemoryCacheExtension, ExtensionError> {
+            fn memory_caches(&self) -> std::result::Result<MemoryCacheRegistryGlobal, ExtensionError> {
+                MemoryCacheRegistryExtension::get_from_req(&mut self.extensions_mut())
+            }
+
+            #[inline]
+            fn memory_cache<N: MemoryCacheName>(&self) -> std::result::Result<MemoryCacheExtension<N>, ExtensionError> {
                 MemoryCacheExtension::get_from_req(&mut self.extensions_mut())
             }
         }
@@ -120,3 +642,190 @@ macro_rules! memory_cache_make_sure {
         $cache.run_pending_tasks().await;
     };
 }
+
+#[cfg(test)]
+mod tests {
+    use super::registry::MemoryCacheRegistry;
+    use super::{MemoryCacheName, MemoryCachePolicy, MemoryCacheWeigh, RemovalCause};
+    use crate::error::MemoryCacheError;
+    use serde_json::json;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    #[ntex::test]
+    async fn namespace_shared() {
+        let registry = MemoryCacheRegistry::default();
+        let users = registry.namespace::<u64, String>("tests:users").unwrap();
+        users.insert(42, "Alice".to_string()).await;
+
+        let again = registry.namespace::<u64, String>("tests:users").unwrap();
+        assert!(Arc::ptr_eq(&users, &again));
+        assert_eq!(again.get(&42).await.as_deref(), Some("Alice"));
+    }
+
+    #[ntex::test]
+    async fn get_or_load_coalesced() {
+        let registry = MemoryCacheRegistry::default();
+        let cache = registry.namespace::<u64, u64>("tests:coalesced").unwrap();
+        let loaded = AtomicUsize::new(0);
+
+        let load = || {
+            cache.get_or_load(1, None, async {
+                loaded.fetch_add(1, Ordering::SeqCst);
+                tokio::time::sleep(Duration::from_millis(50)).await;
+
+                Ok(42)
+            })
+        };
+
+        let (a, b, c) = tokio::join!(load(), load(), load());
+        assert_eq!((a.unwrap(), b.unwrap(), c.unwrap()), (42, 42, 42));
+        assert_eq!(loaded.load(Ordering::SeqCst), 1);
+    }
+
+    #[ntex::test]
+    async fn namespace_type_mismatch() {
+        let registry = MemoryCacheRegistry::default();
+        let _ = registry.namespace::<Arc<str>, u32>("tests:mismatch").unwrap();
+
+        let result = registry.namespace::<Arc<str>, String>("tests:mismatch");
+        assert!(matches!(result, Err(MemoryCacheError::NamespaceTypeMismatch(name)) if name == "tests:mismatch"));
+    }
+
+    #[ntex::test]
+    async fn named_cache_policy() {
+        struct Sessions;
+
+        impl MemoryCacheName for Sessions {
+            const NAME: &'static str = "tests:sessions";
+            type Key = u64;
+            type Value = String;
+
+            fn policy() -> Option<MemoryCachePolicy> {
+                Some(MemoryCachePolicy { ttl: Some(Duration::from_secs(60)), tti: None, max_bytes: 1024 })
+            }
+        }
+
+        let registry = MemoryCacheRegistry::default();
+        let sessions = registry.cache::<Sessions>().unwrap();
+        assert_eq!(sessions.policy(), &Sessions::policy().unwrap());
+        assert!(Arc::ptr_eq(&sessions, &registry.namespace::<u64, String>("tests:sessions").unwrap()));
+
+        let others = registry.namespace::<u64, String>("tests:others").unwrap();
+        assert_eq!(others.policy(), registry.default_policy());
+
+        let result = registry.register::<u64, String>("tests:others", Sessions::policy());
+        assert!(matches!(result, Err(MemoryCacheError::NamespacePolicyMismatch(name)) if name == "tests:others"));
+    }
+
+    #[ntex::test]
+    async fn entry_ttl_overridden() {
+        let registry = MemoryCacheRegistry::default();
+        let cache = registry.namespace::<u64, u64>("tests:entry_ttl").unwrap();
+        cache.insert(1, 1).await;
+        cache.insert_with_ttl(2, 2, Duration::from_millis(20)).await;
+
+        tokio::time::sleep(Duration::from_millis(30)).await;
+        assert_eq!(cache.get(&1).await, Some(1));
+        assert_eq!(cache.get(&2).await, None);
+    }
+
+    #[ntex::test]
+    async fn weighed_in_bytes() {
+        let registry = MemoryCacheRegistry::default();
+        let small = json!({ "a": 1 });
+        let large = json!({ "a": "x".repeat(1024), "b": [1, 2, 3] });
+        assert!(large.weigh() > small.weigh() + 1024);
+
+        let cache = registry.namespace::<u64, serde_json::Value>("tests:weighed").unwrap();
+        cache.insert(1, large.clone()).await;
+        cache.run_pending_tasks().await;
+        assert_eq!(cache.weighted_size(), (1u64.weigh() + large.weigh()) as u64);
+    }
+
+    #[ntex::test]
+    async fn stats_and_eviction_hooks() {
+        let registry = MemoryCacheRegistry::default();
+        let cache = registry.namespace::<u64, u64>("tests:stats").unwrap();
+        let evicted = Arc::new(AtomicUsize::new(0));
+        let hook_evicted = Arc::clone(&evicted);
+        cache.on_eviction(move |_, _, cause| {
+            if cause == RemovalCause::Expired {
+                hook_evicted.fetch_add(1, Ordering::SeqCst);
+            }
+        });
+
+        cache.insert(1, 1).await;
+        cache.insert_with_ttl(2, 2, Duration::from_millis(10)).await;
+        assert_eq!(cache.get(&1).await, Some(1));
+        assert_eq!(cache.get(&3).await, None);
+        cache.invalidate(&1).await;
+
+        // Expired entries are removed by the timer wheel, which ticks in about one second.
+        tokio::time::sleep(Duration::from_millis(1500)).await;
+        cache.run_pending_tasks().await;
+
+        let stats = cache.stats();
+        assert_eq!((stats.hits, stats.misses, stats.inserts), (1, 1, 2));
+        assert_eq!((stats.invalidations, stats.evictions), (1, 1));
+        assert_eq!(evicted.load(Ordering::SeqCst), 1);
+    }
+
+    #[ntex::test]
+    async fn namespace_admin() {
+        let registry = MemoryCacheRegistry::default();
+        let cache = registry.namespace::<Arc<str>, u64>("tests:admin").unwrap();
+        for key in ["user:1", "user:2", "post:1"] {
+            cache.insert(key.into(), 1).await;
+        }
+
+        let admin = registry.namespace_admin("tests:admin").unwrap();
+        let mut keys = admin.keys("user:", 10).await;
+        keys.sort();
+        assert_eq!(keys, ["user:1", "user:2"]);
+
+        assert!(admin.invalidate_key("user:1").await);
+        assert!(!admin.invalidate_key("user:3").await);
+        assert_eq!(cache.get("user:1").await, None);
+        assert!(registry.namespaces().iter().any(|(name, _)| &**name == "tests:admin"));
+    }
+
+    #[ntex::test]
+    async fn invalidate_tag() {
+        let registry = MemoryCacheRegistry::default();
+        let cache = registry.namespace::<u64, u64>("tests:tags").unwrap();
+        cache.insert_tagged(1, 1, None, &["user:42", "posts"]).await;
+        cache.insert_tagged(2, 2, None, &["user:42"]).await;
+        cache.insert_tagged(3, 3, None, &["user:43"]).await;
+        cache.insert(4, 4).await;
+
+        cache.invalidate_tag("user:42").unwrap();
+        assert_eq!(cache.get(&1).await, None);
+        assert_eq!(cache.get(&2).await, None);
+        assert_eq!(cache.get(&3).await, Some(3));
+        assert_eq!(cache.get(&4).await, Some(4));
+    }
+
+    #[ntex::test]
+    async fn get_or_load_error_kept() {
+        let registry = MemoryCacheRegistry::default();
+        let cache = registry.namespace::<u64, u64>("tests:load_error").unwrap();
+
+        let load = || {
+            cache.get_or_load(1, None, async {
+                tokio::time::sleep(Duration::from_millis(20)).await;
+                Err(std::io::Error::new(std::io::ErrorKind::TimedOut, "Loader timed out."))?
+            })
+        };
+        let (first, second) = tokio::join!(load(), load());
+        let errors = [first.unwrap_err(), second.unwrap_err()];
+
+        // Kept in the chain, even of the coalesced call.
+        for error in errors {
+            assert!(error.chain().any(|cause| cause.is::<std::io::Error>()));
+            assert!(format!("{error:#}").ends_with("Loader timed out."));
+        }
+        assert_eq!(cache.get(&1).await, None);
+    }
+}
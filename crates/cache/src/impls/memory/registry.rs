@@ -0,0 +1,112 @@
+/// Registry of the named memory caches.
+/// Each name owns a standalone cache, with its own key/value types and policy.
+use crate::error::MemoryCacheError;
+use crate::impls::memory::{
+    build, MemoryCacheGlobal, MemoryCacheKeyBound, MemoryCacheName, MemoryCacheNamespace, MemoryCachePolicy,
+    MemoryCacheValueBound,
+};
+use std::any::Any;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+pub type MemoryCacheRegistryGlobal = Arc<MemoryCacheRegistry>;
+
+struct MemoryCacheRegistryEntry {
+    cache: Arc<dyn Any + Send + Sync>,
+    admin: Arc<dyn MemoryCacheNamespace>,
+    policy: MemoryCachePolicy,
+}
+
+#[derive(Default)]
+pub struct MemoryCacheRegistry {
+    /// Of the caches registered without their own.
+    default_policy: MemoryCachePolicy,
+    caches: Mutex<HashMap<Arc<str>, MemoryCacheRegistryEntry>>,
+}
+
+impl MemoryCacheRegistry {
+    pub fn new(default_policy: MemoryCachePolicy) -> Self {
+        MemoryCacheRegistry { default_policy, caches: Default::default() }
+    }
+
+    #[inline]
+    pub fn default_policy(&self) -> &MemoryCachePolicy {
+        &self.default_policy
+    }
+
+    /// Get the cache named `N`, register it with its policy if missing.
+    #[inline]
+    pub fn cache<N: MemoryCacheName>(&self) -> Result<MemoryCacheGlobal<N::Key, N::Value>, MemoryCacheError> {
+        self.register(N::NAME, N::policy())
+    }
+
+    /// Get the typed cache registered as `name`, register it with the default policy if missing.
+    #[inline]
+    pub fn namespace<K, V>(&self, name: &str) -> Result<MemoryCacheGlobal<K, V>, MemoryCacheError>
+    where
+        K: MemoryCacheKeyBound,
+        V: MemoryCacheValueBound,
+    {
+        self.register(name, None)
+    }
+
+    /// Get the typed cache registered as `name`, register it with `policy` if missing.
+    /// The same `name` can not be shared by caches with different key/value types or policies.
+    pub fn register<K, V>(
+        &self,
+        name: &str,
+        policy: Option<MemoryCachePolicy>,
+    ) -> Result<MemoryCacheGlobal<K, V>, MemoryCacheError>
+    where
+        K: MemoryCacheKeyBound,
+        V: MemoryCacheValueBound,
+    {
+        // UNWRAP: Nothing panics while holding the lock.
+        let mut caches = self.caches.lock().unwrap();
+
+        match caches.get(name) {
+            Some(entry) => {
+                if policy.is_some_and(|policy| policy != entry.policy) {
+                    return Err(MemoryCacheError::NamespacePolicyMismatch(name.to_string()));
+                }
+
+                Arc::clone(&entry.cache)
+                    .downcast::<crate::impls::memory::MemoryCache<K, V>>()
+                    .map_err(|_| MemoryCacheError::NamespaceTypeMismatch(name.to_string()))
+            }
+            None => {
+                debug!(namespace = name, "Registering the memory cache.");
+
+                let policy = policy.unwrap_or_else(|| self.default_policy.clone());
+                let cache = Arc::new(build::<K, V>(policy.clone()));
+                caches.insert(
+                    name.into(),
+                    MemoryCacheRegistryEntry {
+                        cache: Arc::clone(&cache) as Arc<dyn Any + Send + Sync>,
+                        admin: Arc::clone(&cache) as Arc<dyn MemoryCacheNamespace>,
+                        policy,
+                    },
+                );
+
+                Ok(cache)
+            }
+        }
+    }
+
+    /// All the registered caches, sorted by name.
+    pub fn namespaces(&self) -> Vec<(Arc<str>, Arc<dyn MemoryCacheNamespace>)> {
+        // UNWRAP: Nothing panics while holding the lock.
+        let caches = self.caches.lock().unwrap();
+
+        let mut namespaces =
+            caches.iter().map(|(name, entry)| (Arc::clone(name), Arc::clone(&entry.admin))).collect::<Vec<_>>();
+        namespaces.sort_unstable_by(|(a, _), (b, _)| a.cmp(b));
+
+        namespaces
+    }
+
+    pub fn namespace_admin(&self, name: &str) -> Option<Arc<dyn MemoryCacheNamespace>> {
+        // UNWRAP: Nothing panics while holding the lock.
+        self.caches.lock().unwrap().get(name).map(|entry| Arc::clone(&entry.admin))
+    }
+}
@@ -0,0 +1,271 @@
+/// Snapshots of the memory cache, so the entries survive restarts.
+/// Each entry keeps the shorter of its remaining ttl and tti, the downtime is counted when being restored.
+/// Written as msgpack with zstd, a corrupt or mismatched snapshot is ignored.
+/// Only the default namespace is snapshotted by the app, the other ones start empty.
+use crate::error::MemoryCacheError;
+use crate::impls::codec::{CacheCodec, CacheCodecConfig, CacheCompression};
+use crate::impls::memory::{
+    MemoryCache, MemoryCacheEntry, MemoryCacheGlobal, MemoryCacheKeyBound, MemoryCacheValueBound,
+};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use web_core::prelude::*;
+use web_core::utils::unix_millis;
+
+const SNAPSHOT_VERSION: u32 = 1;
+const SNAPSHOT_CODEC: CacheCodecConfig =
+    CacheCodecConfig { codec: CacheCodec::MessagePack, compression: CacheCompression::Zstd, compress_threshold: 0 };
+
+/// Where and when to write the snapshots of the default namespace, read from the env:
+/// - `MEMORY_CACHE_SNAPSHOT_PATH`, e.g. `/var/lib/web/memory_cache.snapshot`, disabled without it.
+/// - `MEMORY_CACHE_SNAPSHOT_INTERVAL`, e.g. `5m`, only written on graceful shutdown without it.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct MemoryCacheSnapshotConfig {
+    pub path: Option<PathBuf>,
+    pub interval: Option<Duration>,
+}
+
+impl MemoryCacheSnapshotConfig {
+    pub fn from_env() -> Result<Self> {
+        Ok(MemoryCacheSnapshotConfig {
+            path: web_env::var("MEMORY_CACHE_SNAPSHOT_PATH")?.map(PathBuf::from),
+            interval: web_env::var_parsed::<humantime::Duration>("MEMORY_CACHE_SNAPSHOT_INTERVAL")?
+                .map(Duration::from)
+                .filter(|interval| !interval.is_zero()),
+        })
+    }
+}
+
+#[derive(Deserialize)]
+struct SnapshotVersion {
+    version: u32,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Snapshot<K, V> {
+    version: u32,
+    /// Unix time in milliseconds.
+    written_at: u64,
+    entries: Vec<SnapshotEntry<K, V>>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct SnapshotEntry<K, V> {
+    key: K,
+    value: V,
+    /// Remaining milliseconds, never expires if missing.
+    ttl: Option<u64>,
+    tags: Option<Vec<Arc<str>>>,
+}
+
+fn decode<K: DeserializeOwned, V: DeserializeOwned>(content: &[u8]) -> Result<Snapshot<K, V>> {
+    let decompressed = SNAPSHOT_CODEC.decompress(content)?;
+
+    // Checked first, the entries of other versions may not be readable at all.
+    let SnapshotVersion { version } = decompressed.deserialize()?;
+    if version != SNAPSHOT_VERSION {
+        return Err(MemoryCacheError::SnapshotVersionMismatch(version).into());
+    }
+
+    decompressed.deserialize()
+}
+
+impl<K, V> MemoryCache<K, V>
+where
+    K: MemoryCacheKeyBound + Serialize + DeserializeOwned,
+    V: MemoryCacheValueBound + Serialize + DeserializeOwned,
+{
+    /// Write the live entries to `path`, the older snapshot is replaced atomically.
+    /// Returns how many entries are written.
+    pub async fn snapshot(&self, path: &Path) -> Result<usize> {
+        let now = Instant::now();
+        let (policy_ttl, policy_tti) = (self.policy.ttl, self.policy.tti);
+
+        let entries = self
+            .client
+            .iter()
+            .filter_map(|(key, entry)| {
+                let remaining = [
+                    (entry.ttl.or(policy_ttl), now.saturating_duration_since(entry.created_at)),
+                    (policy_tti, entry.idle(now)),
+                ];
+                let ttl = remaining.into_iter().filter_map(|(limit, spent)| Some(limit?.saturating_sub(spent))).min();
+                if ttl.is_some_and(|ttl| ttl.is_zero()) {
+                    return None;
+                }
+
+                Some(SnapshotEntry {
+                    key,
+                    value: entry.value,
+                    ttl: ttl.map(|ttl| ttl.as_millis() as u64),
+                    tags: entry.tags.map(|tags| tags.to_vec()),
+                })
+            })
+            .collect::<Vec<_>>();
+        let count = entries.len();
+
+        let content =
+            SNAPSHOT_CODEC.encode(&Snapshot { version: SNAPSHOT_VERSION, written_at: unix_millis(), entries })?;
+
+        let path = path.to_path_buf();
+        tokio::task::spawn_blocking(move || {
+            let mut temp_path = path.clone().into_os_string();
+            temp_path.push(".tmp");
+
+            std::fs::write(&temp_path, content)?;
+            std::fs::rename(&temp_path, &path)
+        })
+        .await??;
+
+        Ok(count)
+    }
+
+    /// Restore the entries written by `snapshot`, the expired ones are dropped.
+    /// A missing, corrupt or mismatched snapshot restores nothing.
+    /// Returns how many entries are restored.
+    pub async fn restore(&self, path: &Path) -> Result<usize> {
+        let content = match tokio::fs::read(path).await {
+            Ok(content) => content,
+            Err(error) if error.kind() == std::io::ErrorKind::NotFound => return Ok(0),
+            Err(error) => return Err(error.into()),
+        };
+
+        let snapshot = match decode::<K, V>(&content) {
+            Ok(snapshot) => snapshot,
+            Err(error) => {
+                warn!(path = %path.display(), %error, "Memory cache snapshot ignored.");
+                return Ok(0);
+            }
+        };
+
+        let downtime = Duration::from_millis(unix_millis().saturating_sub(snapshot.written_at));
+        let mut restored = 0;
+
+        for entry in snapshot.entries {
+            let ttl = match entry.ttl {
+                Some(ttl) => match Duration::from_millis(ttl).checked_sub(downtime).filter(|ttl| !ttl.is_zero()) {
+                    Some(ttl) => Some(ttl),
+                    None => continue,
+                },
+                None => None,
+            };
+
+            self.stats.record_insert();
+            self.client.insert(entry.key, MemoryCacheEntry::tagged(entry.value, ttl, entry.tags.map(Arc::from))).await;
+            restored += 1;
+        }
+
+        Ok(restored)
+    }
+}
+
+/// Write the snapshot of `cache` every `interval`, until it's dropped.
+pub fn spawn<K, V>(cache: &MemoryCacheGlobal<K, V>, path: PathBuf, interval: Duration) -> tokio::task::JoinHandle<()>
+where
+    K: MemoryCacheKeyBound + Serialize + DeserializeOwned,
+    V: MemoryCacheValueBound + Serialize + DeserializeOwned,
+{
+    let cache = Arc::downgrade(cache);
+
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(interval);
+        ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        // The first tick completes immediately, nothing to write yet.
+        ticker.tick().await;
+
+        loop {
+            ticker.tick().await;
+
+            let Some(cache) = cache.upgrade() else {
+                break;
+            };
+
+            match cache.snapshot(&path).await {
+                Ok(count) => debug!(count, path = %path.display(), "Memory cache snapshot written."),
+                Err(error) => warn!(path = %path.display(), %error, "Failed to write the memory cache snapshot."),
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Snapshot, SNAPSHOT_CODEC};
+    use crate::impls::memory::registry::MemoryCacheRegistry;
+    use crate::impls::memory::{MemoryCacheKey, MemoryCachePolicy, MemoryCacheValue};
+    use serde_json::json;
+    use std::time::Duration;
+
+    fn snapshot_path(name: &str) -> std::path::PathBuf {
+        std::env::temp_dir().join(format!("web_cache_{}_{name}.snapshot", std::process::id()))
+    }
+
+    #[ntex::test]
+    async fn snapshot_and_restore() {
+        let path = snapshot_path("round_trip");
+        let registry = MemoryCacheRegistry::default();
+        let cache = registry.namespace::<MemoryCacheKey, MemoryCacheValue>("tests:snapshot").unwrap();
+        cache.insert_tagged("a".into(), json!({ "id": 1 }), Some(Duration::from_secs(3600)), &["user:1"]).await;
+        cache.insert_with_ttl("b".into(), json!(2), Duration::from_millis(50)).await;
+        assert_eq!(cache.snapshot(&path).await.unwrap(), 2);
+
+        // The downtime is counted.
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        let restored = registry.namespace::<MemoryCacheKey, MemoryCacheValue>("tests:snapshot:restored").unwrap();
+        assert_eq!(restored.restore(&path).await.unwrap(), 1);
+        assert_eq!(restored.get("a").await, Some(json!({ "id": 1 })));
+        assert_eq!(restored.get("b").await, None);
+
+        // Tags are restored too.
+        restored.invalidate_tag("user:1").unwrap();
+        assert_eq!(restored.get("a").await, None);
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[ntex::test]
+    async fn tti_counted() {
+        let path = snapshot_path("tti");
+        let policy = MemoryCachePolicy { ttl: None, tti: Some(Duration::from_millis(400)), max_bytes: 1024 * 1024 };
+        let registry = MemoryCacheRegistry::new(policy);
+        let cache = registry.namespace::<MemoryCacheKey, MemoryCacheValue>("tests:snapshot:tti").unwrap();
+        cache.insert("a".into(), json!(1)).await;
+        cache.insert("b".into(), json!(2)).await;
+
+        // Only `a` is read, `b` is idle since inserted.
+        tokio::time::sleep(Duration::from_millis(250)).await;
+        assert_eq!(cache.get("a").await, Some(json!(1)));
+        assert_eq!(cache.snapshot(&path).await.unwrap(), 2);
+        tokio::time::sleep(Duration::from_millis(250)).await;
+
+        let restored = registry.namespace::<MemoryCacheKey, MemoryCacheValue>("tests:snapshot:tti:restored").unwrap();
+        assert_eq!(restored.restore(&path).await.unwrap(), 1);
+        assert_eq!(restored.get("a").await, Some(json!(1)));
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[ntex::test]
+    async fn ignore_unreadable_snapshots() {
+        let cache = MemoryCacheRegistry::default().namespace::<MemoryCacheKey, MemoryCacheValue>("tests").unwrap();
+
+        let path = snapshot_path("missing");
+        assert_eq!(cache.restore(&path).await.unwrap(), 0);
+
+        let path = snapshot_path("corrupt");
+        std::fs::write(&path, b"\xCA\x01\x01\x01 not a snapshot").unwrap();
+        assert_eq!(cache.restore(&path).await.unwrap(), 0);
+
+        let path = snapshot_path("mismatched");
+        let snapshot = Snapshot::<MemoryCacheKey, MemoryCacheValue> { version: 0, written_at: 0, entries: vec![] };
+        std::fs::write(&path, SNAPSHOT_CODEC.encode(&snapshot).unwrap()).unwrap();
+        assert_eq!(cache.restore(&path).await.unwrap(), 0);
+
+        for name in ["corrupt", "mismatched"] {
+            std::fs::remove_file(snapshot_path(name)).unwrap();
+        }
+    }
+}
@@ -1,3 +1,17 @@
+pub mod codec;
+
 pub mod distribute;
 
+pub mod event_bus;
+
+pub mod job_queue;
+
+pub mod loading;
+
 pub mod memory;
+
+pub mod stats;
+
+pub mod tiered;
+
+pub mod warmup;
@@ -0,0 +1,313 @@
+/// Tiered cache - Memory cache (L1) in front of the distribute cache (L2).
+/// Writes and deletes are broadcasted, so every instance evicts its own L1 copy.
+use crate::impls::codec::CacheCodec;
+use crate::impls::distribute::DistributeCacheGlobal;
+use crate::impls::memory::registry::MemoryCacheRegistry;
+use crate::impls::memory::{MemoryCacheGlobal, MemoryCacheKey, MemoryCacheValue, MemoryCacheValueBound};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::any::TypeId;
+use std::future::Future;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::broadcast::error::RecvError;
+use web_core::prelude::*;
+use web_guard::async_op::AsyncOpGuard;
+
+pub const TIERED_CACHE_INVALIDATION_CHANNEL: &str = "web_cache:tiered:invalidation";
+
+pub type TieredCacheGlobal<V = MemoryCacheValue> = Arc<TieredCache<V>>;
+
+#[derive(Serialize, Deserialize, Debug)]
+struct Invalidation {
+    origin: String,
+    namespace: String,
+    target: InvalidationTarget,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(tag = "kind", content = "value", rename_all = "snake_case")]
+enum InvalidationTarget {
+    Key(String),
+    Tag(String),
+    /// The whole namespace.
+    All,
+}
+
+pub struct TieredCache<V = MemoryCacheValue> {
+    namespace: Arc<str>,
+    l1: MemoryCacheGlobal<MemoryCacheKey, V>,
+    l2: DistributeCacheGlobal,
+    l2_ttl: Option<Duration>,
+}
+
+impl<V> TieredCache<V>
+where
+    V: MemoryCacheValueBound + Serialize + DeserializeOwned,
+{
+    #[inline]
+    fn l2_key(&self, key: &str) -> String {
+        format!("{}:{}", self.namespace, key)
+    }
+
+    /// L1 copies never outlive the L2 ones.
+    async fn l1_insert(&self, key: &str, value: V) {
+        match self.l2_ttl {
+            Some(ttl) => self.l1.insert_with_ttl(key.into(), value, ttl).await,
+            None => self.l1.insert(key.into(), value).await,
+        }
+    }
+
+    /// L1 -> L2.
+    /// The value found in L2 will be written back to L1.
+    pub async fn get(&self, key: &str) -> Result<Option<V>> {
+        if let Some(value) = self.l1.get(key).await {
+            return Ok(Some(value));
+        }
+
+        match self.l2.get_as::<V>(&self.l2_key(key)).await? {
+            Some(value) => {
+                self.l1_insert(key, value.clone()).await;
+
+                Ok(Some(value))
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// L1 -> L2 -> loader.
+    /// Concurrent calls on the same missing key are coalesced on both tiers.
+    pub async fn get_or_load<F>(&self, key: &str, ttl: Option<Duration>, loader: F) -> Result<V>
+    where
+        F: Future<Output = Result<V>>,
+    {
+        let l2_key = self.l2_key(key);
+
+        let ttl = ttl.or(self.l2_ttl);
+
+        self.l1.get_or_load(key.into(), ttl, self.l2.get_or_load(&l2_key, ttl, loader)).await
+    }
+
+    /// Same as `get_or_load`, but loaders on other instances are coalesced as well.
+    /// Loaded without the cross-instance lock if it can't be taken within `LOADING_LOCK_MAX_WAIT`.
+    pub async fn get_or_load_exclusive<F>(
+        &self,
+        key: &str,
+        ttl: Option<Duration>,
+        async_op_guard: &AsyncOpGuard,
+        loader: F,
+    ) -> Result<V>
+    where
+        F: Future<Output = Result<V>>,
+    {
+        let l2_key = self.l2_key(key);
+        let ttl = ttl.or(self.l2_ttl);
+
+        self.l1.get_or_load(key.into(), ttl, self.l2.get_or_load_exclusive(&l2_key, ttl, async_op_guard, loader)).await
+    }
+
+    pub async fn insert(&self, key: &str, value: V) -> Result<()> {
+        self.l2.set_as(&self.l2_key(key), &value, self.l2_ttl).await?;
+        self.l1_insert(key, value).await;
+
+        self.broadcast(InvalidationTarget::Key(key.to_string())).await
+    }
+
+    /// Invalidated by `invalidate_tag` with any of the `tags`, e.g. `&["user:42"]`.
+    pub async fn insert_tagged(&self, key: &str, value: V, tags: &[&str]) -> Result<()> {
+        let l2_tags = tags.iter().map(|tag| self.l2_key(tag)).collect::<Vec<_>>();
+        let l2_tags = l2_tags.iter().map(String::as_str).collect::<Vec<_>>();
+        self.l2.insert_tagged(&self.l2_key(key), &value, self.l2_ttl, &l2_tags).await?;
+        self.l1.insert_tagged(key.into(), value, self.l2_ttl, tags).await;
+
+        self.broadcast(InvalidationTarget::Key(key.to_string())).await
+    }
+
+    /// Invalidate the entries tagged with `tag` on both tiers, and on every instance.
+    pub async fn invalidate_tag(&self, tag: &str) -> Result<()> {
+        self.l2.invalidate_tag(&self.l2_key(tag)).await?;
+        self.l1.invalidate_tag(tag)?;
+
+        self.broadcast(InvalidationTarget::Tag(tag.to_string())).await
+    }
+
+    pub async fn invalidate(&self, key: &str) -> Result<()> {
+        self.l2.del(&self.l2_key(key)).await?;
+        self.l1.invalidate(key).await;
+
+        self.broadcast(InvalidationTarget::Key(key.to_string())).await
+    }
+
+    /// Evict all the L1 copies of the namespace, on every instance.
+    /// The L2 entries are kept, until they expire or are invalidated by key or tag.
+    pub async fn invalidate_all_l1(&self) -> Result<()> {
+        self.l1.invalidate_all();
+
+        self.broadcast(InvalidationTarget::All).await
+    }
+
+    async fn broadcast(&self, target: InvalidationTarget) -> Result<()> {
+        let invalidation =
+            Invalidation { origin: self.l2.id().to_string(), namespace: self.namespace.to_string(), target };
+
+        self.l2.publish(TIERED_CACHE_INVALIDATION_CHANNEL, serde_json::to_vec(&invalidation)?).await
+    }
+
+    /// Evict the L1 copies once other instances changed them.
+    async fn listen(&self) -> Result<()> {
+        let mut message_rx = self.l2.subscribe(TIERED_CACHE_INVALIDATION_CHANNEL).await?;
+        let origin = self.l2.id().to_string();
+        let namespace = Arc::clone(&self.namespace);
+        let l1 = Arc::clone(&self.l1);
+
+        tokio::spawn(async move {
+            loop {
+                let message = match message_rx.recv().await {
+                    Ok(message) => message,
+                    // Some invalidations were missed, the L1 copies can not be trusted any more.
+                    Err(RecvError::Lagged(_)) => {
+                        l1.invalidate_all();
+                        continue;
+                    }
+                    Err(RecvError::Closed) => break,
+                };
+
+                if &*message.channel != TIERED_CACHE_INVALIDATION_CHANNEL {
+                    continue;
+                }
+
+                let invalidation = match serde_json::from_slice::<Invalidation>(&message.payload) {
+                    Ok(invalidation) => invalidation,
+                    Err(error) => {
+                        warn!(error = %error, "Malformed tiered cache invalidation.");
+                        continue;
+                    }
+                };
+
+                // Local copies have been handled already.
+                if invalidation.origin == origin || *invalidation.namespace != *namespace {
+                    continue;
+                }
+
+                trace!(namespace = %namespace, target = ?invalidation.target, "Evicting the tiered cache L1 copy.");
+
+                match invalidation.target {
+                    InvalidationTarget::Key(key) => l1.invalidate(key.as_str()).await,
+                    InvalidationTarget::Tag(tag) => {
+                        if let Err(error) = l1.invalidate_tag(&tag) {
+                            warn!(error = %error, tag, "Failed to invalidate the tiered cache L1 tag.");
+                        }
+                    }
+                    InvalidationTarget::All => l1.invalidate_all(),
+                }
+            }
+        });
+
+        Ok(())
+    }
+}
+
+pub async fn generate<V>(
+    namespace: &str,
+    memory_caches: &MemoryCacheRegistry,
+    distribute_cache: DistributeCacheGlobal,
+    l2_ttl: Option<Duration>,
+) -> Result<TieredCacheGlobal<V>>
+where
+    V: MemoryCacheValueBound + Serialize + DeserializeOwned,
+{
+    // Not self-describing, so the values would be written but never read back.
+    if distribute_cache.codec().codec == CacheCodec::Bincode && TypeId::of::<V>() == TypeId::of::<serde_json::Value>() {
+        anyhow::bail!("The tiered cache `{namespace}` of json values can't be read back with the bincode codec.");
+    }
+
+    let cache = TieredCache {
+        namespace: namespace.into(),
+        l1: memory_caches.namespace::<MemoryCacheKey, V>(&format!("tiered:{namespace}"))?,
+        l2: distribute_cache,
+        l2_ttl,
+    };
+
+    cache.listen().await?;
+
+    Ok(Arc::new(cache))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{generate, Invalidation, InvalidationTarget, TieredCacheGlobal, TIERED_CACHE_INVALIDATION_CHANNEL};
+    use crate::impls::codec::{CacheCodec, CacheCodecConfig};
+    use crate::impls::distribute::{self, DistributeCacheConfig, DistributeCacheGlobal};
+    use crate::impls::memory::registry::MemoryCacheRegistry;
+    use serde_json::{json, Value};
+    use std::time::Duration;
+
+    async fn tiered() -> (TieredCacheGlobal<Value>, DistributeCacheGlobal) {
+        let distribute_cache = distribute::generate(DistributeCacheConfig::Memory, Default::default()).await.unwrap();
+        let cache = generate("tests", &MemoryCacheRegistry::default(), distribute_cache.clone(), None).await.unwrap();
+
+        (cache, distribute_cache)
+    }
+
+    /// As if another instance changed the `target`.
+    async fn invalidate_remotely(distribute_cache: &DistributeCacheGlobal, target: InvalidationTarget) {
+        let invalidation = Invalidation { origin: "other".to_string(), namespace: "tests".to_string(), target };
+        let payload = serde_json::to_vec(&invalidation).unwrap();
+        distribute_cache.publish(TIERED_CACHE_INVALIDATION_CHANNEL, payload).await.unwrap();
+
+        // Handled in the background.
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+
+    #[ntex::test]
+    async fn reject_json_values_with_bincode() {
+        let codec = CacheCodecConfig { codec: CacheCodec::Bincode, ..Default::default() };
+        let distribute_cache = distribute::generate(DistributeCacheConfig::Memory, codec).await.unwrap();
+        let memory_caches = MemoryCacheRegistry::default();
+
+        assert!(generate::<Value>("tests", &memory_caches, distribute_cache.clone(), None).await.is_err());
+        assert!(generate::<u64>("tests:numbers", &memory_caches, distribute_cache, None).await.is_ok());
+    }
+
+    #[ntex::test]
+    async fn l1_hit() {
+        let (cache, distribute_cache) = tiered().await;
+        cache.insert("a", json!(1)).await.unwrap();
+
+        // Gone from L2, still in L1.
+        assert!(distribute_cache.del("tests:a").await.unwrap());
+        assert_eq!(cache.get("a").await.unwrap(), Some(json!(1)));
+    }
+
+    #[ntex::test]
+    async fn l2_fallback_backfills_l1() {
+        let (cache, distribute_cache) = tiered().await;
+        distribute_cache.set_as("tests:a", &json!(1), None).await.unwrap();
+        assert!(!cache.l1.contains_key("a"));
+
+        assert_eq!(cache.get("a").await.unwrap(), Some(json!(1)));
+        assert!(cache.l1.contains_key("a"));
+        assert_eq!(cache.get("b").await.unwrap(), None);
+    }
+
+    #[ntex::test]
+    async fn invalidated_by_other_instances() {
+        let (cache, distribute_cache) = tiered().await;
+        cache.insert("a", json!(1)).await.unwrap();
+        cache.insert_tagged("b", json!(2), &["user:42"]).await.unwrap();
+        cache.insert("c", json!(3)).await.unwrap();
+
+        invalidate_remotely(&distribute_cache, InvalidationTarget::Key("a".to_string())).await;
+        assert!(!cache.l1.contains_key("a"));
+        assert!(cache.l1.contains_key("b"));
+
+        invalidate_remotely(&distribute_cache, InvalidationTarget::Tag("user:42".to_string())).await;
+        assert_eq!(cache.l1.get("b").await, None);
+        assert!(cache.l1.contains_key("c"));
+
+        invalidate_remotely(&distribute_cache, InvalidationTarget::All).await;
+        assert_eq!(cache.l1.get("c").await, None);
+
+        // Only the L1 copies, the L2 ones are read again.
+        assert_eq!(cache.get("c").await.unwrap(), Some(json!(3)));
+    }
+}
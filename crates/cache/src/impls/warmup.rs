@@ -0,0 +1,293 @@
+/// Warm-up of the caches, before the server binds.
+/// Entries are declared in a manifest, e.g. `warmup.toml`:
+/// ```toml
+/// timeout = "3s"
+///
+/// [[entries]]
+/// key = "greeting"
+/// value = "Hello world!"
+///
+/// [[entries]]
+/// key = "users:top"
+/// store = "distribute"
+/// loader = "top_users"
+/// ttl = "10m"
+/// timeout = "10s"
+/// ```
+/// Values are either static or produced by the loaders registered in code.
+use crate::impls::distribute::DistributeCache;
+use crate::impls::memory::registry::MemoryCacheRegistry;
+use crate::impls::memory::{MemoryCacheKey, MemoryCacheValue, MEMORY_CACHE_DEFAULT_NAMESPACE};
+use serde::{Deserialize, Deserializer};
+use std::collections::HashMap;
+use std::future::Future;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::time::Duration;
+use web_core::prelude::*;
+
+const DEFAULT_LOADER_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Where to warm up, read from the env:
+/// - `CACHE_WARMUP_MANIFEST`, path of a `.toml` or `.json` manifest, nothing is warmed up without it.
+/// - `CACHE_WARMUP_TIMEOUT`, of every loader not configured in its entry, overrides the one of the manifest.
+///   Else the one of the manifest, `5s` by default.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct CacheWarmupConfig {
+    pub manifest: Option<PathBuf>,
+    pub timeout: Option<Duration>,
+}
+
+impl CacheWarmupConfig {
+    pub fn from_env() -> Result<Self> {
+        Ok(CacheWarmupConfig {
+            manifest: web_env::var("CACHE_WARMUP_MANIFEST")?.map(PathBuf::from),
+            timeout: web_env::var_parsed::<humantime::Duration>("CACHE_WARMUP_TIMEOUT")?.map(Duration::from),
+        })
+    }
+}
+
+#[derive(Deserialize, Clone, Debug, Default, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct CacheWarmupManifest {
+    /// Of every loader, overridden by the configured one.
+    #[serde(default, deserialize_with = "deserialize_duration")]
+    pub timeout: Option<Duration>,
+    #[serde(default)]
+    pub entries: Vec<CacheWarmupEntry>,
+}
+
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct CacheWarmupEntry {
+    pub key: String,
+    #[serde(default)]
+    pub store: CacheWarmupStore,
+    /// Of the memory cache, the default one if missing.
+    pub namespace: Option<String>,
+    #[serde(default, deserialize_with = "deserialize_duration")]
+    pub ttl: Option<Duration>,
+    /// Of the loader, overrides the one of the manifest.
+    #[serde(default, deserialize_with = "deserialize_duration")]
+    pub timeout: Option<Duration>,
+    #[serde(flatten)]
+    pub source: CacheWarmupSource,
+}
+
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum CacheWarmupStore {
+    #[default]
+    Memory,
+    Distribute,
+}
+
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum CacheWarmupSource {
+    Value(MemoryCacheValue),
+    /// Name of a registered loader.
+    Loader(String),
+}
+
+fn deserialize_duration<'de, D: Deserializer<'de>>(deserializer: D) -> std::result::Result<Option<Duration>, D::Error> {
+    Option::<String>::deserialize(deserializer)?
+        .map(|duration| humantime::parse_duration(&duration).map_err(serde::de::Error::custom))
+        .transpose()
+}
+
+impl CacheWarmupManifest {
+    /// The format is chosen by the extension, `.toml` or `.json`.
+    pub fn from_path(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let content = std::fs::read_to_string(path)
+            .map_err(|error| anyhow::anyhow!("Failed to read cache warm-up manifest `{}`: {error}", path.display()))?;
+
+        Ok(match path.extension().and_then(|extension| extension.to_str()) {
+            Some("toml") => toml::from_str(&content)?,
+            Some("json") => serde_json::from_str(&content)?,
+            _ => anyhow::bail!("Unknown format of cache warm-up manifest `{}`.", path.display()),
+        })
+    }
+}
+
+pub type CacheWarmupLoader =
+    Box<dyn Fn() -> Pin<Box<dyn Future<Output = Result<MemoryCacheValue>> + Send>> + Send + Sync>;
+
+/// Loaders referenced by name in the manifest.
+#[derive(Default)]
+pub struct CacheWarmupLoaders {
+    loaders: HashMap<String, CacheWarmupLoader>,
+}
+
+impl CacheWarmupLoaders {
+    pub fn register<F, Fut>(mut self, name: &str, loader: F) -> Self
+    where
+        F: Fn() -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<MemoryCacheValue>> + Send + 'static,
+    {
+        self.loaders.insert(name.to_string(), Box::new(move || Box::pin(loader())));
+        self
+    }
+}
+
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct CacheWarmupReport {
+    pub warmed: Vec<String>,
+    /// Keys and why they are skipped.
+    pub skipped: Vec<(String, String)>,
+}
+
+/// Populate the caches with the entries in order.
+/// Failed entries are skipped and logged, they never stop the others.
+/// The `timeout` of the loaders, e.g. configured in the env, wins over the one of the manifest.
+pub async fn warm_up(
+    manifest: &CacheWarmupManifest,
+    loaders: &CacheWarmupLoaders,
+    memory_caches: &MemoryCacheRegistry,
+    distribute_cache: &DistributeCache,
+    timeout: Option<Duration>,
+) -> CacheWarmupReport {
+    let mut report = CacheWarmupReport::default();
+    let default_timeout = timeout.or(manifest.timeout).unwrap_or(DEFAULT_LOADER_TIMEOUT);
+
+    for entry in &manifest.entries {
+        match warm_up_entry(entry, loaders, memory_caches, distribute_cache, default_timeout).await {
+            Ok(()) => report.warmed.push(entry.key.clone()),
+            Err(error) => {
+                warn!(key = %entry.key, store = ?entry.store, %error, "Cache warm-up skipped the key.");
+                report.skipped.push((entry.key.clone(), error.to_string()));
+            }
+        }
+    }
+
+    info!(warmed = report.warmed.len(), skipped = report.skipped.len(), "Caches warmed up.");
+
+    report
+}
+
+async fn warm_up_entry(
+    entry: &CacheWarmupEntry,
+    loaders: &CacheWarmupLoaders,
+    memory_caches: &MemoryCacheRegistry,
+    distribute_cache: &DistributeCache,
+    default_timeout: Duration,
+) -> Result<()> {
+    let value = match &entry.source {
+        CacheWarmupSource::Value(value) => value.clone(),
+        CacheWarmupSource::Loader(name) => {
+            let loader =
+                loaders.loaders.get(name).ok_or_else(|| anyhow::anyhow!("Loader `{name}` is not registered."))?;
+            let timeout = entry.timeout.unwrap_or(default_timeout);
+
+            tokio::time::timeout(timeout, loader())
+                .await
+                .map_err(|_| anyhow::anyhow!("Loader `{name}` timed out after {timeout:?}."))??
+        }
+    };
+
+    match entry.store {
+        CacheWarmupStore::Memory => {
+            let cache = memory_caches.namespace::<MemoryCacheKey, MemoryCacheValue>(
+                entry.namespace.as_deref().unwrap_or(MEMORY_CACHE_DEFAULT_NAMESPACE),
+            )?;
+            match entry.ttl {
+                Some(ttl) => cache.insert_with_ttl(entry.key.as_str().into(), value, ttl).await,
+                None => cache.insert(entry.key.as_str().into(), value).await,
+            }
+        }
+        CacheWarmupStore::Distribute => {
+            if entry.namespace.is_some() {
+                anyhow::bail!("Namespaces are only supported by the memory cache.");
+            }
+            distribute_cache.set_as(&entry.key, &value, entry.ttl).await?;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{warm_up, CacheWarmupLoaders, CacheWarmupManifest, CacheWarmupSource, CacheWarmupStore};
+    use crate::impls::distribute::{generate, DistributeCacheConfig};
+    use crate::impls::memory::registry::MemoryCacheRegistry;
+    use crate::impls::memory::{MemoryCacheKey, MemoryCacheValue};
+    use serde_json::json;
+    use std::time::Duration;
+
+    #[test]
+    fn parse_manifest() {
+        let manifest = toml::from_str::<CacheWarmupManifest>(
+            r#"
+            timeout = "3s"
+
+            [[entries]]
+            key = "greeting"
+            value = { text = "Hello" }
+
+            [[entries]]
+            key = "users:top"
+            store = "distribute"
+            loader = "top_users"
+            ttl = "10m"
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(manifest.timeout, Some(Duration::from_secs(3)));
+        assert_eq!(manifest.entries[0].source, CacheWarmupSource::Value(json!({ "text": "Hello" })));
+        assert_eq!(manifest.entries[1].store, CacheWarmupStore::Distribute);
+        assert_eq!(manifest.entries[1].source, CacheWarmupSource::Loader("top_users".into()));
+        assert_eq!(manifest.entries[1].ttl, Some(Duration::from_secs(600)));
+    }
+
+    #[ntex::test]
+    async fn skip_failed_entries() {
+        let distribute_cache = generate(DistributeCacheConfig::Memory, Default::default()).await.unwrap();
+        let manifest = serde_json::from_value::<CacheWarmupManifest>(json!({
+            "entries": [
+                { "key": "static", "namespace": "tests:warmup", "value": 1 },
+                { "key": "loaded", "store": "distribute", "loader": "answer" },
+                { "key": "missing", "loader": "unknown" },
+                { "key": "slow", "loader": "slow", "timeout": "20ms" },
+            ],
+        }))
+        .unwrap();
+        let loaders =
+            CacheWarmupLoaders::default().register("answer", || async { Ok(json!(42)) }).register("slow", || async {
+                tokio::time::sleep(Duration::from_secs(1)).await;
+                Ok(json!(0))
+            });
+
+        let memory_caches = MemoryCacheRegistry::default();
+        let report = warm_up(&manifest, &loaders, &memory_caches, &distribute_cache, None).await;
+        assert_eq!(report.warmed, ["static", "loaded"]);
+        assert_eq!(report.skipped.iter().map(|(key, _)| key.as_str()).collect::<Vec<_>>(), ["missing", "slow"]);
+
+        let memory_cache = memory_caches.namespace::<MemoryCacheKey, MemoryCacheValue>("tests:warmup").unwrap();
+        assert_eq!(memory_cache.get("static").await, Some(json!(1)));
+        assert_eq!(distribute_cache.get_as::<MemoryCacheValue>("loaded").await.unwrap(), Some(json!(42)));
+    }
+
+    #[ntex::test]
+    async fn configured_timeout_wins() {
+        let distribute_cache = generate(DistributeCacheConfig::Memory, Default::default()).await.unwrap();
+        let manifest = serde_json::from_value::<CacheWarmupManifest>(json!({
+            "timeout": "1s",
+            "entries": [{ "key": "slow", "namespace": "tests:warmup_timeout", "loader": "slow" }],
+        }))
+        .unwrap();
+        let loaders = CacheWarmupLoaders::default().register("slow", || async {
+            tokio::time::sleep(Duration::from_millis(200)).await;
+            Ok(json!(0))
+        });
+
+        let memory_caches = MemoryCacheRegistry::default();
+        let report = warm_up(&manifest, &loaders, &memory_caches, &distribute_cache, None).await;
+        assert_eq!(report.warmed, ["slow"]);
+        let report =
+            warm_up(&manifest, &loaders, &memory_caches, &distribute_cache, Some(Duration::from_millis(20))).await;
+        assert_eq!(report.skipped.iter().map(|(key, _)| key.as_str()).collect::<Vec<_>>(), ["slow"]);
+    }
+}
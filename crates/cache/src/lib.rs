@@ -4,30 +4,132 @@ extern crate tracing;
 mod error;
 mod impls;
 
-use once_cell::sync::Lazy;
 use web_core::prelude::*;
 
 pub mod prelude {
+    pub use crate::error::{CodecError, SharedLoadError};
+    pub use crate::impls::codec::{CacheCodec, CacheCodecConfig, CacheCompression};
+    pub use crate::impls::distribute::memory::InMemoryDistributeCache;
     pub use crate::impls::distribute::prelude::*;
+    pub use crate::impls::distribute::redis::{RedisDistributeCache, RedisDistributeCacheConfig};
     pub use crate::impls::distribute::{
         DistributeCache, DistributeCacheConfig, DistributeCacheExtension, DistributeCacheGlobal, DistributeCacheKey,
+        DistributeCacheMessage,
     };
 
+    pub use crate::impls::event_bus::{
+        EventBus, EventBusConfig, EventBusExt, EventBusExtension, EventBusGlobal, EventBusMessage, EventChannel,
+        EventSubscription,
+    };
+
+    pub use crate::impls::job_queue::memory::InMemoryJobQueue;
+    pub use crate::impls::job_queue::redis::RedisJobQueue;
+    pub use crate::impls::job_queue::{
+        Job, JobDeadLetter, JobDelivery, JobHandler, JobHandlers, JobQueue, JobQueueBackend, JobQueueBackendConfig,
+        JobQueueConfig, JobQueueGlobal, JobWorkers,
+    };
+
+    pub use crate::error::MemoryCacheError;
     pub use crate::impls::memory::prelude::*;
+    pub use crate::impls::memory::registry::{MemoryCacheRegistry, MemoryCacheRegistryGlobal};
+    pub use crate::impls::memory::snapshot::MemoryCacheSnapshotConfig;
     pub use crate::impls::memory::{
-        MemoryCache, MemoryCacheExtension, MemoryCacheGlobal, MemoryCacheKey, MemoryCacheValue,
+        MemoryCache, MemoryCacheDefault, MemoryCacheEvictionHook, MemoryCacheExtension, MemoryCacheGlobal,
+        MemoryCacheKey, MemoryCacheKeyBound, MemoryCacheName, MemoryCacheNamespace, MemoryCachePolicy,
+        MemoryCacheRegistryExtension, MemoryCacheSummary, MemoryCacheValue, MemoryCacheValueBound, MemoryCacheWeigh,
+        MEMORY_CACHE_DEFAULT_NAMESPACE,
     };
+    pub use moka::notification::RemovalCause;
+
+    pub use crate::impls::stats::CacheStatsSnapshot;
     pub use crate::memory_cache_make_sure;
+
+    pub use crate::impls::tiered::{TieredCache, TieredCacheGlobal, TIERED_CACHE_INVALIDATION_CHANNEL};
+
+    pub use crate::impls::warmup::{
+        CacheWarmupConfig, CacheWarmupEntry, CacheWarmupLoader, CacheWarmupLoaders, CacheWarmupManifest,
+        CacheWarmupReport, CacheWarmupSource, CacheWarmupStore,
+    };
 }
 
 /// Distribute cache can only be accessed in `app_state`.
 pub async fn generate_distribute_cache(
     config: crate::impls::distribute::DistributeCacheConfig,
+    codec: crate::impls::codec::CacheCodecConfig,
 ) -> Result<crate::impls::distribute::DistributeCacheGlobal> {
     debug!("Connecting to the distribute cache.");
 
-    impls::distribute::generate(config).await
+    impls::distribute::generate(config, codec).await
+}
+
+/// Event bus shares the connections of the distribute cache, unless it's in-process.
+pub async fn generate_event_bus(
+    config: crate::impls::event_bus::EventBusConfig,
+    distribute_cache: crate::impls::distribute::DistributeCacheGlobal,
+) -> Result<crate::impls::event_bus::EventBusGlobal> {
+    debug!(?config, "Generating the event bus.");
+
+    impls::event_bus::generate(config, distribute_cache).await
 }
 
-/// Memory cache should be a global state.
-pub static MEMORY_CACHE: Lazy<crate::impls::memory::MemoryCacheGlobal> = Lazy::new(impls::memory::generate);
+/// Job queue has its own connections, the workers are started by `JobQueue::start`.
+pub async fn generate_job_queue(
+    config: crate::impls::job_queue::JobQueueConfig,
+) -> Result<crate::impls::job_queue::JobQueueGlobal> {
+    debug!(backend = ?config.backend, "Connecting to the job queue.");
+
+    impls::job_queue::generate(config).await
+}
+
+/// Memory caches can only be accessed by name in `app_state`.
+pub fn generate_memory_cache_registry(
+    default_policy: crate::impls::memory::MemoryCachePolicy,
+) -> crate::impls::memory::registry::MemoryCacheRegistryGlobal {
+    debug!(?default_policy, "Generating the memory cache registry.");
+
+    std::sync::Arc::new(impls::memory::registry::MemoryCacheRegistry::new(default_policy))
+}
+
+/// Tiered cache shares the connections of the distribute cache,
+/// its L1 copies are kept in the memory cache registry.
+pub async fn generate_tiered_cache<V>(
+    namespace: &str,
+    memory_caches: &crate::impls::memory::registry::MemoryCacheRegistry,
+    distribute_cache: crate::impls::distribute::DistributeCacheGlobal,
+    l2_ttl: Option<std::time::Duration>,
+) -> Result<crate::impls::tiered::TieredCacheGlobal<V>>
+where
+    V: crate::impls::memory::MemoryCacheValueBound + serde::Serialize + serde::de::DeserializeOwned,
+{
+    debug!(namespace, "Generating the tiered cache.");
+
+    impls::tiered::generate(namespace, memory_caches, distribute_cache, l2_ttl).await
+}
+
+/// Warm up the caches with the manifest, the skipped keys are logged.
+pub async fn warm_up_caches(
+    manifest: &crate::impls::warmup::CacheWarmupManifest,
+    loaders: &crate::impls::warmup::CacheWarmupLoaders,
+    memory_caches: &crate::impls::memory::registry::MemoryCacheRegistry,
+    distribute_cache: &crate::impls::distribute::DistributeCache,
+    timeout: Option<std::time::Duration>,
+) -> crate::impls::warmup::CacheWarmupReport {
+    debug!(entries = manifest.entries.len(), "Warming up the caches.");
+
+    impls::warmup::warm_up(manifest, loaders, memory_caches, distribute_cache, timeout).await
+}
+
+/// Write the snapshot of the memory cache every `interval`, until it's dropped.
+pub fn spawn_memory_cache_snapshots<K, V>(
+    cache: &crate::impls::memory::MemoryCacheGlobal<K, V>,
+    path: std::path::PathBuf,
+    interval: std::time::Duration,
+) -> tokio::task::JoinHandle<()>
+where
+    K: crate::impls::memory::MemoryCacheKeyBound + serde::Serialize + serde::de::DeserializeOwned,
+    V: crate::impls::memory::MemoryCacheValueBound + serde::Serialize + serde::de::DeserializeOwned,
+{
+    debug!(path = %path.display(), ?interval, "Spawning the memory cache snapshots.");
+
+    impls::memory::snapshot::spawn(cache, path, interval)
+}
@@ -25,3 +25,65 @@ impl From<rslock::LockError> for BoxedAppError {
         Box::new(AsyncOpGuardError { error })
     }
 }
+
+#[derive(Debug)]
+struct AsyncOpGuardAcquireError {
+    error: web_guard::async_op::AcquireError,
+}
+
+impl AppError for AsyncOpGuardAcquireError {
+    fn response(&self) -> ntex::http::Response {
+        use web_guard::async_op::AcquireError;
+
+        match &self.error {
+            // Held by others, the request may be retried later.
+            AcquireError::TimedOut(_) => {
+                warn!(error = %self.error, "AsyncOpGuard Acquire Timed Out");
+
+                crate::server_response_failed!(message: self.error.to_string(), status_code: 409).into()
+            }
+            // Given up, usually on shutdown.
+            AcquireError::Cancelled => {
+                warn!(error = %self.error, "AsyncOpGuard Acquire Cancelled");
+
+                crate::server_response_failed!(message: self.error.to_string(), status_code: 503).into()
+            }
+            AcquireError::Lock(error) => {
+                error!(error = %format!("{:?}", error), "AsyncOpGuard Error");
+
+                server_error_response(format!("{:?}", error).into())
+            }
+        }
+    }
+}
+
+impl std::fmt::Display for AsyncOpGuardAcquireError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "AsyncOpGuard Error: {}", self.error)
+    }
+}
+
+impl From<web_guard::async_op::AcquireError> for BoxedAppError {
+    fn from(error: web_guard::async_op::AcquireError) -> Self {
+        Box::new(AsyncOpGuardAcquireError { error })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+    use web_guard::async_op::AcquireError;
+
+    #[test]
+    fn acquire_error_status_codes() {
+        let timed_out: BoxedAppError = AcquireError::TimedOut(Duration::from_secs(1)).into();
+        assert_eq!(timed_out.response().status(), ntex::http::StatusCode::CONFLICT);
+
+        let cancelled: BoxedAppError = AcquireError::Cancelled.into();
+        assert_eq!(cancelled.response().status(), ntex::http::StatusCode::SERVICE_UNAVAILABLE);
+
+        let lock: BoxedAppError = AcquireError::Lock(rslock::LockError::Unavailable).into();
+        assert_eq!(lock.response().status(), ntex::http::StatusCode::INTERNAL_SERVER_ERROR);
+    }
+}
@@ -0,0 +1,97 @@
+/// Runtime of the `#[guarded]` handlers, see `web_proc_macros::guarded`.
+use crate::app_error_impl;
+use crate::error::{AppError, BoxedAppError};
+use ntex::{
+    http::{Payload, RequestHead},
+    util::Extensions,
+    web::{FromRequest, HttpRequest, WebRequest, WebResponseError},
+};
+use std::{future::Future, ops::Deref, sync::Arc, time::Duration};
+use web_guard::async_op::{AcquireOptions, AsyncOpGuard, AsyncOpGuardGlobal};
+
+#[derive(thiserror::Error, Debug)]
+pub enum GuardedError {
+    #[error("Async op guard missing.")]
+    AsyncOpGuardMissing,
+}
+
+app_error_impl!(GuardedError);
+
+pub struct AsyncOpGuardExtension(AsyncOpGuardGlobal);
+
+impl Deref for AsyncOpGuardExtension {
+    type Target = AsyncOpGuardGlobal;
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl AsyncOpGuardExtension {
+    #[inline]
+    pub fn set_into_req(extensions: &mut Extensions, global: AsyncOpGuardGlobal) {
+        if !extensions.contains::<AsyncOpGuardExtension>() {
+            extensions.insert(AsyncOpGuardExtension(global))
+        }
+    }
+
+    #[inline]
+    fn get_from_req(extensions: &mut Extensions) -> Result<AsyncOpGuardExtension, GuardedError> {
+        extensions
+            .get::<AsyncOpGuardExtension>()
+            .ok_or(GuardedError::AsyncOpGuardMissing)
+            .map(|ext| AsyncOpGuardExtension(Arc::clone(&ext.0)))
+    }
+}
+
+impl<Err> FromRequest<Err> for AsyncOpGuardExtension {
+    type Error = GuardedError;
+
+    #[inline]
+    async fn from_request(req: &HttpRequest, _payload: &mut Payload) -> Result<Self, Self::Error> {
+        AsyncOpGuardExtension::get_from_req(&mut req.extensions_mut())
+    }
+}
+
+pub trait AsyncOpGuardExt {
+    fn async_op_guard(&self) -> Result<AsyncOpGuardExtension, GuardedError>;
+}
+
+macro_rules! impl_ext {
+    ($ident: ident) => {
+        impl AsyncOpGuardExt for $ident {
+            #[inline]
+            fn async_op_guard(&self) -> Result<AsyncOpGuardExtension, GuardedError> {
+                AsyncOpGuardExtension::get_from_req(&mut self.extensions_mut())
+            }
+        }
+    };
+
+    ($ident: ident<$($genetic: tt),+>) => {
+        impl<$($genetic)+> AsyncOpGuardExt for $ident<$($genetic)+> {
+            #[inline]
+            fn async_op_guard(&self) -> Result<AsyncOpGuardExtension, GuardedError> {
+                AsyncOpGuardExtension::get_from_req(&mut self.extensions_mut())
+            }
+        }
+    }
+}
+
+impl_ext!(HttpRequest);
+impl_ext!(WebRequest<Err>);
+impl_ext!(RequestHead);
+
+/// Run the `task` holding the lock on the `resource`, waiting for it at most the `wait`.
+/// The `ttl` must cover the whole task, the lock is not extended.
+pub async fn guarded<F: Future>(
+    async_op_guard: &AsyncOpGuard,
+    resource: &[u8],
+    ttl: usize,
+    wait: Duration,
+    task: F,
+) -> Result<F::Output, BoxedAppError> {
+    let lock = async_op_guard.acquire_within(resource, ttl, &AcquireOptions::new(wait)).await?;
+    let output = task.await;
+    lock.release().await;
+
+    Ok(output)
+}
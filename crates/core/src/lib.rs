@@ -10,6 +10,7 @@ mod macros;
 pub mod constants;
 pub mod error;
 pub mod features;
+pub mod guarded;
 pub mod response;
 pub mod utils;
 pub mod view_template;
@@ -31,7 +32,7 @@ pub mod route_prelude {
     pub use crate::features::{HttpRequestExt, RequestUtils, UriUtils};
     pub use crate::prelude::*;
 
-    pub use ntex::web::{get, guard, post, resource, route, scope, to, Route, ServiceConfig};
+    pub use ntex::web::{delete, get, guard, post, resource, route, scope, to, Route, ServiceConfig};
 }
 
 pub mod middleware_prelude {
@@ -63,7 +64,7 @@ pub mod handler_prelude {
 
     pub use sailfish::TemplateOnce;
     pub use serde::{Deserialize, Serialize};
-    pub use web_proc_macros::web_view_template;
+    pub use web_proc_macros::{guarded, web_view_template};
 
     pub use ntex::http::header::HeaderValue;
     pub use ntex::http::StatusCode;
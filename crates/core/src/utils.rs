@@ -39,6 +39,12 @@ pub fn query_to_string(input: QueryMap) -> Result<String> {
     Ok(query_string)
 }
 
+/// Unix time in milliseconds, `0` if the clock is before the epoch.
+#[inline]
+pub fn unix_millis() -> u64 {
+    std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).map_or(0, |elapsed| elapsed.as_millis() as u64)
+}
+
 pub fn parse_into_status_code<S>(status_code: S) -> Option<ntex::http::StatusCode>
 where
     S: TryInto<ntex::http::StatusCode>,
@@ -1,24 +1,626 @@
 /// Async op guard - Redis Lock.
 /// Make sure that only one process here on concurrent env now.
-use rslock::{LockError, LockManager};
+/// Locks are taken with Redlock on independent redis nodes, held once the quorum of them is reached.
+/// A single in-process node stands in for them on a single instance and in tests.
+use futures::future::join_all;
+use monitor::{AsyncOpGuardMonitor, AsyncOpGuardStatsSnapshot, HeldAsyncOpLock};
+use rslock::LockError;
 use std::future::Future;
-use std::ops::Deref;
 use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tracing::{field, Instrument};
+
+pub use tokio_util::sync::CancellationToken;
+
+pub mod memory;
+pub mod monitor;
+pub mod redis;
 
 pub type AsyncOpGuardGlobal = Arc<AsyncOpGuard>;
-pub type AsyncOpGuardConfig = &'static str;
+
+/// Of a single node, the sentinel and cluster ones are replicated, so they can't hold a lock alone.
+const LOCK_REDIS_SCHEMES: [&str; 4] = ["redis://", "rediss://", "redis+unix://", "unix://"];
+const DEFAULT_RETRY_COUNT: u32 = 3;
+const DEFAULT_RETRY_DELAY: Duration = Duration::from_millis(200);
+const DEFAULT_CLOCK_DRIFT_FACTOR: f64 = 0.01;
+/// Between the attempts of a bounded acquisition, so a zero backoff won't busy-loop the nodes.
+const MIN_ACQUIRE_DELAY: Duration = Duration::from_millis(10);
+
+/// A single lock node, where a resource is held with a token until its ttl expires.
+#[async_trait::async_trait]
+pub trait AsyncOpLockBackend: Send + Sync {
+    /// The token of the new lock, `None` if still held by others, an error if the node failed.
+    async fn lock(&self, resource: &[u8], ttl: usize) -> Result<Option<Vec<u8>>, LockError>;
+
+    /// Reset the ttl, only if still held with the `token`.
+    async fn extend(&self, resource: &[u8], token: &[u8], ttl: usize) -> bool;
+
+    /// Only if still held with the `token`.
+    async fn unlock(&self, resource: &[u8], token: &[u8]);
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum AsyncOpGuardBackendConfig {
+    /// The Redlock nodes.
+    Redis(Vec<String>),
+    /// For a single instance and tests without a Redis server.
+    Memory,
+}
+
+/// Lock nodes and retries, read from the env:
+/// - `LOCK_BACKEND`, `redis` (default) or `memory`.
+/// - `LOCK_REDIS_URIS`, e.g. `redis://10.0.0.1:6379,redis://10.0.0.2:6379,redis://10.0.0.3:6379`,
+///   independent nodes, not replicas of each other. Without it, `REDIS_URI` is the only node,
+///   required to be a single node one, e.g. not a `redis-sentinel://` or `redis-cluster://` one.
+/// - `LOCK_RETRY_COUNT`, `3` by default, attempts of taking a lock.
+/// - `LOCK_RETRY_DELAY`, `200ms` by default, the max random delay between the attempts.
+/// - `LOCK_CLOCK_DRIFT_FACTOR`, `0.01` by default, of the ttl, taken off the validity of the locks.
+#[derive(Clone, Debug, PartialEq)]
+pub struct AsyncOpGuardConfig {
+    pub backend: AsyncOpGuardBackendConfig,
+    pub retry_count: u32,
+    pub retry_delay: Duration,
+    pub clock_drift_factor: f64,
+}
+
+impl AsyncOpGuardConfig {
+    /// With the default retries and clock drift factor.
+    pub fn new(backend: AsyncOpGuardBackendConfig) -> Self {
+        AsyncOpGuardConfig {
+            backend,
+            retry_count: DEFAULT_RETRY_COUNT,
+            retry_delay: DEFAULT_RETRY_DELAY,
+            clock_drift_factor: DEFAULT_CLOCK_DRIFT_FACTOR,
+        }
+    }
+
+    pub fn from_env() -> anyhow::Result<Self> {
+        let backend = match web_env::var("LOCK_BACKEND")?.as_deref() {
+            None | Some("redis") => AsyncOpGuardBackendConfig::Redis(redis_uris_from_env()?),
+            Some("memory") => AsyncOpGuardBackendConfig::Memory,
+            Some(backend) => anyhow::bail!("Unknown LOCK_BACKEND `{backend}`."),
+        };
+
+        let retry_count = web_env::var_parsed::<u32>("LOCK_RETRY_COUNT")?.unwrap_or(DEFAULT_RETRY_COUNT);
+        if retry_count == 0 {
+            anyhow::bail!("LOCK_RETRY_COUNT must be greater than 0.");
+        }
+
+        let clock_drift_factor =
+            web_env::var_parsed::<f64>("LOCK_CLOCK_DRIFT_FACTOR")?.unwrap_or(DEFAULT_CLOCK_DRIFT_FACTOR);
+        if !(0.0..1.0).contains(&clock_drift_factor) {
+            anyhow::bail!("LOCK_CLOCK_DRIFT_FACTOR must be within [0, 1).");
+        }
+
+        Ok(AsyncOpGuardConfig {
+            backend,
+            retry_count,
+            retry_delay: web_env::var_parsed::<humantime::Duration>("LOCK_RETRY_DELAY")?
+                .map_or(DEFAULT_RETRY_DELAY, Duration::from),
+            clock_drift_factor,
+        })
+    }
+}
+
+#[inline]
+fn is_lock_redis_uri(uri: &str) -> bool {
+    LOCK_REDIS_SCHEMES.iter().any(|scheme| uri.starts_with(scheme))
+}
+
+/// Never falls back to a local node, the locks would silently be taken apart from the other instances.
+fn redis_uris_from_env() -> anyhow::Result<Vec<String>> {
+    let uris = web_env::list("LOCK_REDIS_URIS")?;
+    if uris.is_empty() {
+        // The cache may be built from `REDIS_TOPOLOGY` and `REDIS_HOSTS` instead.
+        return match web_env::var("REDIS_URI")? {
+            Some(uri) if is_lock_redis_uri(&uri) => Ok(vec![uri]),
+            Some(_) => anyhow::bail!("LOCK_REDIS_URIS is required, REDIS_URI is not a single redis node."),
+            None => anyhow::bail!("LOCK_REDIS_URIS is required without REDIS_URI."),
+        };
+    }
+
+    for uri in &uris {
+        // Opening an unsupported one panics.
+        if !is_lock_redis_uri(uri) {
+            anyhow::bail!("Unsupported lock redis uri `{uri}`.");
+        }
+    }
+
+    Ok(uris)
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum AcquireError {
+    #[error("Lock still held by others after waiting {0:?}.")]
+    TimedOut(Duration),
+    #[error("Lock acquisition cancelled.")]
+    Cancelled,
+    #[error("Lock error: {0:?}.")]
+    Lock(LockError),
+}
+
+impl From<LockError> for AcquireError {
+    fn from(error: LockError) -> Self {
+        AcquireError::Lock(error)
+    }
+}
+
+/// Delays between the attempts of a bounded acquisition.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AcquireBackoff {
+    Fixed(Duration),
+    /// Doubled after each attempt, up to `max`.
+    Exponential {
+        initial: Duration,
+        max: Duration,
+    },
+    /// The exponential delay randomized within `[0, delay]`, so the competing processes spread out.
+    Jittered {
+        initial: Duration,
+        max: Duration,
+    },
+}
+
+impl Default for AcquireBackoff {
+    fn default() -> Self {
+        AcquireBackoff::Jittered { initial: Duration::from_millis(50), max: Duration::from_secs(1) }
+    }
+}
+
+impl AcquireBackoff {
+    /// After the failed `attempt`, starts from `1`. Never shorter than `10ms`.
+    pub fn delay(&self, attempt: u32) -> Duration {
+        let exponential = |initial: Duration, max: Duration| {
+            initial.saturating_mul(2u32.saturating_pow(attempt.saturating_sub(1))).min(max)
+        };
+
+        let delay = match *self {
+            AcquireBackoff::Fixed(delay) => delay,
+            AcquireBackoff::Exponential { initial, max } => exponential(initial, max),
+            AcquireBackoff::Jittered { initial, max } => random_below(exponential(initial, max)),
+        };
+
+        delay.max(MIN_ACQUIRE_DELAY)
+    }
+}
+
+/// How long and how to wait for a lock held by others.
+#[derive(Clone, Debug)]
+pub struct AcquireOptions {
+    max_wait: Duration,
+    backoff: AcquireBackoff,
+    cancel: Option<CancellationToken>,
+}
+
+impl AcquireOptions {
+    /// With the default jittered backoff, never cancelled.
+    pub fn new(max_wait: Duration) -> Self {
+        AcquireOptions { max_wait, backoff: Default::default(), cancel: None }
+    }
+
+    pub fn set_backoff(mut self, backoff: AcquireBackoff) -> Self {
+        self.backoff = backoff;
+        self
+    }
+
+    /// Stop waiting once the `cancel` is cancelled, e.g. on shutdown.
+    pub fn set_cancel(mut self, cancel: CancellationToken) -> Self {
+        self.cancel = Some(cancel);
+        self
+    }
+
+    /// Pending forever without a token.
+    async fn cancelled(&self) {
+        match &self.cancel {
+            Some(cancel) => cancel.cancelled().await,
+            None => std::future::pending().await,
+        }
+    }
+}
+
+/// Random enough to spread the retries, not for anything else.
+fn random_below(max: Duration) -> Duration {
+    let max = max.as_millis() as u64;
+    if max == 0 {
+        return Duration::ZERO;
+    }
+
+    let seed = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |elapsed| elapsed.subsec_nanos() as u64);
+    Duration::from_millis(seed % max)
+}
+
+/// The ttl minus the clock drift and the time spent, `None` if nothing is left.
+pub(crate) fn validity(ttl: usize, clock_drift_factor: f64, started_at: Instant) -> Option<Duration> {
+    let drift = Duration::from_millis((ttl as f64 * clock_drift_factor) as u64 + 2);
+
+    Duration::from_millis(ttl as u64).checked_sub(drift + started_at.elapsed()).filter(|validity| !validity.is_zero())
+}
+
+/// Up to `retry_count` attempts, a random delay up to `retry_delay` in between,
+/// so the competing processes won't retry at the same time.
+/// The error of the last attempt is returned, `Unavailable` if it was held by others.
+pub(crate) async fn retry<T, Fut>(
+    retry_count: u32,
+    retry_delay: Duration,
+    mut attempt: impl FnMut() -> Fut,
+) -> Result<T, LockError>
+where
+    Fut: Future<Output = Result<Option<T>, LockError>>,
+{
+    let mut last_error = LockError::Unavailable;
+    for count in 1..=retry_count {
+        match attempt().await {
+            Ok(Some(taken)) => return Ok(taken),
+            Ok(None) => last_error = LockError::Unavailable,
+            Err(error) => last_error = error,
+        }
+
+        if count < retry_count {
+            tokio::time::sleep(random_below(retry_delay)).await;
+        }
+    }
+
+    Err(last_error)
+}
+
+/// Attempts until the `options` give up, at least one.
+/// An attempt in progress is never interrupted, the deadline and cancellation are checked between them.
+/// Timed out only if the last attempt found it held by others, else its error is returned.
+pub(crate) async fn retry_within<T, Fut>(
+    options: &AcquireOptions,
+    mut attempt: impl FnMut() -> Fut,
+) -> Result<T, AcquireError>
+where
+    Fut: Future<Output = Result<Option<T>, LockError>>,
+{
+    let deadline = Instant::now() + options.max_wait;
+    let mut count = 0;
+
+    loop {
+        if options.cancel.as_ref().is_some_and(CancellationToken::is_cancelled) {
+            return Err(AcquireError::Cancelled);
+        }
+
+        let error = match attempt().await {
+            Ok(Some(taken)) => return Ok(taken),
+            Ok(None) => None,
+            Err(error) => Some(error),
+        };
+
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            return Err(error.map_or(AcquireError::TimedOut(options.max_wait), AcquireError::Lock));
+        }
+
+        count += 1;
+        tokio::select! {
+            _ = tokio::time::sleep(options.backoff.delay(count).min(remaining)) => {}
+            _ = options.cancelled() => return Err(AcquireError::Cancelled),
+        }
+    }
+}
+
+/// The token of a node holding a lock.
+#[derive(Clone)]
+struct NodeLock {
+    node: Arc<dyn AsyncOpLockBackend>,
+    token: Vec<u8>,
+}
+
+/// A lock held on the quorum of the nodes.
+/// Released with `release`, or in the background once dropped, so a panicking or dropped task won't leak it.
+pub struct AsyncOpLock {
+    pub resource: Vec<u8>,
+    /// How long the lock is still valid after being taken or extended.
+    pub validity: Duration,
+    /// Nodes holding the lock.
+    pub acquired: usize,
+    /// Nodes required.
+    pub quorum: usize,
+    taken_at: Instant,
+    locks: Vec<NodeLock>,
+    released: bool,
+    /// Failed to be extended.
+    lost: bool,
+    id: u64,
+    monitor: Arc<AsyncOpGuardMonitor>,
+}
+
+/// Best effort, every node is asked.
+async fn release_all(resource: &[u8], locks: &[NodeLock]) {
+    join_all(locks.iter().map(|lock| lock.node.unlock(resource, &lock.token))).await;
+}
+
+impl AsyncOpLock {
+    /// How long the lock is still valid from now.
+    #[inline]
+    pub fn remaining(&self) -> Duration {
+        self.validity.saturating_sub(self.taken_at.elapsed())
+    }
+
+    /// Best effort, every node is asked.
+    pub async fn release(mut self) {
+        self.released = true;
+
+        let span = debug_span!(
+            "async_op_guard.release",
+            resource = %String::from_utf8_lossy(&self.resource),
+            held_ms = field::Empty,
+        );
+        if let Some(held) = self.record_released() {
+            span.record("held_ms", held.as_millis() as u64);
+        }
+
+        release_all(&self.resource, &self.locks).instrument(span).await;
+    }
+
+    #[inline]
+    fn record_released(&self) -> Option<Duration> {
+        self.monitor.record_released(self.id, self.lost || self.remaining().is_zero())
+    }
+}
+
+impl Drop for AsyncOpLock {
+    fn drop(&mut self) {
+        if self.released {
+            return;
+        }
+
+        self.record_released();
+
+        let Ok(runtime) = tokio::runtime::Handle::try_current() else {
+            warn!(resource = %String::from_utf8_lossy(&self.resource), "Async op lock dropped outside of a runtime, held until it expires.");
+            return;
+        };
+
+        debug!(resource = %String::from_utf8_lossy(&self.resource), "Async op lock dropped, released in the background.");
+
+        // The unlock outlives the guard, so it owns what it needs.
+        let resource = std::mem::take(&mut self.resource);
+        let locks = std::mem::take(&mut self.locks);
+        runtime.spawn(async move { release_all(&resource, &locks).await });
+    }
+}
+
+/// What to do with a watched task once its lock can't be extended.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LockLostPolicy {
+    /// Drop the task at once.
+    Cancel,
+    /// Run the task to the end, the output is flagged as `Unguarded`.
+    Continue,
+}
+
+/// Output of `spawn_watched`.
+#[derive(Debug, PartialEq, Eq)]
+pub enum WatchedOutput<T> {
+    /// The lock was held all along.
+    Exclusive(T),
+    /// The lock was lost while running, another process may have entered too.
+    Unguarded(T),
+    /// Cancelled once the lock was lost.
+    Cancelled,
+}
+
+impl<T> WatchedOutput<T> {
+    #[inline]
+    pub fn is_exclusive(&self) -> bool {
+        matches!(self, WatchedOutput::Exclusive(_))
+    }
+
+    /// The output, whether the lock was held or not.
+    #[inline]
+    pub fn into_output(self) -> Option<T> {
+        match self {
+            WatchedOutput::Exclusive(output) | WatchedOutput::Unguarded(output) => Some(output),
+            WatchedOutput::Cancelled => None,
+        }
+    }
+}
 
 pub struct AsyncOpGuard {
-    inner: LockManager,
+    /// Retried by the guard itself.
+    nodes: Vec<Arc<dyn AsyncOpLockBackend>>,
+    quorum: usize,
+    retry_count: u32,
+    retry_delay: Duration,
+    clock_drift_factor: f64,
+    monitor: Arc<AsyncOpGuardMonitor>,
 }
 
 impl AsyncOpGuard {
     fn new(config: AsyncOpGuardConfig) -> Self {
-        AsyncOpGuard { inner: LockManager::new(vec![config]) }
+        let nodes = match &config.backend {
+            AsyncOpGuardBackendConfig::Redis(uris) => uris
+                .iter()
+                .map(|uri| Arc::new(redis::RedisLockNode::new(uri)) as Arc<dyn AsyncOpLockBackend>)
+                .collect::<Vec<_>>(),
+            AsyncOpGuardBackendConfig::Memory => vec![Arc::new(memory::InMemoryLockNode::default()) as _],
+        };
+
+        AsyncOpGuard::with_nodes(nodes, &config)
+    }
+
+    /// The `backend` of the `config` is ignored.
+    fn with_nodes(nodes: Vec<Arc<dyn AsyncOpLockBackend>>, config: &AsyncOpGuardConfig) -> Self {
+        AsyncOpGuard {
+            quorum: nodes.len() / 2 + 1,
+            nodes,
+            retry_count: config.retry_count,
+            retry_delay: config.retry_delay,
+            clock_drift_factor: config.clock_drift_factor,
+            monitor: Default::default(),
+        }
+    }
+
+    /// Counters of the acquisitions and releases, since started.
+    #[inline]
+    pub fn stats(&self) -> AsyncOpGuardStatsSnapshot {
+        self.monitor.snapshot()
+    }
+
+    /// Locks held by this instance now, ordered by the resource.
+    #[inline]
+    pub fn held(&self) -> Vec<HeldAsyncOpLock> {
+        self.monitor.held()
+    }
+
+    /// How many nodes must hold a lock.
+    #[inline]
+    pub fn quorum(&self) -> usize {
+        self.quorum
+    }
+
+    /// A single attempt on every node, released if the quorum is missed.
+    /// An error only if the failed nodes missed it, not the ones held by others.
+    async fn lock_once(&self, resource: &[u8], ttl: usize) -> Result<Option<AsyncOpLock>, LockError> {
+        let started_at = Instant::now();
+        let mut locks = Vec::with_capacity(self.nodes.len());
+        let mut errors = Vec::new();
+        for (node, locked) in
+            self.nodes.iter().zip(join_all(self.nodes.iter().map(|node| node.lock(resource, ttl))).await)
+        {
+            match locked {
+                Ok(Some(token)) => locks.push(NodeLock { node: Arc::clone(node), token }),
+                Ok(None) => {}
+                Err(error) => errors.push(error),
+            }
+        }
+
+        let acquired = locks.len();
+        match validity(ttl, self.clock_drift_factor, started_at) {
+            Some(validity) if acquired >= self.quorum => {
+                debug!(acquired, quorum = self.quorum, ?validity, "Async op lock taken.");
+
+                Ok(Some(AsyncOpLock {
+                    resource: resource.to_vec(),
+                    validity,
+                    acquired,
+                    quorum: self.quorum,
+                    taken_at: started_at,
+                    locks,
+                    released: false,
+                    lost: false,
+                    id: self.monitor.next_id(),
+                    monitor: Arc::clone(&self.monitor),
+                }))
+            }
+            _ => {
+                let failed = errors.len();
+                debug!(acquired, failed, quorum = self.quorum, "Async op lock quorum missed.");
+                release_all(resource, &locks).await;
+
+                // Would have been reached without the failed nodes.
+                match errors.into_iter().next() {
+                    Some(error) if acquired < self.quorum && acquired + failed >= self.quorum => Err(error),
+                    _ => Ok(None),
+                }
+            }
+        }
+    }
+
+    /// Traced and counted, the whole wait included.
+    async fn observe<E: std::fmt::Debug>(
+        &self,
+        resource: &[u8],
+        ttl: usize,
+        acquisition: impl Future<Output = Result<AsyncOpLock, E>>,
+    ) -> Result<AsyncOpLock, E> {
+        let span = debug_span!(
+            "async_op_guard.acquire",
+            resource = %String::from_utf8_lossy(resource),
+            ttl,
+            waited_ms = field::Empty,
+            acquired = field::Empty,
+        );
+        let started_at = Instant::now();
+        let result = acquisition.instrument(span.clone()).await;
+        let waited = started_at.elapsed();
+        span.record("waited_ms", waited.as_millis() as u64);
+        span.record("acquired", result.is_ok());
+
+        match &result {
+            Ok(lock) => {
+                // UNWRAP: Taken on the quorum of the nodes, at least one.
+                let token = &lock.locks.first().unwrap().token;
+                self.monitor.record_acquired(lock.id, resource, token, lock.validity, waited);
+            }
+            Err(error) => {
+                self.monitor.record_failure(waited);
+                debug!(parent: &span, ?error, "Async op lock not acquired.");
+            }
+        }
+
+        result
+    }
+
+    /// Take the lock on the quorum of the nodes, retried as configured.
+    pub async fn lock(&self, resource: &[u8], ttl: usize) -> Result<AsyncOpLock, LockError> {
+        self.observe(resource, ttl, retry(self.retry_count, self.retry_delay, || self.lock_once(resource, ttl))).await
+    }
+
+    /// Wait for the lock at most the `max_wait` of the `options`, retried with its backoff.
+    pub async fn acquire_within(
+        &self,
+        resource: &[u8],
+        ttl: usize,
+        options: &AcquireOptions,
+    ) -> Result<AsyncOpLock, AcquireError> {
+        self.observe(resource, ttl, retry_within(options, || self.lock_once(resource, ttl))).await
+    }
+
+    /// Loops until the lock is taken.
+    pub async fn acquire(&self, resource: &[u8], ttl: usize) -> AsyncOpLock {
+        let acquisition = async {
+            loop {
+                if let Ok(lock) = retry(self.retry_count, self.retry_delay, || self.lock_once(resource, ttl)).await {
+                    return Ok::<_, std::convert::Infallible>(lock);
+                }
+            }
+        };
+
+        match self.observe(resource, ttl, acquisition).await {
+            Ok(lock) => lock,
+            Err(never) => match never {},
+        }
+    }
+
+    /// Reset the ttl on the nodes holding the lock, the quorum of them must succeed.
+    /// The `lock` is left as it was if not.
+    pub async fn extend(&self, lock: &mut AsyncOpLock, ttl: usize) -> Result<(), LockError> {
+        let started_at = Instant::now();
+        let acquired =
+            join_all(lock.locks.iter().map(|node_lock| node_lock.node.extend(&lock.resource, &node_lock.token, ttl)))
+                .await
+                .into_iter()
+                .filter(|extended| *extended)
+                .count();
+
+        match validity(ttl, self.clock_drift_factor, started_at) {
+            Some(validity) if acquired >= self.quorum => {
+                lock.validity = validity;
+                lock.acquired = acquired;
+                lock.taken_at = started_at;
+                lock.lost = false;
+                self.monitor.record_extended(lock.id, validity);
+
+                Ok(())
+            }
+            _ => {
+                debug!(acquired, quorum = self.quorum, "Async op lock extension quorum missed.");
+                lock.lost = true;
+                Err(LockError::Unavailable)
+            }
+        }
+    }
+
+    /// Same as `AsyncOpLock::release`.
+    pub async fn unlock(&self, lock: AsyncOpLock) {
+        lock.release().await;
     }
 
     /// Acquire a lock.
-    /// May be stuck eternally.
+    /// May be stuck eternally, `spawn_within` gives up after a while.
     pub async fn spawn_acquire<F>(&self, resource: &[u8], ttl: usize, async_task: F) -> F::Output
     where
         F: Future,
@@ -27,7 +629,7 @@ impl AsyncOpGuard {
         // May be stuck.
         let lock = self.acquire(resource, ttl).await;
         let result = async_task.await;
-        drop(lock);
+        lock.release().await;
 
         result
     }
@@ -39,20 +641,559 @@ impl AsyncOpGuard {
     {
         let lock = self.lock(resource, ttl).await?;
         let result = async_task.await;
-        self.unlock(&lock).await;
+        lock.release().await;
 
         Ok(result)
     }
-}
 
-impl Deref for AsyncOpGuard {
-    type Target = LockManager;
+    /// Like `spawn_acquire`, but gives up as the `options` say.
+    pub async fn spawn_within<F>(
+        &self,
+        resource: &[u8],
+        ttl: usize,
+        options: &AcquireOptions,
+        async_task: F,
+    ) -> Result<F::Output, AcquireError>
+    where
+        F: Future,
+        F::Output: Send + Sync,
+    {
+        let lock = self.acquire_within(resource, ttl, options).await?;
+        let result = async_task.await;
+        lock.release().await;
+
+        Ok(result)
+    }
+
+    /// Like `spawn`, but the lock is extended by `ttl` every third of it while the task is running,
+    /// so it never expires before the task finishes. See `watch`.
+    pub async fn spawn_watched<F>(
+        &self,
+        resource: &[u8],
+        ttl: usize,
+        async_task: F,
+        on_lost: LockLostPolicy,
+    ) -> Result<WatchedOutput<F::Output>, LockError>
+    where
+        F: Future,
+        F::Output: Send + Sync,
+    {
+        let lock = self.lock(resource, ttl).await?;
+
+        Ok(self.watch(lock, ttl, async_task, on_lost).await)
+    }
+
+    /// Run the task with a taken `lock`, extended by `ttl` every third of it, then released.
+    /// A failed extension is retried every ninth of the `ttl` while the lock is still valid,
+    /// once it ran out, the task is handled by the `on_lost` policy.
+    pub async fn watch<F: Future>(
+        &self,
+        mut lock: AsyncOpLock,
+        ttl: usize,
+        async_task: F,
+        on_lost: LockLostPolicy,
+    ) -> WatchedOutput<F::Output> {
+        let interval = Duration::from_millis((ttl / 3).max(1) as u64);
+        let retry_delay = Duration::from_millis((ttl / 9).max(1) as u64);
+        let mut next_extension = interval;
+        let mut async_task = std::pin::pin!(async_task);
+        let mut lost = false;
+
+        let output = loop {
+            tokio::select! {
+                output = &mut async_task => break Some(output),
+                _ = tokio::time::sleep(next_extension), if !lost => {
+                    if self.extend(&mut lock, ttl).await.is_ok() {
+                        next_extension = interval;
+                        continue;
+                    }
+
+                    let resource = String::from_utf8_lossy(&lock.resource);
+                    let remaining = lock.remaining();
+                    if !remaining.is_zero() {
+                        debug!(%resource, ?remaining, "Async op lock extension failed, retrying.");
+                        next_extension = retry_delay.min(remaining);
+                        continue;
+                    }
+
+                    warn!(%resource, "Async op lock lost, failed to be extended.");
+                    lost = true;
+                    if on_lost == LockLostPolicy::Cancel {
+                        break None;
+                    }
+                },
+            }
+        };
 
-    fn deref(&self) -> &Self::Target {
-        &self.inner
+        // The nodes still holding it are released too.
+        lock.release().await;
+
+        if lost {
+            return output.map_or(WatchedOutput::Cancelled, WatchedOutput::Unguarded);
+        }
+
+        // UNWRAP: Only being cancelled leaves no output, after the lock was lost.
+        WatchedOutput::Exclusive(output.unwrap())
     }
 }
 
-pub fn generate_async_op_guard(uri: AsyncOpGuardConfig) -> AsyncOpGuardGlobal {
-    Arc::new(AsyncOpGuard::new(uri))
+pub fn generate_async_op_guard(config: AsyncOpGuardConfig) -> AsyncOpGuardGlobal {
+    Arc::new(AsyncOpGuard::new(config))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::memory::InMemoryLockNode;
+    use super::{
+        retry_within, AcquireBackoff, AcquireError, AcquireOptions, AsyncOpGuard, AsyncOpGuardBackendConfig,
+        AsyncOpGuardConfig, AsyncOpLockBackend, CancellationToken, LockLostPolicy, WatchedOutput,
+    };
+    use rslock::LockError;
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+    use std::sync::{Arc, Mutex};
+    use std::time::{Duration, Instant};
+
+    /// The env is shared by the tests.
+    static MUTEX: Mutex<()> = Mutex::new(());
+
+    /// An in-memory node, slowed down or failing to lock or extend.
+    #[derive(Default)]
+    pub(super) struct TestNode {
+        inner: InMemoryLockNode,
+        delay: Duration,
+        lock_fails: AtomicBool,
+        pub(super) extend_fails: AtomicBool,
+        unlocks: AtomicUsize,
+    }
+
+    #[async_trait::async_trait]
+    impl AsyncOpLockBackend for TestNode {
+        async fn lock(&self, resource: &[u8], ttl: usize) -> Result<Option<Vec<u8>>, LockError> {
+            tokio::time::sleep(self.delay).await;
+            if self.lock_fails.load(Ordering::Relaxed) {
+                return Err(LockError::Unavailable);
+            }
+
+            self.inner.lock(resource, ttl).await
+        }
+
+        async fn extend(&self, resource: &[u8], token: &[u8], ttl: usize) -> bool {
+            !self.extend_fails.load(Ordering::Relaxed) && self.inner.extend(resource, token, ttl).await
+        }
+
+        async fn unlock(&self, resource: &[u8], token: &[u8]) {
+            self.unlocks.fetch_add(1, Ordering::Relaxed);
+            self.inner.unlock(resource, token).await
+        }
+    }
+
+    pub(super) fn guard(nodes: &[Arc<TestNode>]) -> AsyncOpGuard {
+        let config =
+            AsyncOpGuardConfig { retry_count: 1, ..AsyncOpGuardConfig::new(AsyncOpGuardBackendConfig::Memory) };
+
+        AsyncOpGuard::with_nodes(nodes.iter().map(|node| Arc::clone(node) as _).collect(), &config)
+    }
+
+    fn nodes(count: usize) -> Vec<Arc<TestNode>> {
+        (0..count).map(|_| Arc::default()).collect()
+    }
+
+    #[tokio::test]
+    async fn count_quorum() {
+        let nodes = nodes(3);
+        let guard = guard(&nodes);
+        assert_eq!(guard.quorum(), 2);
+
+        // Held by others on one node.
+        let other = nodes[0].lock(b"tests", 1000).await.unwrap().unwrap();
+        let lock = guard.lock(b"tests", 1000).await.unwrap();
+        assert_eq!((lock.acquired, lock.quorum), (2, 2));
+        lock.release().await;
+
+        // Held by others on two nodes.
+        nodes[1].lock(b"tests", 1000).await.unwrap().unwrap();
+        assert!(guard.lock(b"tests", 1000).await.is_err());
+
+        nodes[0].unlock(b"tests", &other).await;
+        assert_eq!(guard.lock(b"tests", 1000).await.unwrap().acquired, 2);
+    }
+
+    #[tokio::test]
+    async fn release_when_quorum_missed() {
+        let nodes = nodes(3);
+        let guard = guard(&nodes);
+        nodes[0].lock(b"tests", 1000).await.unwrap().unwrap();
+        nodes[1].lock(b"tests", 1000).await.unwrap().unwrap();
+
+        assert!(guard.lock(b"tests", 1000).await.is_err());
+
+        // Not left held on the node it was taken on.
+        assert!(nodes[2].lock(b"tests", 1000).await.unwrap().is_some());
+        assert!(guard.held().is_empty());
+    }
+
+    #[tokio::test]
+    async fn failed_nodes_apart_from_held() {
+        let nodes = nodes(3);
+        let guard = guard(&nodes);
+        let once = AcquireOptions::new(Duration::ZERO);
+
+        // Still reached without the failed one.
+        nodes[0].lock_fails.store(true, Ordering::Relaxed);
+        guard.acquire_within(b"tests", 1000, &once).await.unwrap().release().await;
+
+        // Missed for the failed ones.
+        nodes[1].lock_fails.store(true, Ordering::Relaxed);
+        let result = guard.acquire_within(b"tests", 1000, &once).await;
+        assert!(matches!(result, Err(AcquireError::Lock(LockError::Unavailable))));
+
+        // Missed for the held ones, whatever failed.
+        nodes[1].lock_fails.store(false, Ordering::Relaxed);
+        nodes[1].lock(b"tests", 1000).await.unwrap().unwrap();
+        nodes[2].lock(b"tests", 1000).await.unwrap().unwrap();
+        let result = guard.acquire_within(b"tests", 1000, &once).await;
+        assert!(matches!(result, Err(AcquireError::TimedOut(_))));
+    }
+
+    #[tokio::test]
+    async fn validity_after_clock_drift() {
+        let nodes = nodes(3);
+        let config = AsyncOpGuardConfig {
+            clock_drift_factor: 0.1,
+            ..AsyncOpGuardConfig::new(AsyncOpGuardBackendConfig::Memory)
+        };
+        let drifting = AsyncOpGuard::with_nodes(nodes.iter().map(|node| Arc::clone(node) as _).collect(), &config);
+
+        // The drift and the time spent are taken off.
+        let lock = drifting.lock(b"tests", 1000).await.unwrap();
+        assert!(lock.validity <= Duration::from_millis(898));
+        assert!(lock.validity > Duration::from_millis(850));
+        lock.release().await;
+
+        // Nothing is left after a slow quorum, released on every node.
+        let slow = (0..3)
+            .map(|_| Arc::new(TestNode { delay: Duration::from_millis(60), ..Default::default() }))
+            .collect::<Vec<_>>();
+        assert!(guard(&slow).lock(b"tests", 50).await.is_err());
+        for node in &slow {
+            assert!(node.inner.lock(b"tests", 50).await.unwrap().is_some());
+        }
+    }
+
+    #[tokio::test]
+    async fn extend_watched_past_ttl() {
+        let nodes = nodes(3);
+        let guard = guard(&nodes);
+
+        let output = guard
+            .spawn_watched(
+                b"tests",
+                60,
+                async {
+                    tokio::time::sleep(Duration::from_millis(200)).await;
+                    // Still held by the task.
+                    guard.lock(b"tests", 60).await.is_err()
+                },
+                LockLostPolicy::Cancel,
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(output, WatchedOutput::Exclusive(true));
+        assert!(guard.lock(b"tests", 60).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn retry_watched_extension() {
+        let nodes = nodes(1);
+        let guard = guard(&nodes);
+
+        // Fails for a while, but recovers before the lock runs out.
+        let output = guard
+            .spawn_watched(
+                b"tests",
+                90,
+                async {
+                    nodes[0].extend_fails.store(true, Ordering::Relaxed);
+                    tokio::time::sleep(Duration::from_millis(40)).await;
+                    nodes[0].extend_fails.store(false, Ordering::Relaxed);
+                    tokio::time::sleep(Duration::from_millis(150)).await;
+                },
+                LockLostPolicy::Cancel,
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(output, WatchedOutput::Exclusive(()));
+        assert_eq!(guard.stats().expirations, 0);
+    }
+
+    #[tokio::test]
+    async fn cancel_watched_once_lost() {
+        let nodes = nodes(1);
+        let guard = guard(&nodes);
+        nodes[0].extend_fails.store(true, Ordering::Relaxed);
+        let finished = AtomicBool::new(false);
+
+        let output = guard
+            .spawn_watched(
+                b"tests",
+                60,
+                async {
+                    tokio::time::sleep(Duration::from_millis(300)).await;
+                    finished.store(true, Ordering::Relaxed);
+                },
+                LockLostPolicy::Cancel,
+            )
+            .await
+            .unwrap();
+
+        // Dropped once the lock ran out.
+        assert_eq!(output, WatchedOutput::Cancelled);
+        assert!(!finished.load(Ordering::Relaxed));
+        assert_eq!(guard.stats().expirations, 1);
+    }
+
+    #[tokio::test]
+    async fn continue_watched_once_lost() {
+        let nodes = nodes(1);
+        let guard = guard(&nodes);
+        nodes[0].extend_fails.store(true, Ordering::Relaxed);
+
+        let output = guard
+            .spawn_watched(
+                b"tests",
+                60,
+                async {
+                    tokio::time::sleep(Duration::from_millis(150)).await;
+                    "done"
+                },
+                LockLostPolicy::Continue,
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(output, WatchedOutput::Unguarded("done"));
+    }
+
+    #[tokio::test]
+    async fn release_dropped_lock() {
+        let nodes = nodes(3);
+        let guard = Arc::new(guard(&nodes));
+
+        // Dropped with the task, long before it expires.
+        let task = tokio::spawn({
+            let guard = Arc::clone(&guard);
+            async move {
+                let _lock = guard.lock(b"tests", 10_000).await.unwrap();
+                std::future::pending::<()>().await;
+            }
+        });
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert!(guard.lock(b"tests", 10_000).await.is_err());
+        task.abort();
+        assert!(task.await.unwrap_err().is_cancelled());
+
+        // Released in the background.
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        let lock = guard.lock(b"tests", 10_000).await.unwrap();
+        lock.release().await;
+        assert!(guard.held().is_empty());
+    }
+
+    #[tokio::test]
+    async fn release_lock_of_panicked_task() {
+        let nodes = nodes(3);
+        let guard = Arc::new(guard(&nodes));
+
+        let task = tokio::spawn({
+            let guard = Arc::clone(&guard);
+            async move {
+                let _lock = guard.lock(b"tests", 10_000).await.unwrap();
+                panic!("Task failed.");
+            }
+        });
+        assert!(task.await.unwrap_err().is_panic());
+
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert!(guard.lock(b"tests", 10_000).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn decrease_remaining() {
+        let guard = guard(&nodes(1));
+        let lock = guard.lock(b"tests", 1000).await.unwrap();
+
+        let first = lock.remaining();
+        assert!(first <= lock.validity);
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        let second = lock.remaining();
+        assert!(second <= first - Duration::from_millis(50));
+
+        // Never below zero once expired.
+        let expired = guard.lock(b"others", 20).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(30)).await;
+        assert!(expired.remaining().is_zero());
+    }
+
+    #[tokio::test]
+    async fn unlock_released_lock_once() {
+        let nodes = nodes(3);
+        let guard = guard(&nodes);
+
+        // Dropped by `release`, not unlocked again in the background.
+        guard.lock(b"tests", 10_000).await.unwrap().release().await;
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        for node in &nodes {
+            assert_eq!(node.unlocks.load(Ordering::Relaxed), 1);
+        }
+        assert_eq!(guard.stats().released, 1);
+    }
+
+    #[test]
+    fn backoff_delay() {
+        let exponential =
+            AcquireBackoff::Exponential { initial: Duration::from_millis(50), max: Duration::from_millis(300) };
+        let delays = (1..=5).map(|attempt| exponential.delay(attempt).as_millis()).collect::<Vec<_>>();
+        assert_eq!(delays, [50, 100, 200, 300, 300]);
+
+        let jittered = AcquireBackoff::Jittered { initial: Duration::from_millis(50), max: Duration::from_millis(300) };
+        for attempt in 1..=5 {
+            assert!(jittered.delay(attempt) <= exponential.delay(attempt));
+        }
+
+        // Never busy-loops.
+        assert_eq!(AcquireBackoff::Fixed(Duration::ZERO).delay(1), Duration::from_millis(10));
+        assert_eq!(AcquireBackoff::Fixed(Duration::from_millis(20)).delay(7), Duration::from_millis(20));
+    }
+
+    #[tokio::test]
+    async fn retry_within_deadline() {
+        let options =
+            AcquireOptions::new(Duration::from_millis(100)).set_backoff(AcquireBackoff::Fixed(Duration::ZERO));
+        let attempts = AtomicUsize::new(0);
+        let started_at = Instant::now();
+
+        let result = retry_within(&options, || async {
+            attempts.fetch_add(1, Ordering::Relaxed);
+            Ok(None::<()>)
+        })
+        .await;
+
+        assert!(matches!(result, Err(AcquireError::TimedOut(max_wait)) if max_wait == Duration::from_millis(100)));
+        assert!(started_at.elapsed() >= Duration::from_millis(100));
+        assert!(attempts.load(Ordering::Relaxed) <= 11);
+    }
+
+    #[tokio::test]
+    async fn cancel_retry_within_while_sleeping() {
+        let cancel = CancellationToken::new();
+        let options = AcquireOptions::new(Duration::from_secs(10))
+            .set_backoff(AcquireBackoff::Fixed(Duration::from_secs(5)))
+            .set_cancel(cancel.clone());
+        let attempts = AtomicUsize::new(0);
+
+        tokio::spawn({
+            let cancel = cancel.clone();
+            async move {
+                tokio::time::sleep(Duration::from_millis(50)).await;
+                cancel.cancel();
+            }
+        });
+        let started_at = Instant::now();
+        let result = retry_within(&options, || async {
+            attempts.fetch_add(1, Ordering::Relaxed);
+            Ok(None::<()>)
+        })
+        .await;
+
+        assert!(matches!(result, Err(AcquireError::Cancelled)));
+        assert!(started_at.elapsed() < Duration::from_secs(1));
+        assert_eq!(attempts.load(Ordering::Relaxed), 1);
+
+        // Not even attempted once cancelled.
+        let result = retry_within(&options, || async {
+            attempts.fetch_add(1, Ordering::Relaxed);
+            Ok(Some(()))
+        })
+        .await;
+        assert!(matches!(result, Err(AcquireError::Cancelled)));
+        assert_eq!(attempts.load(Ordering::Relaxed), 1);
+    }
+
+    /// Every var read by `from_env` is set or removed explicitly, whatever the `.env` says.
+    fn set_env(vars: &[(&str, Option<&str>)]) {
+        // The `.env` is loaded on the first read, never overriding what's set.
+        let _ = web_env::var("LOCK_BACKEND");
+
+        let keys = ["LOCK_BACKEND", "LOCK_REDIS_URIS", "REDIS_URI", "LOCK_RETRY_COUNT", "LOCK_CLOCK_DRIFT_FACTOR"];
+        for key in keys {
+            std::env::remove_var(key);
+        }
+        for (key, value) in vars {
+            if let Some(value) = value {
+                std::env::set_var(key, value);
+            }
+        }
+    }
+
+    #[test]
+    fn redis_uris_from_env() {
+        let _guard = MUTEX.lock().unwrap();
+        let uris = |vars: &[(&str, Option<&str>)]| {
+            set_env(vars);
+            AsyncOpGuardConfig::from_env().map(|config| config.backend)
+        };
+
+        let nodes = "redis://10.0.0.1:6379, redis://10.0.0.2:6379,redis://10.0.0.3:6379";
+        assert_eq!(
+            uris(&[("LOCK_REDIS_URIS", Some(nodes)), ("REDIS_URI", Some("redis-sentinel://10.0.0.1:26379"))]).unwrap(),
+            AsyncOpGuardBackendConfig::Redis(vec![
+                "redis://10.0.0.1:6379".into(),
+                "redis://10.0.0.2:6379".into(),
+                "redis://10.0.0.3:6379".into()
+            ])
+        );
+        assert_eq!(
+            uris(&[("REDIS_URI", Some("rediss://10.0.0.1:6379"))]).unwrap(),
+            AsyncOpGuardBackendConfig::Redis(vec!["rediss://10.0.0.1:6379".into()])
+        );
+
+        // Never falls back to a local node.
+        for uri in ["redis-sentinel://10.0.0.1:26379/mymaster", "redis-cluster://10.0.0.1:6379"] {
+            let error = uris(&[("REDIS_URI", Some(uri))]).unwrap_err();
+            assert_eq!(error.to_string(), "LOCK_REDIS_URIS is required, REDIS_URI is not a single redis node.");
+        }
+        let error = uris(&[]).unwrap_err();
+        assert_eq!(error.to_string(), "LOCK_REDIS_URIS is required without REDIS_URI.");
+
+        let error = uris(&[("LOCK_REDIS_URIS", Some("redis://10.0.0.1:6379,http://10.0.0.2"))]).unwrap_err();
+        assert_eq!(error.to_string(), "Unsupported lock redis uri `http://10.0.0.2`.");
+
+        // Not needed by the in-memory node.
+        assert_eq!(uris(&[("LOCK_BACKEND", Some("memory"))]).unwrap(), AsyncOpGuardBackendConfig::Memory);
+
+        set_env(&[]);
+    }
+
+    #[test]
+    fn validate_env() {
+        let _guard = MUTEX.lock().unwrap();
+        let error = |vars: &[(&str, Option<&str>)]| {
+            set_env(vars);
+            AsyncOpGuardConfig::from_env().unwrap_err().to_string()
+        };
+
+        assert_eq!(error(&[("LOCK_BACKEND", Some("etcd"))]), "Unknown LOCK_BACKEND `etcd`.");
+        assert_eq!(
+            error(&[("LOCK_BACKEND", Some("memory")), ("LOCK_RETRY_COUNT", Some("0"))]),
+            "LOCK_RETRY_COUNT must be greater than 0."
+        );
+        assert_eq!(
+            error(&[("LOCK_BACKEND", Some("memory")), ("LOCK_CLOCK_DRIFT_FACTOR", Some("1"))]),
+            "LOCK_CLOCK_DRIFT_FACTOR must be within [0, 1)."
+        );
+
+        set_env(&[]);
+    }
 }
@@ -0,0 +1,93 @@
+/// In-process node of the async op guard.
+/// Same ttl semantics as the redis one, but nothing is shared with other instances.
+use crate::async_op::AsyncOpLockBackend;
+use rslock::LockError;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+use tokio::time::Instant;
+
+/// The token and the expiry, by the resource.
+type Locks = HashMap<Vec<u8>, (Vec<u8>, Instant)>;
+
+#[derive(Default)]
+pub struct InMemoryLockNode {
+    locks: Mutex<Locks>,
+    tokens: AtomicU64,
+}
+
+impl InMemoryLockNode {
+    /// Whether the `resource` is still held with the `token`.
+    fn with_held<R>(&self, resource: &[u8], token: &[u8], f: impl FnOnce(&mut Locks) -> R) -> Option<R> {
+        // UNWRAP: Nothing panics while holding the lock.
+        let mut locks = self.locks.lock().unwrap();
+
+        match locks.get(resource) {
+            Some((held, expires_at)) if held == token && *expires_at > Instant::now() => Some(f(&mut locks)),
+            _ => None,
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl AsyncOpLockBackend for InMemoryLockNode {
+    async fn lock(&self, resource: &[u8], ttl: usize) -> Result<Option<Vec<u8>>, LockError> {
+        let now = Instant::now();
+        // UNWRAP: Nothing panics while holding the lock.
+        let mut locks = self.locks.lock().unwrap();
+
+        if locks.get(resource).is_some_and(|(_, expires_at)| *expires_at > now) {
+            return Ok(None);
+        }
+
+        // The expired ones are never released by their holders.
+        locks.retain(|_, (_, expires_at)| *expires_at > now);
+
+        let token = self.tokens.fetch_add(1, Ordering::Relaxed).to_be_bytes().to_vec();
+        locks.insert(resource.to_vec(), (token.clone(), now + Duration::from_millis(ttl as u64)));
+
+        Ok(Some(token))
+    }
+
+    async fn extend(&self, resource: &[u8], token: &[u8], ttl: usize) -> bool {
+        self.with_held(resource, token, |locks| {
+            locks.insert(resource.to_vec(), (token.to_vec(), Instant::now() + Duration::from_millis(ttl as u64)));
+        })
+        .is_some()
+    }
+
+    async fn unlock(&self, resource: &[u8], token: &[u8]) {
+        self.with_held(resource, token, |locks| locks.remove(resource));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::InMemoryLockNode;
+    use crate::async_op::AsyncOpLockBackend;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn held_until_expired() {
+        let node = InMemoryLockNode::default();
+
+        let token = node.lock(b"tests", 50).await.unwrap().unwrap();
+        assert_eq!(node.lock(b"tests", 50).await.unwrap(), None);
+        assert!(node.lock(b"others", 50).await.unwrap().is_some());
+
+        assert!(node.extend(b"tests", &token, 100).await);
+        tokio::time::sleep(Duration::from_millis(60)).await;
+        assert_eq!(node.lock(b"tests", 50).await.unwrap(), None);
+
+        tokio::time::sleep(Duration::from_millis(60)).await;
+        assert!(!node.extend(b"tests", &token, 100).await);
+        let next = node.lock(b"tests", 50).await.unwrap().unwrap();
+
+        // Only the holder releases it.
+        node.unlock(b"tests", &token).await;
+        assert_eq!(node.lock(b"tests", 50).await.unwrap(), None);
+        node.unlock(b"tests", &next).await;
+        assert!(node.lock(b"tests", 50).await.unwrap().is_some());
+    }
+}
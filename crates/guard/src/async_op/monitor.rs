@@ -0,0 +1,196 @@
+/// Observability of the async op guard - counters of the acquisitions and releases,
+/// and the locks held by this instance, so the contention can be told apart from slow tasks.
+use serde::Serialize;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Counters of a guard, updated without locking.
+#[derive(Default, Debug)]
+struct AsyncOpGuardStats {
+    acquired: AtomicU64,
+    failures: AtomicU64,
+    released: AtomicU64,
+    expirations: AtomicU64,
+    wait_total_ms: AtomicU64,
+    wait_max_ms: AtomicU64,
+    hold_total_ms: AtomicU64,
+    hold_max_ms: AtomicU64,
+}
+
+#[derive(Serialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct AsyncOpGuardStatsSnapshot {
+    pub acquired: u64,
+    /// Gave up, e.g. still held by others, timed out or cancelled.
+    pub failures: u64,
+    pub released: u64,
+    /// Released after running out of validity, or after failing to be extended.
+    pub expirations: u64,
+    /// From asking until taking or giving up.
+    pub wait_total_ms: u64,
+    pub wait_max_ms: u64,
+    /// From taking until releasing.
+    pub hold_total_ms: u64,
+    pub hold_max_ms: u64,
+}
+
+/// A lock held by this instance.
+#[derive(Serialize, Clone, Debug, PartialEq, Eq)]
+pub struct HeldAsyncOpLock {
+    pub resource: String,
+    /// Fingerprint of the token, tells the holders apart without exposing the token itself.
+    pub owner: String,
+    pub held_ms: u64,
+    pub remaining_ttl_ms: u64,
+}
+
+struct HeldEntry {
+    resource: Vec<u8>,
+    owner: String,
+    taken_at: Instant,
+    expires_at: Instant,
+}
+
+#[derive(Default)]
+pub(crate) struct AsyncOpGuardMonitor {
+    stats: AsyncOpGuardStats,
+    next_id: AtomicU64,
+    held: Mutex<HashMap<u64, HeldEntry>>,
+}
+
+#[inline]
+fn record_duration(total: &AtomicU64, max: &AtomicU64, duration: Duration) {
+    let millis = duration.as_millis() as u64;
+    total.fetch_add(millis, Ordering::Relaxed);
+    max.fetch_max(millis, Ordering::Relaxed);
+}
+
+/// The token unlocks the resource on the nodes, so only its hash is kept.
+fn fingerprint(token: &[u8]) -> String {
+    let mut hasher = DefaultHasher::new();
+    token.hash(&mut hasher);
+
+    format!("{:08x}", hasher.finish() as u32)
+}
+
+impl AsyncOpGuardMonitor {
+    /// Of a lock being taken.
+    #[inline]
+    pub(crate) fn next_id(&self) -> u64 {
+        self.next_id.fetch_add(1, Ordering::Relaxed)
+    }
+
+    pub(crate) fn record_acquired(&self, id: u64, resource: &[u8], token: &[u8], validity: Duration, waited: Duration) {
+        self.stats.acquired.fetch_add(1, Ordering::Relaxed);
+        record_duration(&self.stats.wait_total_ms, &self.stats.wait_max_ms, waited);
+
+        let owner = fingerprint(token);
+        let now = Instant::now();
+        // UNWRAP: Nothing panics while holding the lock.
+        self.held
+            .lock()
+            .unwrap()
+            .insert(id, HeldEntry { resource: resource.to_vec(), owner, taken_at: now, expires_at: now + validity });
+    }
+
+    #[inline]
+    pub(crate) fn record_failure(&self, waited: Duration) {
+        self.stats.failures.fetch_add(1, Ordering::Relaxed);
+        record_duration(&self.stats.wait_total_ms, &self.stats.wait_max_ms, waited);
+    }
+
+    pub(crate) fn record_extended(&self, id: u64, validity: Duration) {
+        // UNWRAP: Nothing panics while holding the lock.
+        if let Some(entry) = self.held.lock().unwrap().get_mut(&id) {
+            entry.expires_at = Instant::now() + validity;
+        }
+    }
+
+    /// How long it was held, `None` if it was never acquired.
+    pub(crate) fn record_released(&self, id: u64, expired: bool) -> Option<Duration> {
+        // UNWRAP: Nothing panics while holding the lock.
+        let entry = self.held.lock().unwrap().remove(&id)?;
+        let held = entry.taken_at.elapsed();
+
+        self.stats.released.fetch_add(1, Ordering::Relaxed);
+        if expired {
+            self.stats.expirations.fetch_add(1, Ordering::Relaxed);
+        }
+        record_duration(&self.stats.hold_total_ms, &self.stats.hold_max_ms, held);
+
+        Some(held)
+    }
+
+    pub(crate) fn snapshot(&self) -> AsyncOpGuardStatsSnapshot {
+        AsyncOpGuardStatsSnapshot {
+            acquired: self.stats.acquired.load(Ordering::Relaxed),
+            failures: self.stats.failures.load(Ordering::Relaxed),
+            released: self.stats.released.load(Ordering::Relaxed),
+            expirations: self.stats.expirations.load(Ordering::Relaxed),
+            wait_total_ms: self.stats.wait_total_ms.load(Ordering::Relaxed),
+            wait_max_ms: self.stats.wait_max_ms.load(Ordering::Relaxed),
+            hold_total_ms: self.stats.hold_total_ms.load(Ordering::Relaxed),
+            hold_max_ms: self.stats.hold_max_ms.load(Ordering::Relaxed),
+        }
+    }
+
+    /// The expired ones are left out, even if not released yet. Ordered by the resource.
+    pub(crate) fn held(&self) -> Vec<HeldAsyncOpLock> {
+        let now = Instant::now();
+        // UNWRAP: Nothing panics while holding the lock.
+        let mut held = self
+            .held
+            .lock()
+            .unwrap()
+            .values()
+            .filter(|entry| entry.expires_at > now)
+            .map(|entry| HeldAsyncOpLock {
+                resource: String::from_utf8_lossy(&entry.resource).into_owned(),
+                owner: entry.owner.clone(),
+                held_ms: now.duration_since(entry.taken_at).as_millis() as u64,
+                remaining_ttl_ms: entry.expires_at.duration_since(now).as_millis() as u64,
+            })
+            .collect::<Vec<_>>();
+        held.sort_by(|a, b| a.resource.cmp(&b.resource));
+
+        held
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::async_op::{generate_async_op_guard, AsyncOpGuardBackendConfig, AsyncOpGuardConfig};
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn count_and_list_held() {
+        let guard = generate_async_op_guard(AsyncOpGuardConfig {
+            retry_count: 1,
+            ..AsyncOpGuardConfig::new(AsyncOpGuardBackendConfig::Memory)
+        });
+
+        let a = guard.lock(b"tests:a", 1000).await.unwrap();
+        let b = guard.lock(b"tests:b", 50).await.unwrap();
+        assert!(guard.lock(b"tests:a", 1000).await.is_err());
+
+        let held = guard.held();
+        assert_eq!(held.iter().map(|lock| lock.resource.as_str()).collect::<Vec<_>>(), ["tests:a", "tests:b"]);
+        assert!(held[0].remaining_ttl_ms > 900 && held[0].remaining_ttl_ms <= 1000);
+        assert_eq!(held[0].owner.len(), 8);
+        assert_ne!(held[0].owner, held[1].owner);
+
+        // Left out once expired, counted when released.
+        tokio::time::sleep(Duration::from_millis(60)).await;
+        assert_eq!(guard.held().len(), 1);
+        b.release().await;
+        a.release().await;
+        assert!(guard.held().is_empty());
+
+        let stats = guard.stats();
+        assert_eq!((stats.acquired, stats.failures, stats.released, stats.expirations), (2, 1, 2, 1));
+        assert!(stats.hold_max_ms >= 60);
+    }
+}
@@ -0,0 +1,52 @@
+/// Redis node of the async op guard, a lock is a key set with `NX` and the ttl.
+/// Taken here rather than by `rslock`, so a failed node is told apart from a lock held by others.
+use crate::async_op::AsyncOpLockBackend;
+use rslock::{Lock, LockError, LockManager};
+
+pub struct RedisLockNode {
+    /// Of this node alone, the quorum is reached by the guard.
+    manager: LockManager,
+}
+
+impl RedisLockNode {
+    pub fn new(uri: &str) -> Self {
+        let mut manager = LockManager::new(vec![uri]);
+        // Retried by the guard itself.
+        manager.set_retry(1, 1);
+
+        RedisLockNode { manager }
+    }
+
+    #[inline]
+    fn held<'a>(&'a self, resource: &[u8], token: &[u8]) -> Lock<'a> {
+        Lock { resource: resource.to_vec(), val: token.to_vec(), validity_time: 0, lock_manager: &self.manager }
+    }
+}
+
+#[async_trait::async_trait]
+impl AsyncOpLockBackend for RedisLockNode {
+    async fn lock(&self, resource: &[u8], ttl: usize) -> Result<Option<Vec<u8>>, LockError> {
+        let token = self.manager.get_unique_lock_id().map_err(LockError::Io)?;
+        // Created with the single uri.
+        let mut connection = self.manager.servers[0].get_async_connection().await.map_err(LockError::Redis)?;
+        let set = redis::cmd("SET")
+            .arg(resource)
+            .arg(&token)
+            .arg("NX")
+            .arg("PX")
+            .arg(ttl)
+            .query_async::<_, Option<String>>(&mut connection)
+            .await
+            .map_err(LockError::Redis)?;
+
+        Ok(set.map(|_| token))
+    }
+
+    async fn extend(&self, resource: &[u8], token: &[u8], ttl: usize) -> bool {
+        self.manager.extend(&self.held(resource, token), ttl).await.is_ok()
+    }
+
+    async fn unlock(&self, resource: &[u8], token: &[u8]) {
+        self.manager.unlock(&self.held(resource, token)).await;
+    }
+}
@@ -0,0 +1,159 @@
+/// Leader election - at most one instance leads a name at a time, e.g. the one running the scheduled tasks.
+/// The leadership is an exclusive lease renewed every third of its ttl, the leader steps down once a renewal fails,
+/// and another instance takes over after the lease expired, e.g. when the leader died.
+use crate::async_op::{AsyncOpGuardConfig, CancellationToken};
+use crate::lease::{Lease, LeaseBackendGlobal, LeaseKind, Leaser};
+use std::time::Duration;
+use tokio::sync::watch;
+
+/// Prefixed to the names, so they never clash with the read/write locks.
+const LEADER_NAMESPACE: &str = "leader";
+
+pub struct LeaderElection {
+    name: String,
+    ttl: Duration,
+    leaser: Leaser,
+}
+
+impl LeaderElection {
+    /// Campaign in the background, until the leadership is shut down.
+    pub fn start(self) -> Leadership {
+        let (leading_tx, leading) = watch::channel(false);
+        let stopped = CancellationToken::new();
+
+        debug!(name = self.name, ttl = ?self.ttl, "Starting the leader election.");
+
+        let task = tokio::spawn(campaign(self, leading_tx, stopped.clone()));
+
+        Leadership { leading, stopped, task }
+    }
+}
+
+/// Take the leadership whenever it's free, then keep renewing it, until being stopped.
+async fn campaign(election: LeaderElection, leading: watch::Sender<bool>, stopped: CancellationToken) {
+    let LeaderElection { name, ttl, leaser } = election;
+    let resource = format!("{LEADER_NAMESPACE}:{name}");
+    let ttl_millis = ttl.as_millis() as usize;
+    let mut lease: Option<Lease> = None;
+
+    loop {
+        lease = match lease.take() {
+            None => leaser
+                .lease_once(&resource, LeaseKind::Write, ttl_millis)
+                .await
+                .inspect(|_| info!(name, "Leadership taken.")),
+            Some(mut held) => {
+                // Not renewed before it expires, another instance may have taken over.
+                let remaining = held.remaining();
+                match tokio::time::timeout(remaining, leaser.extend(&mut held, ttl_millis)).await {
+                    Ok(Ok(())) => Some(held),
+                    Ok(Err(_)) | Err(_) => {
+                        warn!(name, "Failed to renew the leadership, stepped down.");
+                        None
+                    }
+                }
+            }
+        };
+        let held = lease.is_some();
+        leading.send_if_modified(|leading| std::mem::replace(leading, held) != held);
+
+        tokio::select! {
+            _ = stopped.cancelled() => break,
+            _ = tokio::time::sleep(ttl / 3) => {}
+        }
+    }
+
+    leading.send_replace(false);
+    if let Some(lease) = lease {
+        lease.release().await;
+        info!(name, "Leadership released.");
+    }
+}
+
+/// The running campaign, stopped by `shutdown`.
+pub struct Leadership {
+    leading: watch::Receiver<bool>,
+    stopped: CancellationToken,
+    task: tokio::task::JoinHandle<()>,
+}
+
+impl Leadership {
+    #[inline]
+    pub fn is_leader(&self) -> bool {
+        *self.leading.borrow()
+    }
+
+    /// Notified whenever the leadership is taken or lost.
+    #[inline]
+    pub fn subscribe(&self) -> watch::Receiver<bool> {
+        self.leading.clone()
+    }
+
+    /// Step down if leading, so another instance takes over without waiting for the lease to expire.
+    pub async fn shutdown(self) {
+        self.stopped.cancel();
+        let _ = self.task.await;
+    }
+}
+
+/// Renewed every third of the `ttl`, the leadership of a dead leader is taken over after it.
+pub fn generate_leader_election(
+    config: &AsyncOpGuardConfig,
+    backend: &LeaseBackendGlobal,
+    name: &str,
+    ttl: Duration,
+) -> LeaderElection {
+    LeaderElection { name: name.to_string(), ttl, leaser: Leaser::new(config, backend) }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::generate_leader_election;
+    use crate::async_op::{AsyncOpGuardBackendConfig, AsyncOpGuardConfig};
+    use crate::lease::{memory::InMemoryLeaseBackend, LeaseBackendGlobal, LeaseKind};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    const TTL: Duration = Duration::from_millis(150);
+
+    #[tokio::test]
+    async fn one_leader_at_a_time() {
+        let config = AsyncOpGuardConfig::new(AsyncOpGuardBackendConfig::Memory);
+        let backend: LeaseBackendGlobal = Arc::new(InMemoryLeaseBackend::default());
+
+        let first = generate_leader_election(&config, &backend, "tests", TTL).start();
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        let second = generate_leader_election(&config, &backend, "tests", TTL).start();
+        let mut second_leading = second.subscribe();
+
+        // Renewed longer than the ttl.
+        tokio::time::sleep(TTL * 2).await;
+        assert!(first.is_leader());
+        assert!(!second.is_leader());
+
+        // Taken over once the first one stepped down.
+        first.shutdown().await;
+        tokio::time::timeout(TTL, second_leading.wait_for(|leading| *leading)).await.unwrap().unwrap();
+        assert!(second.is_leader());
+
+        second.shutdown().await;
+    }
+
+    #[tokio::test]
+    async fn take_over_dead_leader() {
+        let config = AsyncOpGuardConfig::new(AsyncOpGuardBackendConfig::Memory);
+        let backend: LeaseBackendGlobal = Arc::new(InMemoryLeaseBackend::default());
+
+        // Held by a leader which died without stepping down.
+        assert!(backend.acquire("leader:tests", LeaseKind::Write, "dead", TTL.as_millis() as usize).await);
+
+        let leadership = generate_leader_election(&config, &backend, "tests", TTL).start();
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert!(!leadership.is_leader());
+
+        tokio::time::sleep(TTL + TTL / 2).await;
+        assert!(leadership.is_leader());
+
+        leadership.shutdown().await;
+    }
+}
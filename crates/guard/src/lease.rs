@@ -0,0 +1,224 @@
+/// Leases with a ttl, shared by the semaphores and the read/write locks.
+/// Unlike the async op locks, they live on a single node, the first redis one of the async op guard,
+/// connected the same way as the distribute cache, e.g. with its TLS, pool and reconnect policy.
+use crate::async_op::{
+    retry, retry_within, validity, AcquireError, AcquireOptions, AsyncOpGuardBackendConfig, AsyncOpGuardConfig,
+};
+use rslock::LockError;
+use std::future::Future;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+pub mod memory;
+pub mod redis;
+
+pub type LeaseBackendGlobal = Arc<dyn LeaseBackend>;
+
+/// How long the new readers back off once a writer waits for the current ones, so it's not starved.
+pub(crate) const WRITER_WAITING_TTL: usize = 2000;
+
+static TOKEN_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LeaseKind {
+    /// One of at most `limit` ones.
+    Permit { limit: usize },
+    /// Shared with other readers, never with a writer.
+    Read,
+    /// Exclusive.
+    Write,
+}
+
+/// Where the leases are held until released or expired.
+#[async_trait::async_trait]
+pub trait LeaseBackend: Send + Sync {
+    /// Lease the `name` with the `token`, `false` if not available now.
+    async fn acquire(&self, name: &str, kind: LeaseKind, token: &str, ttl: usize) -> bool;
+
+    /// Reset the ttl, only if still leased with the `token`.
+    async fn extend(&self, name: &str, kind: LeaseKind, token: &str, ttl: usize) -> bool;
+
+    /// Only if still leased with the `token`.
+    async fn release(&self, name: &str, kind: LeaseKind, token: &str);
+}
+
+/// Unique across the instances.
+fn new_token() -> String {
+    let nanos = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |elapsed| elapsed.as_nanos());
+
+    format!("{}:{}:{}", std::process::id(), nanos, TOKEN_COUNTER.fetch_add(1, Ordering::Relaxed))
+}
+
+/// A lease held on a name.
+/// Released with `release`, or in the background once dropped, so a panicking or dropped task won't leak it.
+pub struct Lease {
+    pub name: String,
+    pub kind: LeaseKind,
+    /// How long the lease is still valid after being taken or extended.
+    pub validity: Duration,
+    backend: LeaseBackendGlobal,
+    token: String,
+    taken_at: Instant,
+    released: bool,
+}
+
+impl Lease {
+    /// How long the lease is still valid from now.
+    #[inline]
+    pub fn remaining(&self) -> Duration {
+        self.validity.saturating_sub(self.taken_at.elapsed())
+    }
+
+    pub async fn release(mut self) {
+        self.released = true;
+        self.backend.release(&self.name, self.kind, &self.token).await;
+    }
+}
+
+impl Drop for Lease {
+    fn drop(&mut self) {
+        if self.released {
+            return;
+        }
+
+        let Ok(runtime) = tokio::runtime::Handle::try_current() else {
+            warn!(name = self.name, "Lease dropped outside of a runtime, held until it expires.");
+            return;
+        };
+
+        debug!(name = self.name, "Lease dropped, released in the background.");
+
+        let backend = Arc::clone(&self.backend);
+        let name = std::mem::take(&mut self.name);
+        let token = std::mem::take(&mut self.token);
+        let kind = self.kind;
+        runtime.spawn(async move { backend.release(&name, kind, &token).await });
+    }
+}
+
+/// Takes the leases of a kind, retried like the async op locks.
+pub(crate) struct Leaser {
+    backend: LeaseBackendGlobal,
+    retry_count: u32,
+    retry_delay: Duration,
+    clock_drift_factor: f64,
+}
+
+impl Leaser {
+    pub(crate) fn new(config: &AsyncOpGuardConfig, backend: &LeaseBackendGlobal) -> Self {
+        Leaser {
+            backend: Arc::clone(backend),
+            retry_count: config.retry_count,
+            retry_delay: config.retry_delay,
+            clock_drift_factor: config.clock_drift_factor,
+        }
+    }
+
+    pub(crate) async fn lease_once(&self, name: &str, kind: LeaseKind, ttl: usize) -> Option<Lease> {
+        let started_at = Instant::now();
+        let token = new_token();
+        if !self.backend.acquire(name, kind, &token, ttl).await {
+            return None;
+        }
+
+        let mut lease = Lease {
+            name: name.to_string(),
+            kind,
+            validity: Duration::ZERO,
+            backend: Arc::clone(&self.backend),
+            token,
+            taken_at: started_at,
+            released: false,
+        };
+
+        match validity(ttl, self.clock_drift_factor, started_at) {
+            Some(validity) => {
+                lease.validity = validity;
+                Some(lease)
+            }
+            None => {
+                lease.release().await;
+                None
+            }
+        }
+    }
+
+    pub(crate) async fn lease(&self, name: &str, kind: LeaseKind, ttl: usize) -> Result<Lease, LockError> {
+        retry(self.retry_count, self.retry_delay, || async { Ok(self.lease_once(name, kind, ttl).await) }).await
+    }
+
+    pub(crate) async fn lease_within(
+        &self,
+        name: &str,
+        kind: LeaseKind,
+        ttl: usize,
+        options: &AcquireOptions,
+    ) -> Result<Lease, AcquireError> {
+        retry_within(options, || async { Ok(self.lease_once(name, kind, ttl).await) }).await
+    }
+
+    pub(crate) async fn extend(&self, lease: &mut Lease, ttl: usize) -> Result<(), LockError> {
+        let started_at = Instant::now();
+        if !self.backend.extend(&lease.name, lease.kind, &lease.token, ttl).await {
+            return Err(LockError::Unavailable);
+        }
+
+        lease.validity = validity(ttl, self.clock_drift_factor, started_at).ok_or(LockError::Unavailable)?;
+        lease.taken_at = started_at;
+
+        Ok(())
+    }
+
+    pub(crate) async fn spawn<F>(
+        &self,
+        name: &str,
+        kind: LeaseKind,
+        ttl: usize,
+        async_task: F,
+    ) -> Result<F::Output, LockError>
+    where
+        F: Future,
+        F::Output: Send + Sync,
+    {
+        let lease = self.lease(name, kind, ttl).await?;
+        let result = async_task.await;
+        lease.release().await;
+
+        Ok(result)
+    }
+
+    pub(crate) async fn spawn_within<F>(
+        &self,
+        name: &str,
+        kind: LeaseKind,
+        ttl: usize,
+        options: &AcquireOptions,
+        async_task: F,
+    ) -> Result<F::Output, AcquireError>
+    where
+        F: Future,
+        F::Output: Send + Sync,
+    {
+        let lease = self.lease_within(name, kind, ttl, options).await?;
+        let result = async_task.await;
+        lease.release().await;
+
+        Ok(result)
+    }
+}
+
+/// On the first node of the redis backend, connected like the other redis clients with the `connection`.
+pub fn generate_lease_backend(
+    config: &AsyncOpGuardConfig,
+    connection: redis::RedisLeaseConnection,
+) -> anyhow::Result<LeaseBackendGlobal> {
+    Ok(match &config.backend {
+        AsyncOpGuardBackendConfig::Redis(uris) => {
+            let uri = uris.first().ok_or_else(|| anyhow::anyhow!("No lock redis uri."))?;
+
+            Arc::new(redis::RedisLeaseBackend::new(uri, connection)?)
+        }
+        AsyncOpGuardBackendConfig::Memory => Arc::new(memory::InMemoryLeaseBackend::default()),
+    })
+}
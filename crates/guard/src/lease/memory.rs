@@ -0,0 +1,169 @@
+/// In-process backend of the leases.
+/// Same ttl semantics as the redis one, but nothing is shared with other instances.
+use crate::lease::{LeaseBackend, LeaseKind, WRITER_WAITING_TTL};
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Duration;
+use tokio::time::Instant;
+
+/// The tokens and when they expire.
+#[derive(Default)]
+struct Leases {
+    permits: HashMap<String, Instant>,
+    readers: HashMap<String, Instant>,
+    writer: Option<(String, Instant)>,
+    writer_waiting_until: Option<Instant>,
+}
+
+impl Leases {
+    fn expire(&mut self, now: Instant) {
+        self.permits.retain(|_, expires_at| *expires_at > now);
+        self.readers.retain(|_, expires_at| *expires_at > now);
+        self.writer = self.writer.take().filter(|(_, expires_at)| *expires_at > now);
+        self.writer_waiting_until = self.writer_waiting_until.filter(|until| *until > now);
+    }
+
+    #[inline]
+    fn is_empty(&self) -> bool {
+        self.permits.is_empty()
+            && self.readers.is_empty()
+            && self.writer.is_none()
+            && self.writer_waiting_until.is_none()
+    }
+
+    /// The expiry of the `token`.
+    fn held(&mut self, kind: LeaseKind, token: &str) -> Option<&mut Instant> {
+        match kind {
+            LeaseKind::Permit { .. } => self.permits.get_mut(token),
+            LeaseKind::Read => self.readers.get_mut(token),
+            LeaseKind::Write => {
+                self.writer.as_mut().filter(|(held, _)| held == token).map(|(_, expires_at)| expires_at)
+            }
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct InMemoryLeaseBackend {
+    leases: Mutex<HashMap<String, Leases>>,
+}
+
+impl InMemoryLeaseBackend {
+    /// With the expired leases of the `name` removed.
+    fn with_leases<R>(&self, name: &str, f: impl FnOnce(&mut Leases, Instant) -> R) -> R {
+        let now = Instant::now();
+        // UNWRAP: Nothing panics while holding the lock.
+        let mut leases = self.leases.lock().unwrap();
+
+        let entry = leases.entry(name.to_string()).or_default();
+        entry.expire(now);
+        let result = f(entry, now);
+
+        if entry.is_empty() {
+            leases.remove(name);
+        }
+
+        result
+    }
+}
+
+#[async_trait::async_trait]
+impl LeaseBackend for InMemoryLeaseBackend {
+    async fn acquire(&self, name: &str, kind: LeaseKind, token: &str, ttl: usize) -> bool {
+        self.with_leases(name, |leases, now| {
+            let expires_at = now + Duration::from_millis(ttl as u64);
+
+            match kind {
+                LeaseKind::Permit { limit } if leases.permits.len() < limit => {
+                    leases.permits.insert(token.to_string(), expires_at);
+                    true
+                }
+                LeaseKind::Read if leases.writer.is_none() && leases.writer_waiting_until.is_none() => {
+                    leases.readers.insert(token.to_string(), expires_at);
+                    true
+                }
+                LeaseKind::Write if leases.writer.is_none() => {
+                    if !leases.readers.is_empty() {
+                        leases.writer_waiting_until =
+                            Some(now + Duration::from_millis(WRITER_WAITING_TTL.min(ttl) as u64));
+                        return false;
+                    }
+
+                    leases.writer = Some((token.to_string(), expires_at));
+                    leases.writer_waiting_until = None;
+                    true
+                }
+                _ => false,
+            }
+        })
+    }
+
+    async fn extend(&self, name: &str, kind: LeaseKind, token: &str, ttl: usize) -> bool {
+        self.with_leases(name, |leases, now| match leases.held(kind, token) {
+            Some(expires_at) => {
+                *expires_at = now + Duration::from_millis(ttl as u64);
+                true
+            }
+            None => false,
+        })
+    }
+
+    async fn release(&self, name: &str, kind: LeaseKind, token: &str) {
+        self.with_leases(name, |leases, _| match kind {
+            LeaseKind::Permit { .. } => {
+                leases.permits.remove(token);
+            }
+            LeaseKind::Read => {
+                leases.readers.remove(token);
+            }
+            LeaseKind::Write => {
+                leases.writer = leases.writer.take().filter(|(held, _)| held != token);
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::InMemoryLeaseBackend;
+    use crate::lease::{LeaseBackend, LeaseKind};
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn permits_expire() {
+        let backend = InMemoryLeaseBackend::default();
+        let kind = LeaseKind::Permit { limit: 2 };
+
+        assert!(backend.acquire("tests", kind, "a", 50).await);
+        assert!(backend.acquire("tests", kind, "b", 200).await);
+        assert!(!backend.acquire("tests", kind, "c", 50).await);
+
+        tokio::time::sleep(Duration::from_millis(60)).await;
+        assert!(!backend.extend("tests", kind, "a", 50).await);
+        assert!(backend.acquire("tests", kind, "c", 50).await);
+
+        backend.release("tests", kind, "b").await;
+        assert!(backend.acquire("tests", kind, "d", 50).await);
+    }
+
+    #[tokio::test]
+    async fn writer_waits_for_readers() {
+        let backend = InMemoryLeaseBackend::default();
+
+        assert!(backend.acquire("tests", LeaseKind::Read, "a", 1000).await);
+        assert!(backend.acquire("tests", LeaseKind::Read, "b", 1000).await);
+        assert!(!backend.acquire("tests", LeaseKind::Write, "w", 1000).await);
+
+        // No new readers while the writer waits.
+        assert!(!backend.acquire("tests", LeaseKind::Read, "c", 1000).await);
+
+        backend.release("tests", LeaseKind::Read, "a").await;
+        backend.release("tests", LeaseKind::Read, "b").await;
+        assert!(backend.acquire("tests", LeaseKind::Write, "w", 1000).await);
+        assert!(!backend.acquire("tests", LeaseKind::Read, "c", 1000).await);
+        assert!(!backend.acquire("tests", LeaseKind::Write, "x", 1000).await);
+
+        backend.release("tests", LeaseKind::Write, "w").await;
+        assert!(backend.acquire("tests", LeaseKind::Read, "c", 1000).await);
+    }
+}
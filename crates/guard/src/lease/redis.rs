@@ -0,0 +1,259 @@
+/// Redis backend of the leases, each change is a script, so it's atomic.
+/// The permits and the readers are sorted sets of the tokens, scored by when they expire on the server clock.
+use crate::lease::{LeaseBackend, LeaseKind, WRITER_WAITING_TTL};
+use fred::prelude::*;
+use fred::types::Script;
+use std::time::Duration;
+use tokio::sync::OnceCell;
+
+const DEFAULT_COMMAND_TIMEOUT: Duration = Duration::from_secs(10);
+const DEFAULT_RECONNECT_DELAY: Duration = Duration::from_secs(8);
+
+const NOW: &str = r#"
+local time = redis.call('TIME')
+local now = tonumber(time[1]) * 1000 + math.floor(tonumber(time[2]) / 1000)
+"#;
+
+/// KEYS: the sorted set. ARGV: the token, the ttl and the limit.
+const ACQUIRE_PERMIT: &str = r#"
+redis.call('ZREMRANGEBYSCORE', KEYS[1], '-inf', now)
+if redis.call('ZCARD', KEYS[1]) >= tonumber(ARGV[3]) then
+    return 0
+end
+redis.call('ZADD', KEYS[1], now + tonumber(ARGV[2]), ARGV[1])
+if redis.call('PTTL', KEYS[1]) < tonumber(ARGV[2]) then
+    redis.call('PEXPIRE', KEYS[1], ARGV[2])
+end
+return 1
+"#;
+
+/// KEYS: the readers, the writer and the waiting writer. ARGV: the token and the ttl.
+const ACQUIRE_READ: &str = r#"
+if redis.call('EXISTS', KEYS[2]) == 1 or redis.call('EXISTS', KEYS[3]) == 1 then
+    return 0
+end
+redis.call('ZREMRANGEBYSCORE', KEYS[1], '-inf', now)
+redis.call('ZADD', KEYS[1], now + tonumber(ARGV[2]), ARGV[1])
+if redis.call('PTTL', KEYS[1]) < tonumber(ARGV[2]) then
+    redis.call('PEXPIRE', KEYS[1], ARGV[2])
+end
+return 1
+"#;
+
+/// KEYS: the readers, the writer and the waiting writer. ARGV: the token, the ttl and the waiting ttl.
+const ACQUIRE_WRITE: &str = r#"
+if redis.call('EXISTS', KEYS[2]) == 1 then
+    return 0
+end
+redis.call('ZREMRANGEBYSCORE', KEYS[1], '-inf', now)
+if redis.call('ZCARD', KEYS[1]) > 0 then
+    redis.call('SET', KEYS[3], ARGV[1], 'PX', ARGV[3])
+    return 0
+end
+redis.call('SET', KEYS[2], ARGV[1], 'PX', ARGV[2])
+redis.call('DEL', KEYS[3])
+return 1
+"#;
+
+/// KEYS: the sorted set. ARGV: the token and the ttl.
+const EXTEND_MEMBER: &str = r#"
+local expires_at = redis.call('ZSCORE', KEYS[1], ARGV[1])
+if not expires_at or tonumber(expires_at) <= now then
+    return 0
+end
+redis.call('ZADD', KEYS[1], now + tonumber(ARGV[2]), ARGV[1])
+if redis.call('PTTL', KEYS[1]) < tonumber(ARGV[2]) then
+    redis.call('PEXPIRE', KEYS[1], ARGV[2])
+end
+return 1
+"#;
+
+/// KEYS: the writer. ARGV: the token and the ttl.
+const EXTEND_WRITE: &str = r#"
+if redis.call('GET', KEYS[1]) ~= ARGV[1] then
+    return 0
+end
+redis.call('PEXPIRE', KEYS[1], ARGV[2])
+return 1
+"#;
+
+/// KEYS: the writer. ARGV: the token.
+const RELEASE_WRITE: &str = r#"
+if redis.call('GET', KEYS[1]) == ARGV[1] then
+    redis.call('DEL', KEYS[1])
+end
+return 1
+"#;
+
+/// The hash tag keeps the keys of a name on the same cluster slot.
+#[inline]
+fn permits_key(name: &str) -> String {
+    format!("web_guard:semaphore:{{{name}}}")
+}
+
+#[inline]
+fn rw_keys(name: &str) -> Vec<String> {
+    ["readers", "writer", "writer_waiting"].iter().map(|key| format!("web_guard:rw:{{{name}}}:{key}")).collect()
+}
+
+/// How the leases connect, the same way as the other redis clients of the app, e.g. the distribute cache.
+#[derive(Clone, Debug)]
+pub struct RedisLeaseConnection {
+    /// Used unless the lease uri has its own, e.g. a `rediss://` one.
+    #[cfg(feature = "redis-tls")]
+    pub tls: Option<fred::types::TlsConfig>,
+    pub pool_size: usize,
+    pub command_timeout: Duration,
+    pub reconnect_policy: ReconnectPolicy,
+}
+
+impl Default for RedisLeaseConnection {
+    fn default() -> Self {
+        RedisLeaseConnection {
+            #[cfg(feature = "redis-tls")]
+            tls: None,
+            pool_size: 1,
+            command_timeout: DEFAULT_COMMAND_TIMEOUT,
+            reconnect_policy: ReconnectPolicy::new_constant(u32::MAX, DEFAULT_RECONNECT_DELAY.as_millis() as u32),
+        }
+    }
+}
+
+/// Run by its hash, the server caches it once run by its source.
+struct LeaseScript {
+    lua: String,
+    script: Script,
+}
+
+impl LeaseScript {
+    fn new(body: &str) -> Self {
+        let lua = format!("{NOW}{body}");
+        let script = Script::from_hash(sha1_smol::Sha1::from(&lua).digest().to_string());
+
+        LeaseScript { lua, script }
+    }
+}
+
+struct LeaseScripts {
+    acquire_permit: LeaseScript,
+    acquire_read: LeaseScript,
+    acquire_write: LeaseScript,
+    extend_member: LeaseScript,
+    extend_write: LeaseScript,
+    release_write: LeaseScript,
+}
+
+pub struct RedisLeaseBackend {
+    pool: RedisPool,
+    connected: OnceCell<()>,
+    scripts: LeaseScripts,
+}
+
+impl RedisLeaseBackend {
+    pub fn new(uri: &str, connection: RedisLeaseConnection) -> anyhow::Result<Self> {
+        #[allow(unused_mut)]
+        let mut config = RedisConfig::from_url(uri)?;
+        #[cfg(feature = "redis-tls")]
+        if config.tls.is_none() {
+            config.tls.clone_from(&connection.tls);
+        }
+
+        let pool = RedisPool::new(
+            config,
+            Some(PerformanceConfig { default_command_timeout: connection.command_timeout, ..Default::default() }),
+            None,
+            Some(connection.reconnect_policy),
+            connection.pool_size,
+        )?;
+
+        Ok(RedisLeaseBackend {
+            pool,
+            connected: OnceCell::new(),
+            scripts: LeaseScripts {
+                acquire_permit: LeaseScript::new(ACQUIRE_PERMIT),
+                acquire_read: LeaseScript::new(ACQUIRE_READ),
+                acquire_write: LeaseScript::new(ACQUIRE_WRITE),
+                extend_member: LeaseScript::new(EXTEND_MEMBER),
+                extend_write: LeaseScript::new(EXTEND_WRITE),
+                release_write: LeaseScript::new(RELEASE_WRITE),
+            },
+        })
+    }
+
+    /// Connected on the first use.
+    async fn pool(&self) -> &RedisPool {
+        self.connected
+            .get_or_init(|| async {
+                debug!(size = self.pool.size(), "Connecting to the redis leases.");
+
+                // No need to wait for being connected.
+                #[allow(clippy::let_underscore_future)]
+                let _ = self.pool.connect();
+                let _ = self.pool.wait_for_connect().await;
+            })
+            .await;
+
+        &self.pool
+    }
+
+    /// Failures are taken as `0`.
+    async fn eval(&self, script: &LeaseScript, keys: Vec<String>, args: Vec<String>) -> i64 {
+        let pool = self.pool().await;
+        let result = match script.script.evalsha::<i64, _, _, _>(pool, keys.clone(), args.clone()).await {
+            Err(error) if error.details().starts_with("NOSCRIPT") => {
+                pool.eval::<i64, _, _, _>(script.lua.as_str(), keys, args).await
+            }
+            result => result,
+        };
+
+        match result {
+            Ok(result) => result,
+            Err(error) => {
+                warn!(error = %error, "Failed to run the lease script.");
+                0
+            }
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl LeaseBackend for RedisLeaseBackend {
+    async fn acquire(&self, name: &str, kind: LeaseKind, token: &str, ttl: usize) -> bool {
+        let scripts = &self.scripts;
+        let (script, keys, mut args) = match kind {
+            LeaseKind::Permit { limit } => (&scripts.acquire_permit, vec![permits_key(name)], vec![limit.to_string()]),
+            LeaseKind::Read => (&scripts.acquire_read, rw_keys(name), vec![]),
+            LeaseKind::Write => (&scripts.acquire_write, rw_keys(name), vec![WRITER_WAITING_TTL.min(ttl).to_string()]),
+        };
+        args.splice(0..0, [token.to_string(), ttl.to_string()]);
+
+        self.eval(script, keys, args).await == 1
+    }
+
+    async fn extend(&self, name: &str, kind: LeaseKind, token: &str, ttl: usize) -> bool {
+        let args = vec![token.to_string(), ttl.to_string()];
+        let result = match kind {
+            LeaseKind::Permit { .. } => self.eval(&self.scripts.extend_member, vec![permits_key(name)], args).await,
+            LeaseKind::Read => self.eval(&self.scripts.extend_member, vec![rw_keys(name).swap_remove(0)], args).await,
+            LeaseKind::Write => self.eval(&self.scripts.extend_write, vec![rw_keys(name).swap_remove(1)], args).await,
+        };
+
+        result == 1
+    }
+
+    async fn release(&self, name: &str, kind: LeaseKind, token: &str) {
+        let result = match kind {
+            LeaseKind::Permit { .. } => self.pool().await.zrem::<(), _, _>(permits_key(name), token).await,
+            LeaseKind::Read => self.pool().await.zrem::<(), _, _>(rw_keys(name).swap_remove(0), token).await,
+            LeaseKind::Write => {
+                let keys = vec![rw_keys(name).swap_remove(1)];
+                self.eval(&self.scripts.release_write, keys, vec![token.to_string()]).await;
+                Ok(())
+            }
+        };
+
+        if let Err(error) = result {
+            warn!(error = %error, name, "Failed to release the lease.");
+        }
+    }
+}
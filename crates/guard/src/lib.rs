@@ -1 +1,8 @@
+#[macro_use]
+extern crate tracing;
+
 pub mod async_op;
+pub mod leader;
+pub mod lease;
+pub mod rw_lock;
+pub mod semaphore;
@@ -0,0 +1,162 @@
+/// Async read/write lock - many readers or a single writer on a name, across the instances.
+/// Once a writer waits, the new readers back off, so it's not starved.
+use crate::async_op::{AcquireError, AcquireOptions, AsyncOpGuardConfig};
+use crate::lease::{Lease, LeaseBackendGlobal, LeaseKind, Leaser};
+use rslock::LockError;
+use std::future::Future;
+use std::sync::Arc;
+
+pub type AsyncRwLockGlobal = Arc<AsyncRwLock>;
+
+pub struct AsyncRwLock {
+    leaser: Leaser,
+}
+
+impl AsyncRwLock {
+    /// Retried like the async op locks.
+    pub async fn read(&self, name: &str, ttl: usize) -> Result<Lease, LockError> {
+        self.leaser.lease(name, LeaseKind::Read, ttl).await
+    }
+
+    /// Retried like the async op locks.
+    pub async fn write(&self, name: &str, ttl: usize) -> Result<Lease, LockError> {
+        self.leaser.lease(name, LeaseKind::Write, ttl).await
+    }
+
+    pub async fn read_within(&self, name: &str, ttl: usize, options: &AcquireOptions) -> Result<Lease, AcquireError> {
+        self.leaser.lease_within(name, LeaseKind::Read, ttl, options).await
+    }
+
+    pub async fn write_within(&self, name: &str, ttl: usize, options: &AcquireOptions) -> Result<Lease, AcquireError> {
+        self.leaser.lease_within(name, LeaseKind::Write, ttl, options).await
+    }
+
+    /// Reset the ttl of the lock, if it's not expired yet.
+    pub async fn extend(&self, lock: &mut Lease, ttl: usize) -> Result<(), LockError> {
+        self.leaser.extend(lock, ttl).await
+    }
+
+    pub async fn spawn_read<F>(&self, name: &str, ttl: usize, async_task: F) -> Result<F::Output, LockError>
+    where
+        F: Future,
+        F::Output: Send + Sync,
+    {
+        self.leaser.spawn(name, LeaseKind::Read, ttl, async_task).await
+    }
+
+    pub async fn spawn_write<F>(&self, name: &str, ttl: usize, async_task: F) -> Result<F::Output, LockError>
+    where
+        F: Future,
+        F::Output: Send + Sync,
+    {
+        self.leaser.spawn(name, LeaseKind::Write, ttl, async_task).await
+    }
+
+    pub async fn spawn_read_within<F>(
+        &self,
+        name: &str,
+        ttl: usize,
+        options: &AcquireOptions,
+        async_task: F,
+    ) -> Result<F::Output, AcquireError>
+    where
+        F: Future,
+        F::Output: Send + Sync,
+    {
+        self.leaser.spawn_within(name, LeaseKind::Read, ttl, options, async_task).await
+    }
+
+    pub async fn spawn_write_within<F>(
+        &self,
+        name: &str,
+        ttl: usize,
+        options: &AcquireOptions,
+        async_task: F,
+    ) -> Result<F::Output, AcquireError>
+    where
+        F: Future,
+        F::Output: Send + Sync,
+    {
+        self.leaser.spawn_within(name, LeaseKind::Write, ttl, options, async_task).await
+    }
+}
+
+pub fn generate_async_rw_lock(config: &AsyncOpGuardConfig, backend: &LeaseBackendGlobal) -> AsyncRwLockGlobal {
+    Arc::new(AsyncRwLock { leaser: Leaser::new(config, backend) })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::generate_async_rw_lock;
+    use crate::async_op::{AcquireError, AcquireOptions, AsyncOpGuardBackendConfig, AsyncOpGuardConfig};
+    use crate::lease::{memory::InMemoryLeaseBackend, LeaseBackendGlobal};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    fn rw_lock() -> super::AsyncRwLockGlobal {
+        let config = AsyncOpGuardConfig::new(AsyncOpGuardBackendConfig::Memory);
+        let backend: LeaseBackendGlobal = Arc::new(InMemoryLeaseBackend::default());
+
+        generate_async_rw_lock(&config, &backend)
+    }
+
+    #[tokio::test]
+    async fn readers_share_writers_exclude() {
+        let rw_lock = rw_lock();
+        let (readers, writers, max_readers) = (AtomicUsize::new(0), AtomicUsize::new(0), AtomicUsize::new(0));
+        let options = AcquireOptions::new(Duration::from_secs(10));
+
+        let read = || {
+            rw_lock.spawn_read_within("tests", 1000, &options, async {
+                assert_eq!(writers.load(Ordering::SeqCst), 0);
+                let now_reading = readers.fetch_add(1, Ordering::SeqCst) + 1;
+                max_readers.fetch_max(now_reading, Ordering::SeqCst);
+                tokio::time::sleep(Duration::from_millis(50)).await;
+                readers.fetch_sub(1, Ordering::SeqCst);
+            })
+        };
+        let write = || {
+            rw_lock.spawn_write_within("tests", 1000, &options, async {
+                assert_eq!(readers.load(Ordering::SeqCst), 0);
+                assert_eq!(writers.fetch_add(1, Ordering::SeqCst), 0);
+                tokio::time::sleep(Duration::from_millis(50)).await;
+                writers.fetch_sub(1, Ordering::SeqCst);
+            })
+        };
+
+        let (reads, writes) = tokio::join!(
+            futures::future::join_all((0..4).map(|_| read())),
+            futures::future::join_all((0..2).map(|_| write())),
+        );
+
+        assert!(reads.iter().chain(writes.iter()).all(Result::is_ok));
+        assert!(max_readers.load(Ordering::SeqCst) > 1);
+    }
+
+    #[tokio::test]
+    async fn waiting_writer_comes_first() {
+        let rw_lock = rw_lock();
+        let reader = rw_lock.read("tests", 1000).await.unwrap();
+
+        let writer = {
+            let rw_lock = Arc::clone(&rw_lock);
+            tokio::spawn(async move {
+                let options = AcquireOptions::new(Duration::from_secs(5));
+                rw_lock.spawn_write_within("tests", 1000, &options, async {}).await
+            })
+        };
+        tokio::time::sleep(Duration::from_millis(50)).await;
+
+        // The new readers back off while the writer waits for the current one.
+        let options = AcquireOptions::new(Duration::from_millis(100));
+        assert!(matches!(
+            rw_lock.spawn_read_within("tests", 1000, &options, async {}).await,
+            Err(AcquireError::TimedOut(_))
+        ));
+
+        reader.release().await;
+        writer.await.unwrap().unwrap();
+        assert!(rw_lock.spawn_read("tests", 1000, async {}).await.is_ok());
+    }
+}
@@ -0,0 +1,114 @@
+/// Async semaphore - at most `permits` processes on a name at the same time, across the instances.
+/// E.g. at most 3 exports running at once.
+/// The permits are leased with a ttl, so the ones of a crashed process expire.
+use crate::async_op::{AcquireError, AcquireOptions, AsyncOpGuardConfig};
+use crate::lease::{Lease, LeaseBackendGlobal, LeaseKind, Leaser};
+use rslock::LockError;
+use std::future::Future;
+use std::sync::Arc;
+
+pub type AsyncSemaphoreGlobal = Arc<AsyncSemaphore>;
+
+pub struct AsyncSemaphore {
+    leaser: Leaser,
+}
+
+impl AsyncSemaphore {
+    /// One of the `permits`, retried like the async op locks.
+    pub async fn acquire(&self, name: &str, permits: usize, ttl: usize) -> Result<Lease, LockError> {
+        self.leaser.lease(name, LeaseKind::Permit { limit: permits }, ttl).await
+    }
+
+    /// Wait for one of the `permits` at most the `max_wait` of the `options`.
+    pub async fn acquire_within(
+        &self,
+        name: &str,
+        permits: usize,
+        ttl: usize,
+        options: &AcquireOptions,
+    ) -> Result<Lease, AcquireError> {
+        self.leaser.lease_within(name, LeaseKind::Permit { limit: permits }, ttl, options).await
+    }
+
+    /// Reset the ttl of the permit, if it's not expired yet.
+    pub async fn extend(&self, permit: &mut Lease, ttl: usize) -> Result<(), LockError> {
+        self.leaser.extend(permit, ttl).await
+    }
+
+    pub async fn spawn<F>(&self, name: &str, permits: usize, ttl: usize, async_task: F) -> Result<F::Output, LockError>
+    where
+        F: Future,
+        F::Output: Send + Sync,
+    {
+        self.leaser.spawn(name, LeaseKind::Permit { limit: permits }, ttl, async_task).await
+    }
+
+    pub async fn spawn_within<F>(
+        &self,
+        name: &str,
+        permits: usize,
+        ttl: usize,
+        options: &AcquireOptions,
+        async_task: F,
+    ) -> Result<F::Output, AcquireError>
+    where
+        F: Future,
+        F::Output: Send + Sync,
+    {
+        self.leaser.spawn_within(name, LeaseKind::Permit { limit: permits }, ttl, options, async_task).await
+    }
+}
+
+pub fn generate_async_semaphore(config: &AsyncOpGuardConfig, backend: &LeaseBackendGlobal) -> AsyncSemaphoreGlobal {
+    Arc::new(AsyncSemaphore { leaser: Leaser::new(config, backend) })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::generate_async_semaphore;
+    use crate::async_op::{AcquireOptions, AsyncOpGuardBackendConfig, AsyncOpGuardConfig};
+    use crate::lease::{memory::InMemoryLeaseBackend, LeaseBackendGlobal};
+    use rslock::LockError;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn at_most_the_permits() {
+        let config = AsyncOpGuardConfig::new(AsyncOpGuardBackendConfig::Memory);
+        let backend: LeaseBackendGlobal = Arc::new(InMemoryLeaseBackend::default());
+        let semaphore = generate_async_semaphore(&config, &backend);
+        let (running, max_running) = (AtomicUsize::new(0), AtomicUsize::new(0));
+        let options = AcquireOptions::new(Duration::from_secs(5));
+
+        let results = futures::future::join_all((0..6).map(|_| {
+            semaphore.spawn_within("tests", 2, 1000, &options, async {
+                let now_running = running.fetch_add(1, Ordering::SeqCst) + 1;
+                max_running.fetch_max(now_running, Ordering::SeqCst);
+                tokio::time::sleep(Duration::from_millis(50)).await;
+                running.fetch_sub(1, Ordering::SeqCst);
+            })
+        }))
+        .await;
+
+        assert!(results.iter().all(Result::is_ok));
+        assert_eq!(max_running.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn unavailable_once_all_taken() {
+        let config =
+            AsyncOpGuardConfig { retry_count: 1, ..AsyncOpGuardConfig::new(AsyncOpGuardBackendConfig::Memory) };
+        let backend: LeaseBackendGlobal = Arc::new(InMemoryLeaseBackend::default());
+        let semaphore = generate_async_semaphore(&config, &backend);
+
+        let first = semaphore.acquire("tests", 2, 1000).await.unwrap();
+        let second = semaphore.acquire("tests", 2, 1000).await.unwrap();
+        assert!(matches!(semaphore.spawn("tests", 2, 1000, async {}).await, Err(LockError::Unavailable)));
+
+        // Available again once released.
+        first.release().await;
+        assert_eq!(semaphore.spawn("tests", 2, 1000, async { 1 }).await.unwrap(), 1);
+        second.release().await;
+    }
+}
@@ -0,0 +1,104 @@
+use proc_macro::TokenStream;
+use quote::quote;
+use syn::parse::Parser;
+use syn::{parse_quote, Expr, ItemFn, LitStr};
+
+/// `"order:{path.id}"` into `"order:{}"` and `path.id`.
+fn parse_key(key: &LitStr) -> syn::Result<(String, Vec<Expr>)> {
+    let value = key.value();
+    let mut format = String::new();
+    let mut exprs = vec![];
+    let mut chars = value.chars().peekable();
+
+    while let Some(char) = chars.next() {
+        match char {
+            '{' if chars.peek() == Some(&'{') => {
+                chars.next();
+                format.push_str("{{");
+            }
+            '{' => {
+                let mut expr = String::new();
+                loop {
+                    match chars.next() {
+                        Some('}') => break,
+                        Some(char) => expr.push(char),
+                        None => return Err(syn::Error::new(key.span(), "Unclosed `{` in the key.")),
+                    }
+                }
+
+                exprs.push(syn::parse_str::<Expr>(&expr).map_err(|error| {
+                    syn::Error::new(key.span(), format!("Invalid expression `{expr}` in the key: {error}."))
+                })?);
+                format.push_str("{}");
+            }
+            '}' if chars.peek() == Some(&'}') => {
+                chars.next();
+                format.push_str("}}");
+            }
+            '}' => return Err(syn::Error::new(key.span(), "Unmatched `}` in the key.")),
+            char => format.push(char),
+        }
+    }
+
+    Ok((format, exprs))
+}
+
+/// In ms.
+fn parse_duration(duration: &LitStr) -> syn::Result<u64> {
+    humantime::parse_duration(&duration.value())
+        .map(|duration| duration.as_millis() as u64)
+        .map_err(|error| syn::Error::new(duration.span(), format!("Invalid duration: {error}.")))
+}
+
+fn expand(args: TokenStream, mut ast: ItemFn) -> syn::Result<TokenStream> {
+    let (mut key, mut ttl, mut wait) = (None, None, None);
+    syn::meta::parser(|meta| {
+        let value = meta.value()?.parse::<LitStr>()?;
+        if meta.path.is_ident("key") {
+            key = Some(value);
+        } else if meta.path.is_ident("ttl") {
+            ttl = Some(value);
+        } else if meta.path.is_ident("wait") {
+            wait = Some(value);
+        } else {
+            return Err(meta.error("Unsupported guarded property, expected `key`, `ttl` or `wait`."));
+        }
+
+        Ok(())
+    })
+    .parse(args)?;
+
+    if ast.sig.asyncness.is_none() {
+        return Err(syn::Error::new_spanned(ast.sig.fn_token, "Only async handlers can be guarded."));
+    }
+
+    let key = key.ok_or_else(|| syn::Error::new_spanned(&ast.sig.ident, "The guarded `key` is required."))?;
+    let ttl = ttl.ok_or_else(|| syn::Error::new_spanned(&ast.sig.ident, "The guarded `ttl` is required."))?;
+    let (format, exprs) = parse_key(&key)?;
+    let ttl = parse_duration(&ttl)? as usize;
+    let wait = wait.as_ref().map(parse_duration).transpose()?.unwrap_or_default();
+
+    ast.sig.inputs.push(parse_quote! {
+        __async_op_guard: ::web_core::guarded::AsyncOpGuardExtension
+    });
+
+    let block = &ast.block;
+    ast.block = parse_quote!({
+        let __guarded_key = ::std::format!(#format, #(#exprs),*);
+
+        ::web_core::guarded::guarded(
+            &__async_op_guard,
+            __guarded_key.as_bytes(),
+            #ttl,
+            ::std::time::Duration::from_millis(#wait),
+            async #block,
+        )
+        .await?
+    });
+
+    Ok(quote!(#ast).into())
+}
+
+pub fn impl_attr_guarded(args: TokenStream, ast: ItemFn) -> TokenStream {
+    expand(args, ast).unwrap_or_else(|error| error.to_compile_error().into())
+}
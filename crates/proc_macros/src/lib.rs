@@ -1,8 +1,23 @@
 use proc_macro::TokenStream;
 
+mod guarded;
 mod view_template;
 
 #[proc_macro_attribute]
 pub fn web_view_template(args: TokenStream, input: TokenStream) -> TokenStream {
     view_template::impl_attr_web_view_template(args, syn::parse(input).unwrap())
 }
+
+/// Serialize a handler on a resource key with the async op guard, e.g.
+/// `#[guarded(key = "order:{path.id}", ttl = "5s", wait = "2s")]`.
+/// - `key`, the `{}` parts are expressions of the extractors, `{{` and `}}` are escaped.
+/// - `ttl`, how long the lock is held at most, it must cover the whole handler.
+/// - `wait`, how long to wait for the lock, `0s` by default.
+///
+/// The handler must return a result whose error is from the `BoxedAppError`,
+/// a lock not taken in time fails it with a `409` server response.
+/// Requires the `AsyncOpGuardExtension` to be set into the requests.
+#[proc_macro_attribute]
+pub fn guarded(args: TokenStream, input: TokenStream) -> TokenStream {
+    guarded::impl_attr_guarded(args, syn::parse_macro_input!(input as syn::ItemFn))
+}
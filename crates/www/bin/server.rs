@@ -7,7 +7,11 @@ async fn main() -> Result<()> {
 
     let server_config = web_www::config::Server::from_env()?;
     let server_bind = (server_config.ip, server_config.port);
-    let app = Arc::new(web_www::app::App::new(server_config).await?);
+    let app = Arc::new(web_www::app::App::new(server_config, web_www::utils::warmup::loaders()).await?);
+
+    let state = web_www::app::AppState(app.clone());
+    let job_workers = app.job_queue.start(web_www::jobs::handlers(state.clone()));
+    let scheduler_workers = app.scheduler.start(web_www::schedules::tasks(state.clone())?);
 
     let server = ntex::web::HttpServer::new(move || {
         ntex::web::App::new()
@@ -19,10 +23,10 @@ async fn main() -> Result<()> {
                     .set_redirect_status(301)
                     .enable_interior_slash_ops(),
             )
-            // .wrap(web_www::middlewares::extensions::PrepareCaches)
+            .wrap(web_www::middlewares::extensions::PrepareCaches)
             .wrap(ntex::web::middleware::Compress::default())
             .wrap(ntex::web::middleware::DefaultHeaders::new().header("X-Powered-By", "ntex-rs"))
-            .state(web_www::app::AppState(app.clone()))
+            .state(state.clone())
             .configure(web_www::routes::build_routes)
     });
 
@@ -40,5 +44,9 @@ async fn main() -> Result<()> {
 
     server.run().await?;
 
+    scheduler_workers.shutdown().await;
+    job_workers.shutdown().await;
+    app.shutdown().await?;
+
     Ok(())
 }
@@ -4,20 +4,95 @@ use web_core::prelude::*;
 pub struct App {
     pub config: crate::config::Server,
     pub distribute_cache: web_cache::prelude::DistributeCacheGlobal,
-    pub memory_cache: web_cache::prelude::MemoryCacheGlobal,
+    pub memory_caches: web_cache::prelude::MemoryCacheRegistryGlobal,
+    pub tiered_cache: web_cache::prelude::TieredCacheGlobal,
+    pub event_bus: web_cache::prelude::EventBusGlobal,
+    pub job_queue: web_cache::prelude::JobQueueGlobal,
+    pub scheduler: crate::scheduler::SchedulerGlobal,
     pub async_op_guard: web_guard::async_op::AsyncOpGuardGlobal,
+    pub async_semaphore: web_guard::semaphore::AsyncSemaphoreGlobal,
+    pub async_rw_lock: web_guard::rw_lock::AsyncRwLockGlobal,
 }
 
 impl App {
-    pub async fn new(server_config: crate::config::Server) -> Result<Self> {
+    /// The caches are warmed up before returning, with the manifest configured and the `warmup_loaders`.
+    pub async fn new(
+        server_config: crate::config::Server,
+        warmup_loaders: web_cache::prelude::CacheWarmupLoaders,
+    ) -> Result<Self> {
+        let memory_caches = web_cache::generate_memory_cache_registry(server_config.memory_cache_policy.clone());
+        let distribute_cache = web_cache::generate_distribute_cache(
+            server_config.distribute_cache_config.clone(),
+            server_config.distribute_cache_codec,
+        )
+        .await?;
+
+        // Restored before warming up, so the manifest wins.
+        if let Some(path) = &server_config.memory_cache_snapshot.path {
+            let memory_cache = memory_caches.cache::<web_cache::prelude::MemoryCacheDefault>()?;
+            match memory_cache.restore(path).await {
+                Ok(restored) => info!(restored, "Memory cache restored."),
+                Err(error) => warn!(%error, "Failed to restore the memory cache."),
+            }
+
+            if let Some(interval) = server_config.memory_cache_snapshot.interval {
+                web_cache::spawn_memory_cache_snapshots(&memory_cache, path.clone(), interval);
+            }
+        }
+
+        if let Some(manifest) = &server_config.cache_warmup.manifest {
+            let manifest = web_cache::prelude::CacheWarmupManifest::from_path(manifest)?;
+            web_cache::warm_up_caches(
+                &manifest,
+                &warmup_loaders,
+                &memory_caches,
+                &distribute_cache,
+                server_config.cache_warmup.timeout,
+            )
+            .await;
+        }
+
+        let event_bus =
+            web_cache::generate_event_bus(server_config.event_bus_config, Arc::clone(&distribute_cache)).await?;
+        crate::events::spawn_cache_invalidation_listener(&event_bus, &memory_caches).await?;
+
+        let async_op_guard_config = &server_config.async_op_guard_config;
+        let lease_connection = match &server_config.distribute_cache_config {
+            web_cache::prelude::DistributeCacheConfig::Redis(config) => config.lease_connection(),
+            web_cache::prelude::DistributeCacheConfig::Memory => Default::default(),
+        };
+        let lease_backend = web_guard::lease::generate_lease_backend(async_op_guard_config, lease_connection)?;
+        let scheduler = crate::scheduler::generate(
+            server_config.scheduler_config.clone(),
+            async_op_guard_config,
+            &lease_backend,
+            Arc::clone(&distribute_cache),
+        );
+
         Ok(App {
-            distribute_cache: web_cache::generate_distribute_cache(server_config.distribute_cache_config.clone())
+            tiered_cache: web_cache::generate_tiered_cache("app", &memory_caches, Arc::clone(&distribute_cache), None)
                 .await?,
-            memory_cache: Arc::clone(&web_cache::MEMORY_CACHE),
-            async_op_guard: web_guard::async_op::generate_async_op_guard(server_config.async_op_guard_config),
+            distribute_cache,
+            memory_caches,
+            event_bus,
+            job_queue: web_cache::generate_job_queue(server_config.job_queue_config.clone()).await?,
+            scheduler,
+            async_op_guard: web_guard::async_op::generate_async_op_guard(async_op_guard_config.clone()),
+            async_semaphore: web_guard::semaphore::generate_async_semaphore(async_op_guard_config, &lease_backend),
+            async_rw_lock: web_guard::rw_lock::generate_async_rw_lock(async_op_guard_config, &lease_backend),
             config: server_config,
         })
     }
+
+    /// After the server stopped gracefully.
+    pub async fn shutdown(&self) -> Result<()> {
+        if let Some(path) = &self.config.memory_cache_snapshot.path {
+            let count = self.memory_caches.cache::<web_cache::prelude::MemoryCacheDefault>()?.snapshot(path).await?;
+            info!(count, "Memory cache snapshot written.");
+        }
+
+        Ok(())
+    }
 }
 
 #[derive(Clone)]
@@ -30,3 +105,132 @@ impl Deref for AppState {
         &self.0
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{App, AppState};
+    use std::future::Future;
+    use std::sync::Arc;
+    use std::time::Duration;
+    use web_cache::prelude::*;
+
+    /// Without redis, warmed up with the manifest.
+    async fn app() -> App {
+        App::new(
+            crate::config::Server {
+                cache_warmup: CacheWarmupConfig {
+                    manifest: Some(std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("warmup.toml")),
+                    ..Default::default()
+                },
+                ..crate::config::Server::testing()
+            },
+            crate::utils::warmup::loaders(),
+        )
+        .await
+        .unwrap()
+    }
+
+    /// Polled until true, failed after a few seconds.
+    async fn wait_until<Fut: Future<Output = bool>>(mut condition: impl FnMut() -> Fut) {
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while !condition().await {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .unwrap();
+    }
+
+    #[ntex::test]
+    async fn new_without_redis() {
+        let app = app().await;
+
+        // Warmed up with the manifest.
+        assert_eq!(app.distribute_cache.get_as::<serde_json::Value>("test").await.unwrap(), Some(serde_json::json!(1)));
+        let memory_cache = app.memory_caches.cache::<MemoryCacheDefault>().unwrap();
+        assert_eq!(memory_cache.get("test").await, Some(serde_json::json!(1)));
+    }
+
+    #[ntex::test]
+    async fn distribute_cache() {
+        let app = app().await;
+
+        app.distribute_cache.set("tests:app", b"1".to_vec(), None).await.unwrap();
+        assert_eq!(app.distribute_cache.incr_by("tests:app", 1).await.unwrap(), 2);
+    }
+
+    #[ntex::test]
+    async fn tiered_cache() {
+        let app = app().await;
+
+        app.tiered_cache.insert("tests:app", serde_json::json!(42)).await.unwrap();
+        assert_eq!(app.tiered_cache.get("tests:app").await.unwrap(), Some(serde_json::json!(42)));
+    }
+
+    #[ntex::test]
+    async fn event_bus() {
+        let app = app().await;
+
+        let mut subscription = app.event_bus.subscribe::<crate::events::UserLoggedOut>().await.unwrap();
+        let event = crate::events::UserLoggedOut { user_id: "42".into() };
+        app.event_bus.publish::<crate::events::UserLoggedOut>(&event).await.unwrap();
+        assert_eq!(subscription.recv().await.unwrap().event, event);
+    }
+
+    #[ntex::test]
+    async fn job_queue() {
+        let state = AppState(Arc::new(app().await));
+        state.distribute_cache.insert_tagged("tests:tagged", &1, None, &["tests"]).await.unwrap();
+
+        let job_workers = state.job_queue.start(crate::jobs::handlers(state.clone()));
+        let job = crate::jobs::InvalidateDistributeCacheTag { tag: "tests".into() };
+        state.job_queue.enqueue(&job).await.unwrap();
+        wait_until(|| async { state.distribute_cache.get("tests:tagged").await.unwrap().is_none() }).await;
+        job_workers.shutdown().await;
+    }
+
+    #[ntex::test]
+    async fn async_op_guard() {
+        let app = app().await;
+
+        let lock = app.async_op_guard.lock(b"tests:lock", 1000).await.unwrap();
+        assert!(app.async_op_guard.lock(b"tests:lock", 1000).await.is_err());
+        assert_eq!(app.async_op_guard.held()[0].resource, "tests:lock");
+        lock.release().await;
+        assert!(app.async_op_guard.lock(b"tests:lock", 1000).await.is_ok());
+    }
+
+    #[ntex::test]
+    async fn scheduler() {
+        let state = AppState(Arc::new(app().await));
+
+        let scheduler_workers = state.scheduler.start(crate::schedules::tasks(state.clone()).unwrap());
+        wait_until(|| async { scheduler_workers.is_leader() }).await;
+        scheduler_workers.shutdown().await;
+    }
+
+    #[ntex::test]
+    async fn async_semaphore() {
+        let app = app().await;
+
+        let permits = [
+            app.async_semaphore.acquire("tests:semaphore", 2, 1000).await.unwrap(),
+            app.async_semaphore.acquire("tests:semaphore", 2, 1000).await.unwrap(),
+        ];
+        assert!(app.async_semaphore.acquire("tests:semaphore", 2, 1000).await.is_err());
+        for permit in permits {
+            permit.release().await;
+        }
+        assert!(app.async_semaphore.spawn("tests:semaphore", 2, 1000, async { 1 }).await.is_ok());
+    }
+
+    #[ntex::test]
+    async fn async_rw_lock() {
+        let app = app().await;
+
+        let read = app.async_rw_lock.read("tests:rw", 1000).await.unwrap();
+        assert!(app.async_rw_lock.read("tests:rw", 1000).await.is_ok());
+        assert!(app.async_rw_lock.write("tests:rw", 1000).await.is_err());
+        read.release().await;
+    }
+}
@@ -2,23 +2,70 @@ use std::net::{IpAddr, Ipv4Addr};
 use web_cache::prelude::*;
 use web_core::prelude::*;
 
+use crate::scheduler::SchedulerConfig;
+
 #[derive(Clone)]
 pub struct Server {
     pub ip: IpAddr,
     pub port: u16,
     pub distribute_cache_config: DistributeCacheConfig,
+    pub distribute_cache_codec: CacheCodecConfig,
+    pub memory_cache_policy: MemoryCachePolicy,
+    pub memory_cache_snapshot: MemoryCacheSnapshotConfig,
+    pub cache_warmup: CacheWarmupConfig,
+    pub event_bus_config: EventBusConfig,
+    pub job_queue_config: JobQueueConfig,
+    pub scheduler_config: SchedulerConfig,
     pub async_op_guard_config: web_guard::async_op::AsyncOpGuardConfig,
+    /// Bearer token of the admin routes, they reject every request without it.
+    pub admin_token: Option<String>,
 }
 
 impl Server {
     pub fn from_env() -> Result<Self> {
-        let redis_config = web_env::required_var_parsed::<String>("REDIS_URI")?.leak();
+        // `redis` (default) or `memory`.
+        let distribute_cache_config = match web_env::var("DISTRIBUTE_CACHE_BACKEND")?.as_deref() {
+            None | Some("redis") => DistributeCacheConfig::Redis(RedisDistributeCacheConfig::from_env()?),
+            Some("memory") => DistributeCacheConfig::Memory,
+            Some(backend) => anyhow::bail!("Unknown DISTRIBUTE_CACHE_BACKEND `{backend}`."),
+        };
 
         Ok(Server {
             ip: web_env::var_parsed("IP")?.unwrap_or(Ipv4Addr::UNSPECIFIED.into()),
             port: web_env::var_parsed("PORT")?.unwrap_or(9527),
-            distribute_cache_config: DistributeCacheConfig::from_url(redis_config)?,
-            async_op_guard_config: redis_config,
+            distribute_cache_config,
+            distribute_cache_codec: CacheCodecConfig::from_env()?,
+            memory_cache_policy: MemoryCachePolicy::from_env()?,
+            memory_cache_snapshot: MemoryCacheSnapshotConfig::from_env()?,
+            cache_warmup: CacheWarmupConfig::from_env()?,
+            event_bus_config: EventBusConfig::from_env()?,
+            job_queue_config: JobQueueConfig::from_env()?,
+            scheduler_config: SchedulerConfig::from_env()?,
+            async_op_guard_config: web_guard::async_op::AsyncOpGuardConfig::from_env()?,
+            admin_token: web_env::var("ADMIN_TOKEN")?.filter(|token| !token.is_empty()),
         })
     }
 }
+
+#[cfg(test)]
+impl Server {
+    /// Without redis, nothing is warmed up or snapshotted.
+    pub(crate) fn testing() -> Self {
+        Server {
+            ip: Ipv4Addr::LOCALHOST.into(),
+            port: 9527,
+            distribute_cache_config: DistributeCacheConfig::Memory,
+            distribute_cache_codec: Default::default(),
+            memory_cache_policy: Default::default(),
+            memory_cache_snapshot: Default::default(),
+            cache_warmup: Default::default(),
+            event_bus_config: EventBusConfig::Memory,
+            job_queue_config: Default::default(),
+            scheduler_config: Default::default(),
+            async_op_guard_config: web_guard::async_op::AsyncOpGuardConfig::new(
+                web_guard::async_op::AsyncOpGuardBackendConfig::Memory,
+            ),
+            admin_token: None,
+        }
+    }
+}
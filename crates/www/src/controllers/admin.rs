@@ -0,0 +1,94 @@
+use crate::error::AdminError;
+use crate::events::CacheInvalidated;
+use crate::jobs::InvalidateDistributeCacheTag;
+use crate::models::controllers::{
+    CacheKeys, CacheKeysQuery, CacheOverview, LockOverview, MemoryCacheNamespaceOverview,
+};
+use ntex::web::types::Query;
+use std::sync::Arc;
+use web_cache::prelude::*;
+use web_core::handler_prelude::*;
+
+/// At most such keys are listed once.
+const CACHE_KEYS_LIMIT: usize = 1000;
+
+fn memory_cache_namespace(state: &crate::app::AppState, namespace: &str) -> AppResult<Arc<dyn MemoryCacheNamespace>> {
+    Ok(state
+        .memory_caches
+        .namespace_admin(namespace)
+        .ok_or_else(|| AdminError::CacheNamespaceNotFound(namespace.into()))?)
+}
+
+/// Entry counts, weighted sizes and counters of all the caches.
+pub async fn caches(state: State<crate::app::AppState>) -> AppResult<impl Responder> {
+    let mut memory = vec![];
+    for (namespace, cache) in state.memory_caches.namespaces() {
+        memory.push(MemoryCacheNamespaceOverview { namespace: namespace.to_string(), summary: cache.summary().await });
+    }
+
+    Ok(server_response_success!(data: CacheOverview { memory, distribute: state.distribute_cache.stats() }))
+}
+
+/// Keys of a memory cache namespace, filtered by the `prefix`.
+pub async fn memory_cache_keys(
+    namespace: Path<String>,
+    query: Query<CacheKeysQuery>,
+    state: State<crate::app::AppState>,
+) -> AppResult<impl Responder> {
+    let cache = memory_cache_namespace(&state, &namespace)?;
+    let limit = query.limit.unwrap_or(CACHE_KEYS_LIMIT).min(CACHE_KEYS_LIMIT);
+    let keys = cache.keys(query.prefix.as_deref().unwrap_or_default(), limit).await;
+
+    Ok(server_response_success!(data: CacheKeys { keys }))
+}
+
+pub async fn invalidate_memory_cache_key(
+    path: Path<(String, String)>,
+    state: State<crate::app::AppState>,
+) -> AppResult<impl Responder> {
+    let (namespace, key) = path.into_inner();
+    let invalidated = memory_cache_namespace(&state, &namespace)?.invalidate_key(&key).await;
+    state.event_bus.publish::<CacheInvalidated>(&CacheInvalidated { namespace, key: Some(key) }).await?;
+
+    Ok(server_response_success!(data: invalidated))
+}
+
+pub async fn invalidate_memory_cache_namespace(
+    namespace: Path<String>,
+    state: State<crate::app::AppState>,
+) -> AppResult<impl Responder> {
+    memory_cache_namespace(&state, &namespace)?.invalidate_all().await;
+    state
+        .event_bus
+        .publish::<CacheInvalidated>(&CacheInvalidated { namespace: namespace.into_inner(), key: None })
+        .await?;
+
+    Ok(server_response_success!())
+}
+
+pub async fn invalidate_distribute_cache_key(
+    key: Path<String>,
+    state: State<crate::app::AppState>,
+) -> AppResult<impl Responder> {
+    let invalidated = state.distribute_cache.del(&key).await?;
+
+    Ok(server_response_success!(data: invalidated))
+}
+
+/// Invalidated in the background, returns the id of the job.
+pub async fn invalidate_distribute_cache_tag(
+    tag: Path<String>,
+    state: State<crate::app::AppState>,
+) -> AppResult<impl Responder> {
+    let id = state.job_queue.enqueue(&InvalidateDistributeCacheTag { tag: tag.into_inner() }).await?;
+
+    Ok(server_response_success!(data: id))
+}
+
+/// Async op locks held by this instance, with the counters of the guard.
+pub async fn locks(state: State<crate::app::AppState>) -> AppResult<impl Responder> {
+    Ok(server_response_success!(data: LockOverview {
+        held: state.async_op_guard.held(),
+        stats: state.async_op_guard.stats()
+    }))
+}
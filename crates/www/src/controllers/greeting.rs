@@ -13,9 +13,9 @@ use web_core::handler_prelude::*;
 )]
 pub async fn hello(_req: HttpRequest, state: State<crate::app::AppState>) -> AppResult<&'static str> {
     // let distribute_cache = req.distribute_cache()?;
-    // let _test_val = distribute_cache.get::<Option<String>, _>("test").await?;
+    // let _test_val = distribute_cache.get("test").await?;
 
-    let _test_val = state.distribute_cache.get::<Option<String>, _>("test").await?;
+    let _test_val = state.distribute_cache.get("test").await?;
 
     info!("Ip: {}, Port: {}", state.config.ip, state.config.port);
 
@@ -30,23 +30,16 @@ pub async fn hello(_req: HttpRequest, state: State<crate::app::AppState>) -> App
         (status = 500, description = "Something wrong.", body = ServerResponseHelloWorld),
     ),
 )]
-pub async fn hello2(
-    state: State<crate::app::AppState>,
-    // _memory_cache: MemoryCacheExtension,
-) -> AppResult<impl Responder> {
-    // let memory_cache = memory_cache.read().await;
-    let memory_cache = state.memory_cache.read().await;
-
-    // Closure. Make sure we only insert once.
+pub async fn hello2(memory_cache: MemoryCacheExtension) -> AppResult<impl Responder> {
+    // Make sure we only load once.
     memory_cache_make_sure!(memory_cache, {
         let _test_val = memory_cache
-            .entry("test")
-            .or_insert_with(async {
+            .get_or_load("test".into(), None, async {
                 info!("-----------------------------------------");
 
-                json!(1)
+                Ok(json!(1))
             })
-            .await;
+            .await?;
     });
 
     info!(count = %memory_cache.entry_count()); // Must to be 1.
@@ -68,15 +61,25 @@ pub async fn hello3(req: HttpRequest, state: State<crate::app::AppState>) -> App
     // E.g. `ab -n 50 -c 10 http://localhost:5000/greeting/hello3`
     // Means concurrently request `hello3` with 10 client at the mean time and max requests are 500.
     // You will see that each second, here will only be one "-----" and one "22222" log.
-    // Others requests are blocking.
-    state
+    // Others requests are blocking, as long as the `LOCK_RETRY_COUNT` attempts last.
+    // The task takes as long as the ttl, so the lock is extended while it's running.
+    let output = state
         .async_op_guard
-        .spawn("AAA".as_bytes(), 1000, async {
-            tokio::time::sleep(std::time::Duration::from_secs(1)).await;
-            info!(uri = %req.uri(), "-----------------");
-        })
+        .spawn_watched(
+            "AAA".as_bytes(),
+            1000,
+            async {
+                tokio::time::sleep(std::time::Duration::from_secs(1)).await;
+                info!(uri = %req.uri(), "-----------------");
+            },
+            web_guard::async_op::LockLostPolicy::Continue,
+        )
         .await?;
 
+    if !output.is_exclusive() {
+        warn!("Lock lost while running, another process may have entered.");
+    }
+
     info!("2222222222222");
 
     Ok(server_response_success!(status_code: 300))
@@ -93,3 +96,89 @@ pub async fn hello3(req: HttpRequest, state: State<crate::app::AppState>) -> App
 pub async fn hello4(_state: State<crate::app::AppState>) -> AppResult<impl Responder> {
     Ok(server_response_success!(status_code: 401))
 }
+
+#[utoipa::path(
+    get,
+    path = "/greeting/hello5/{name}",
+    params(("name" = String, Path, description = "Who to greet.")),
+    responses(
+        (status = 200, description = "Hello world.", body = ServerResponseNullData),
+        (status = 409, description = "Still greeting the same one.", body = ServerResponseNullData),
+    ),
+)]
+// One greeting of the same name at a time, the others wait for it at most 2 seconds.
+#[guarded(key = "greeting:{name.0}", ttl = "5s", wait = "2s")]
+pub async fn hello5(name: Path<(String,)>) -> AppResult<impl Responder> {
+    tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+    info!(name = name.0, "Greeted.");
+
+    Ok(server_response_success!())
+}
+
+#[cfg(test)]
+mod tests {
+    use ntex::http::StatusCode;
+    use ntex::web::test::{init_service, TestRequest};
+    use ntex::web::{resource, App};
+    use std::sync::Arc;
+    use std::time::{Duration, Instant};
+    use web_guard::async_op::{AsyncOpGuardBackendConfig, AsyncOpGuardConfig};
+
+    use crate::app::AppState;
+    use crate::middlewares::extensions::PrepareCaches;
+
+    #[ntex::test]
+    async fn hello3_serialized() {
+        // Retried long enough to wait for the running one.
+        let config = crate::config::Server {
+            async_op_guard_config: AsyncOpGuardConfig {
+                retry_count: 30,
+                retry_delay: Duration::from_millis(100),
+                ..AsyncOpGuardConfig::new(AsyncOpGuardBackendConfig::Memory)
+            },
+            ..crate::config::Server::testing()
+        };
+        let state = AppState(Arc::new(crate::app::App::new(config, Default::default()).await.unwrap()));
+        let app =
+            init_service(App::new().state(state.clone()).service(resource("/greeting/hello3").to(super::hello3))).await;
+
+        let started_at = Instant::now();
+        let (first, second) = tokio::join!(
+            app.call(TestRequest::with_uri("/greeting/hello3").to_request()),
+            app.call(TestRequest::with_uri("/greeting/hello3").to_request()),
+        );
+        assert_eq!(first.unwrap().status(), StatusCode::MULTIPLE_CHOICES);
+        assert_eq!(second.unwrap().status(), StatusCode::MULTIPLE_CHOICES);
+
+        // One after the other, each holds the lock for a second.
+        assert!(started_at.elapsed() >= Duration::from_secs(2));
+        assert_eq!(state.async_op_guard.stats().acquired, 2);
+    }
+
+    #[ntex::test]
+    async fn hello5_guarded() {
+        let app = crate::app::App::new(crate::config::Server::testing(), Default::default()).await.unwrap();
+        let state = AppState(Arc::new(app));
+        let app = init_service(
+            App::new()
+                .state(state.clone())
+                .wrap(PrepareCaches)
+                .service(resource("/greeting/hello5/{name}").to(super::hello5)),
+        )
+        .await;
+
+        // Held by another greeting of the same name for a while.
+        let lock = state.async_op_guard.lock(b"greeting:bob", 5000).await.unwrap();
+        ntex::rt::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(200)).await;
+            lock.release().await;
+        });
+        let resp = app.call(TestRequest::with_uri("/greeting/hello5/bob").to_request()).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        // Not released within the wait.
+        let _lock = state.async_op_guard.lock(b"greeting:alice", 5000).await.unwrap();
+        let resp = app.call(TestRequest::with_uri("/greeting/hello5/alice").to_request()).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::CONFLICT);
+    }
+}
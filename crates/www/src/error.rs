@@ -11,3 +11,11 @@ pub enum MiddlewareError {
 }
 
 app_error_impl!(MiddlewareError, ntex::http::StatusCode::BAD_REQUEST);
+
+#[derive(thiserror::Error, Debug)]
+pub enum AdminError {
+    #[error("Memory cache namespace `{0}` not found.")]
+    CacheNamespaceNotFound(String),
+}
+
+app_error_impl!(AdminError, ntex::http::StatusCode::NOT_FOUND);
@@ -0,0 +1,73 @@
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use web_cache::prelude::*;
+use web_core::prelude::*;
+
+/// A runtime config value changed on one of the instances.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct ConfigChanged {
+    pub key: String,
+}
+
+impl EventChannel for ConfigChanged {
+    const NAME: &'static str = "config_changed";
+    type Event = Self;
+}
+
+/// Sessions of the user kept on other instances should be dropped.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct UserLoggedOut {
+    pub user_id: String,
+}
+
+impl EventChannel for UserLoggedOut {
+    const NAME: &'static str = "user_logged_out";
+    type Event = Self;
+}
+
+/// A memory cache key, or the whole namespace without it.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct CacheInvalidated {
+    pub namespace: String,
+    pub key: Option<String>,
+}
+
+impl EventChannel for CacheInvalidated {
+    const NAME: &'static str = "cache_invalidated";
+    type Event = Self;
+}
+
+/// Apply the memory cache invalidations of other instances, until the registry is dropped.
+pub async fn spawn_cache_invalidation_listener(
+    event_bus: &EventBus,
+    memory_caches: &MemoryCacheRegistryGlobal,
+) -> Result<tokio::task::JoinHandle<()>> {
+    let mut subscription = event_bus.subscribe::<CacheInvalidated>().await?;
+    let origin = event_bus.id().to_string();
+    let memory_caches = Arc::downgrade(memory_caches);
+
+    Ok(tokio::spawn(async move {
+        while let Some(message) = subscription.recv().await {
+            // Applied before being published.
+            if message.origin == origin {
+                continue;
+            }
+
+            let Some(memory_caches) = memory_caches.upgrade() else {
+                break;
+            };
+
+            let CacheInvalidated { namespace, key } = message.event;
+            let Some(cache) = memory_caches.namespace_admin(&namespace) else {
+                continue;
+            };
+
+            match key {
+                Some(key) => {
+                    cache.invalidate_key(&key).await;
+                }
+                None => cache.invalidate_all().await,
+            }
+        }
+    }))
+}
@@ -0,0 +1,27 @@
+use crate::app::AppState;
+use serde::{Deserialize, Serialize};
+use web_cache::prelude::*;
+use web_core::prelude::*;
+
+/// Tags may hold many keys, so they are invalidated outside the request.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct InvalidateDistributeCacheTag {
+    pub tag: String,
+}
+
+impl Job for InvalidateDistributeCacheTag {
+    const NAME: &'static str = "invalidate_distribute_cache_tag";
+}
+
+/// Handlers of every job, started with the server.
+pub fn handlers(state: AppState) -> JobHandlers {
+    JobHandlers::default().register(move |job: InvalidateDistributeCacheTag| {
+        let state = state.clone();
+        async move {
+            let count = state.distribute_cache.invalidate_tag(&job.tag).await?;
+            info!(tag = job.tag, count, "Distribute cache tag invalidated.");
+
+            Ok(())
+        }
+    })
+}
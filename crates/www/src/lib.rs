@@ -9,8 +9,12 @@ extern crate serde_json;
 
 pub mod app;
 pub mod config;
+pub mod events;
+pub mod jobs;
 pub mod middlewares;
 pub mod routes;
+pub mod scheduler;
+pub mod schedules;
 pub mod utils;
 
 mod constants;
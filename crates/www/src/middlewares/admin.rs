@@ -0,0 +1,123 @@
+use crate::app::AppState;
+use crate::error::MiddlewareError;
+use ntex::http::header::AUTHORIZATION;
+use web_core::middleware_prelude::*;
+
+const BEARER_PREFIX: &str = "Bearer ";
+const UNAUTHORIZED_MESSAGE: &str = "Admin token required.";
+
+/// Requires the `Authorization: Bearer <ADMIN_TOKEN>` header.
+/// Fails closed, every request is rejected while `ADMIN_TOKEN` is not set.
+pub struct RequireAdmin;
+
+impl<S> Middleware<S> for RequireAdmin {
+    type Service = RequireAdminInner<S>;
+
+    fn create(&self, service: S) -> Self::Service {
+        RequireAdminInner { service }
+    }
+}
+
+pub struct RequireAdminInner<S> {
+    service: S,
+}
+
+impl<S, Err> Service<WebRequest<Err>> for RequireAdminInner<S>
+where
+    S: Service<WebRequest<Err>, Response = WebResponse, Error = Error>,
+    Err: ErrorRenderer,
+{
+    type Response = WebResponse;
+    type Error = S::Error;
+
+    ntex::forward_poll_ready!(service);
+
+    async fn call(&self, req: WebRequest<Err>, ctx: ServiceCtx<'_, Self>) -> Result<Self::Response, Self::Error> {
+        let app_state = req.app_state::<AppState>().ok_or(MiddlewareError::AppStateMissing)?;
+        let Some(admin_token) = app_state.config.admin_token.as_deref() else {
+            warn!(path = req.path(), "Admin request rejected, ADMIN_TOKEN is not set.");
+            return Ok(unauthorized(req));
+        };
+
+        let token = req
+            .headers()
+            .get(AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix(BEARER_PREFIX));
+        if !token.is_some_and(|token| constant_time_eq(token.as_bytes(), admin_token.as_bytes())) {
+            return Ok(unauthorized(req));
+        }
+
+        ctx.call(&self.service, req).await
+    }
+}
+
+#[inline]
+fn unauthorized<Err: ErrorRenderer>(req: WebRequest<Err>) -> WebResponse {
+    ntex::web::HttpResponse::from(server_response_failed!(message: UNAUTHORIZED_MESSAGE, status_code: 401))
+        .into_web_response(req)
+}
+
+/// Compares every byte, so the time taken tells nothing about the common prefix.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (a, b)| diff | (a ^ b)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use ntex::http::header::AUTHORIZATION;
+    use ntex::http::StatusCode;
+    use ntex::web::test::{init_service, TestRequest};
+    use ntex::web::App;
+    use std::sync::Arc;
+
+    use crate::app::AppState;
+    use crate::config::Server;
+
+    async fn state(admin_token: Option<&str>) -> AppState {
+        let config = Server { admin_token: admin_token.map(str::to_string), ..Server::testing() };
+
+        AppState(Arc::new(crate::app::App::new(config, Default::default()).await.unwrap()))
+    }
+
+    #[ntex::test]
+    async fn require_admin_token() {
+        let app =
+            init_service(App::new().state(state(Some("secret")).await).configure(crate::routes::build_routes)).await;
+
+        let req = TestRequest::with_uri("/admin/caches").to_request();
+        assert_eq!(app.call(req).await.unwrap().status(), StatusCode::UNAUTHORIZED);
+
+        let req = TestRequest::with_uri("/admin/caches").header(AUTHORIZATION, "Bearer wrong").to_request();
+        assert_eq!(app.call(req).await.unwrap().status(), StatusCode::UNAUTHORIZED);
+
+        // Delete any key or tag, must never be reached without the token.
+        let req = TestRequest::with_uri("/admin/caches/distribute/keys/tests")
+            .method(ntex::http::Method::DELETE)
+            .to_request();
+        assert_eq!(app.call(req).await.unwrap().status(), StatusCode::UNAUTHORIZED);
+        let req = TestRequest::with_uri("/admin/caches/distribute/tags/tests")
+            .method(ntex::http::Method::DELETE)
+            .to_request();
+        assert_eq!(app.call(req).await.unwrap().status(), StatusCode::UNAUTHORIZED);
+
+        let req = TestRequest::with_uri("/admin/caches").header(AUTHORIZATION, "Bearer secret").to_request();
+        assert_eq!(app.call(req).await.unwrap().status(), StatusCode::OK);
+
+        // Lists the held resources.
+        let req = TestRequest::with_uri("/admin/locks").to_request();
+        assert_eq!(app.call(req).await.unwrap().status(), StatusCode::UNAUTHORIZED);
+        let req = TestRequest::with_uri("/admin/locks").header(AUTHORIZATION, "Bearer secret").to_request();
+        assert_eq!(app.call(req).await.unwrap().status(), StatusCode::OK);
+    }
+
+    #[ntex::test]
+    async fn fail_closed_without_admin_token() {
+        let app = init_service(App::new().state(state(None).await).configure(crate::routes::build_routes)).await;
+
+        for authorization in ["Bearer ", "Bearer secret"] {
+            let req = TestRequest::with_uri("/admin/caches").header(AUTHORIZATION, authorization).to_request();
+            assert_eq!(app.call(req).await.unwrap().status(), StatusCode::UNAUTHORIZED);
+        }
+    }
+}
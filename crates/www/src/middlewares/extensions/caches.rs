@@ -1,6 +1,7 @@
 use std::sync::Arc;
 
 use web_cache::prelude::*;
+use web_core::guarded::AsyncOpGuardExtension;
 use web_core::middleware_prelude::*;
 
 use crate::{app::AppState, utils::extensions};
@@ -35,7 +36,9 @@ where
             let app_state = req.app_state::<AppState>().unwrap();
 
             DistributeCacheExtension::set_into_req(&mut extensions, Arc::clone(&app_state.distribute_cache));
-            MemoryCacheExtension::set_into_req(&mut extensions, Arc::clone(&app_state.memory_cache));
+            MemoryCacheRegistryExtension::set_into_req(&mut extensions, Arc::clone(&app_state.memory_caches));
+            EventBusExtension::set_into_req(&mut extensions, Arc::clone(&app_state.event_bus));
+            AsyncOpGuardExtension::set_into_req(&mut extensions, Arc::clone(&app_state.async_op_guard));
         }
 
         ctx.call(&self.service, req).await
@@ -0,0 +1,427 @@
+use crate::app::AppState;
+use crate::middlewares::response_cache::{read_body, CachedResponse};
+use ntex::http::header::{HeaderName, AUTHORIZATION, CONTENT_LENGTH};
+use ntex::util::{Bytes, BytesMut};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fmt::Write;
+use std::rc::Rc;
+use std::time::Duration;
+use web_cache::prelude::*;
+use web_core::error::{AppResult, BoxedAppError};
+use web_core::middleware_prelude::*;
+use web_guard::async_op::{AcquireError, AcquireOptions, LockLostPolicy};
+
+pub const IDEMPOTENCY_KEY_HEADER_NAME: &str = "idempotency-key";
+pub const IDEMPOTENT_REPLAYED_HEADER_NAME: &str = "idempotent-replayed";
+const IDEMPOTENCY_NAMESPACE: &str = "web_www:idempotency";
+/// Extended while the first request runs, only expires after it died.
+const DEFAULT_LOCK_TTL: Duration = Duration::from_secs(60);
+/// Same as the default `PayloadConfig` of ntex.
+const DEFAULT_PAYLOAD_LIMIT: usize = 262_144;
+/// The key of the requests without the `Authorization` header.
+const ANONYMOUS_CALLER: &str = "anonymous";
+
+const IN_PROGRESS_MESSAGE: &str = "A request with the same idempotency key is still in progress.";
+const KEY_REUSED_MESSAGE: &str = "The idempotency key was used with a different request.";
+const PAYLOAD_TOO_LARGE_MESSAGE: &str = "The payload is too large to be idempotent.";
+
+#[derive(Serialize, Deserialize)]
+struct StoredResponse {
+    /// Of the first request.
+    fingerprint: String,
+    response: CachedResponse,
+}
+
+struct IdempotencyConfig {
+    ttl: Duration,
+    lock_ttl: Duration,
+    payload_limit: usize,
+}
+
+/// Runs the requests with the same `Idempotency-Key` header once, the retries get the first response replayed.
+/// A retry while the first one is still running gets `409`, the same key with another request gets `422`.
+/// The keys of the callers are apart, told by the `Authorization` header.
+/// The payload is buffered to be fingerprinted, a larger one than the `payload_limit` gets `413`.
+/// Server errors are not stored, so they may be retried.
+pub struct Idempotency {
+    config: Rc<IdempotencyConfig>,
+}
+
+impl Idempotency {
+    /// The first responses are kept for the `ttl`.
+    pub fn new(ttl: Duration) -> Self {
+        Idempotency {
+            config: Rc::new(IdempotencyConfig {
+                ttl,
+                lock_ttl: DEFAULT_LOCK_TTL,
+                payload_limit: DEFAULT_PAYLOAD_LIMIT,
+            }),
+        }
+    }
+
+    /// How long the first request holds the key without being extended, e.g. after the instance died.
+    pub fn lock_ttl(mut self, lock_ttl: Duration) -> Self {
+        // UNWRAP: Only called when building, the config is not shared yet.
+        Rc::get_mut(&mut self.config).unwrap().lock_ttl = lock_ttl;
+
+        self
+    }
+
+    /// In bytes, `256KiB` by default, keep it in line with the `PayloadConfig` of the app.
+    pub fn payload_limit(mut self, payload_limit: usize) -> Self {
+        // UNWRAP: Only called when building, the config is not shared yet.
+        Rc::get_mut(&mut self.config).unwrap().payload_limit = payload_limit;
+
+        self
+    }
+}
+
+impl<S> Middleware<S> for Idempotency {
+    type Service = IdempotencyInner<S>;
+
+    fn create(&self, service: S) -> Self::Service {
+        IdempotencyInner { service, config: Rc::clone(&self.config) }
+    }
+}
+
+pub struct IdempotencyInner<S> {
+    service: S,
+    config: Rc<IdempotencyConfig>,
+}
+
+impl<S, Err> Service<WebRequest<Err>> for IdempotencyInner<S>
+where
+    S: Service<WebRequest<Err>, Response = WebResponse, Error = Error>,
+    Err: ErrorRenderer,
+{
+    type Response = WebResponse;
+    type Error = S::Error;
+
+    ntex::forward_poll_ready!(service);
+
+    async fn call(&self, mut req: WebRequest<Err>, ctx: ServiceCtx<'_, Self>) -> Result<Self::Response, Self::Error> {
+        let Some(idempotency_key) =
+            req.headers().get(IDEMPOTENCY_KEY_HEADER_NAME).and_then(|value| value.to_str().ok()).map(str::to_string)
+        else {
+            return ctx.call(&self.service, req).await;
+        };
+
+        let app_state = req.app_state::<AppState>().ok_or(crate::error::MiddlewareError::AppStateMissing)?.clone();
+        let key =
+            format!("{}:{}:{}:{}:{}", IDEMPOTENCY_NAMESPACE, caller(&req), req.method(), req.path(), idempotency_key);
+        let Some(body) = read_payload(&mut req, self.config.payload_limit).await? else {
+            return Ok(rejected(req, StatusCode::PAYLOAD_TOO_LARGE, PAYLOAD_TOO_LARGE_MESSAGE));
+        };
+        let fingerprint = fingerprint(&req, &body);
+
+        if let Some(stored) = stored(&app_state.distribute_cache, &key).await {
+            return replay(req, stored, &fingerprint);
+        }
+
+        let lock_ttl = self.config.lock_ttl.as_millis() as usize;
+        let async_op_guard = &app_state.async_op_guard;
+        // A single attempt, a retry never waits for the first one.
+        let once = AcquireOptions::new(Duration::ZERO);
+        let lock = match async_op_guard.acquire_within(format!("{key}:lock").as_bytes(), lock_ttl, &once).await {
+            Ok(lock) => lock,
+            Err(AcquireError::TimedOut(_)) => return Ok(rejected(req, StatusCode::CONFLICT, IN_PROGRESS_MESSAGE)),
+            Err(error) => return Err(BoxedAppError::from(error).into()),
+        };
+
+        // Finished between the lookup and the lock.
+        if let Some(stored) = stored(&app_state.distribute_cache, &key).await {
+            lock.release().await;
+
+            return replay(req, stored, &fingerprint);
+        }
+
+        // Held until stored, however long the request runs.
+        let handled = async {
+            let res = ctx.call(&self.service, req).await?;
+            if res.status().is_server_error() {
+                return Ok(res);
+            }
+
+            let (res, body) = read_body(res).await?;
+            let stored = StoredResponse { fingerprint, response: CachedResponse::new(&res, &body) };
+            if let Err(error) = app_state.distribute_cache.set_as(&key, &stored, Some(self.config.ttl)).await {
+                warn!(error = %error, key, "Failed to store the idempotent response.");
+            }
+
+            Ok(res)
+        };
+
+        let output = async_op_guard.watch(lock, lock_ttl, handled, LockLostPolicy::Continue).await;
+        if !output.is_exclusive() {
+            warn!(key, "Idempotency key lost while running, a retry may have run again.");
+        }
+
+        // UNWRAP: Never cancelled, it runs on once the lock is lost.
+        output.into_output().unwrap()
+    }
+}
+
+async fn stored(distribute_cache: &DistributeCacheGlobal, key: &str) -> Option<StoredResponse> {
+    distribute_cache
+        .get_as::<StoredResponse>(key)
+        .await
+        .inspect_err(|error| warn!(error = %error, key, "Failed to read the idempotent response."))
+        .ok()
+        .flatten()
+}
+
+fn replay<Err: ErrorRenderer>(
+    req: WebRequest<Err>,
+    stored: StoredResponse,
+    fingerprint: &str,
+) -> Result<WebResponse, Error> {
+    if stored.fingerprint != fingerprint {
+        return Ok(rejected(req, StatusCode::UNPROCESSABLE_ENTITY, KEY_REUSED_MESSAGE));
+    }
+
+    let mut res = stored.response.into_response()?.into_web_response(req);
+    res.headers_mut()
+        .insert(HeaderName::from_static(IDEMPOTENT_REPLAYED_HEADER_NAME), HeaderValue::from_static("true"));
+
+    Ok(res)
+}
+
+#[inline]
+fn rejected<Err: ErrorRenderer>(req: WebRequest<Err>, status_code: StatusCode, message: &str) -> WebResponse {
+    ntex::web::HttpResponse::from(server_response_failed!(message: message, status_code: status_code))
+        .into_web_response(req)
+}
+
+/// Buffer the whole payload, and put it back. `None` once larger than the `limit`.
+async fn read_payload<Err>(req: &mut WebRequest<Err>, limit: usize) -> AppResult<Option<Bytes>> {
+    let content_length = req
+        .headers()
+        .get(CONTENT_LENGTH)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse::<usize>().ok());
+    if content_length.is_some_and(|content_length| content_length > limit) {
+        return Ok(None);
+    }
+
+    let mut payload = req.take_payload();
+    let mut bytes = BytesMut::new();
+
+    while let Some(chunk) = payload.recv().await {
+        bytes.extend_from_slice(&chunk.map_err(|error| anyhow_error(error.to_string().into()))?);
+        if bytes.len() > limit {
+            return Ok(None);
+        }
+    }
+
+    let bytes = bytes.freeze();
+    let mut replayed = ntex::http::h1::Payload::empty();
+    replayed.unread_data(bytes.clone());
+    req.set_payload(replayed.into());
+
+    Ok(Some(bytes))
+}
+
+/// Hex of the sha256 of the `Authorization` header, so the credentials are never stored.
+fn caller<Err>(req: &WebRequest<Err>) -> String {
+    match req.headers().get(AUTHORIZATION) {
+        Some(authorization) => hex(&Sha256::digest(authorization.as_bytes())),
+        None => ANONYMOUS_CALLER.to_string(),
+    }
+}
+
+/// Method + path + query + body, hex of the sha256.
+fn fingerprint<Err>(req: &WebRequest<Err>, body: &[u8]) -> String {
+    let mut hasher = Sha256::new();
+    for part in [req.method().as_str().as_bytes(), req.path().as_bytes(), req.query_string().as_bytes(), body] {
+        hasher.update((part.len() as u64).to_be_bytes());
+        hasher.update(part);
+    }
+
+    hex(&hasher.finalize())
+}
+
+#[inline]
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().fold(String::new(), |mut hex, byte| {
+        // UNWRAP: Writing into a string never fails.
+        write!(hex, "{byte:02x}").unwrap();
+        hex
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use ntex::http::{Method, StatusCode};
+    use ntex::web::test::{init_service, read_body, TestRequest};
+    use ntex::web::{resource, App, HttpResponse};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use super::{
+        Idempotency, ANONYMOUS_CALLER, IDEMPOTENCY_KEY_HEADER_NAME, IDEMPOTENCY_NAMESPACE,
+        IDEMPOTENT_REPLAYED_HEADER_NAME,
+    };
+    use crate::app::AppState;
+    use ntex::http::header::AUTHORIZATION;
+
+    macro_rules! init_service {
+        ($path: expr, $state: expr, $counter: expr) => {{
+            let counter = Arc::clone(&$counter);
+
+            init_service(
+                App::new().state($state.clone()).service(
+                    resource($path)
+                        .wrap(
+                            Idempotency::new(Duration::from_secs(60))
+                                .lock_ttl(Duration::from_millis(90))
+                                .payload_limit(16),
+                        )
+                        .to(move |body: String| {
+                            let count = counter.fetch_add(1, Ordering::SeqCst) + 1;
+
+                            async move {
+                                // Runs longer than the lock ttl.
+                                if body == "slow" {
+                                    tokio::time::sleep(Duration::from_millis(200)).await;
+                                }
+
+                                HttpResponse::Created().body(format!("count: {count}, body: {body}"))
+                            }
+                        }),
+                ),
+            )
+            .await
+        }};
+    }
+
+    macro_rules! request {
+        ($path: expr, $key: expr, $body: expr) => {{
+            TestRequest::with_uri($path)
+                .method(Method::POST)
+                .header(IDEMPOTENCY_KEY_HEADER_NAME, $key)
+                .set_payload($body)
+                .to_request()
+        }};
+    }
+
+    async fn state() -> AppState {
+        AppState(Arc::new(crate::app::App::new(crate::config::Server::testing(), Default::default()).await.unwrap()))
+    }
+
+    #[ntex::test]
+    async fn replay_first_response() {
+        let counter = Arc::new(AtomicUsize::new(0));
+        let state = state().await;
+        let app = init_service!("/tests/replay", state, counter);
+
+        let resp = app.call(request!("/tests/replay", "a", "1")).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::CREATED);
+        assert!(resp.headers().get(IDEMPOTENT_REPLAYED_HEADER_NAME).is_none());
+        assert_eq!(read_body(resp).await, "count: 1, body: 1");
+
+        let resp = app.call(request!("/tests/replay", "a", "1")).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::CREATED);
+        assert_eq!(resp.headers().get(IDEMPOTENT_REPLAYED_HEADER_NAME).unwrap(), "true");
+        assert_eq!(read_body(resp).await, "count: 1, body: 1");
+
+        // Another key, or none.
+        let resp = app.call(request!("/tests/replay", "b", "1")).await.unwrap();
+        assert_eq!(read_body(resp).await, "count: 2, body: 1");
+        let req = TestRequest::with_uri("/tests/replay").method(Method::POST).set_payload("1").to_request();
+        assert_eq!(read_body(app.call(req).await.unwrap()).await, "count: 3, body: 1");
+
+        assert_eq!(counter.load(Ordering::SeqCst), 3);
+    }
+
+    #[ntex::test]
+    async fn reject_another_body() {
+        let counter = Arc::new(AtomicUsize::new(0));
+        let state = state().await;
+        let app = init_service!("/tests/another-body", state, counter);
+
+        let resp = app.call(request!("/tests/another-body", "a", "1")).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::CREATED);
+
+        let resp = app.call(request!("/tests/another-body", "a", "2")).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::UNPROCESSABLE_ENTITY);
+
+        assert_eq!(counter.load(Ordering::SeqCst), 1);
+    }
+
+    #[ntex::test]
+    async fn conflict_in_progress() {
+        let counter = Arc::new(AtomicUsize::new(0));
+        let state = state().await;
+        let app = init_service!("/tests/in-progress", state, counter);
+
+        // Held like by the first request.
+        let resource = format!("{}:{}:POST:/tests/in-progress:a:lock", IDEMPOTENCY_NAMESPACE, ANONYMOUS_CALLER);
+        let lock = state.async_op_guard.lock(resource.as_bytes(), 60_000).await.unwrap();
+
+        let resp = app.call(request!("/tests/in-progress", "a", "1")).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::CONFLICT);
+
+        lock.release().await;
+        let resp = app.call(request!("/tests/in-progress", "a", "1")).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::CREATED);
+
+        assert_eq!(counter.load(Ordering::SeqCst), 1);
+    }
+
+    #[ntex::test]
+    async fn apart_by_caller() {
+        let counter = Arc::new(AtomicUsize::new(0));
+        let state = state().await;
+        let app = init_service!("/tests/caller", state, counter);
+
+        let request = |authorization: &str| {
+            TestRequest::with_uri("/tests/caller")
+                .method(Method::POST)
+                .header(IDEMPOTENCY_KEY_HEADER_NAME, "a")
+                .header(AUTHORIZATION, authorization)
+                .set_payload("1")
+                .to_request()
+        };
+
+        assert_eq!(read_body(app.call(request("Bearer alice")).await.unwrap()).await, "count: 1, body: 1");
+        assert_eq!(read_body(app.call(request("Bearer bob")).await.unwrap()).await, "count: 2, body: 1");
+        assert_eq!(read_body(app.call(request("Bearer alice")).await.unwrap()).await, "count: 1, body: 1");
+        assert_eq!(read_body(app.call(request!("/tests/caller", "a", "1")).await.unwrap()).await, "count: 3, body: 1");
+    }
+
+    #[ntex::test]
+    async fn reject_large_payload() {
+        let counter = Arc::new(AtomicUsize::new(0));
+        let state = state().await;
+        let app = init_service!("/tests/large", state, counter);
+
+        let resp = app.call(request!("/tests/large", "a", "0123456789abcdefg")).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::PAYLOAD_TOO_LARGE);
+
+        // Not limited without the key.
+        let req =
+            TestRequest::with_uri("/tests/large").method(Method::POST).set_payload("0123456789abcdefg").to_request();
+        assert_eq!(app.call(req).await.unwrap().status(), StatusCode::CREATED);
+
+        assert_eq!(counter.load(Ordering::SeqCst), 1);
+    }
+
+    #[ntex::test]
+    async fn hold_key_while_running() {
+        let counter = Arc::new(AtomicUsize::new(0));
+        let state = state().await;
+        let app = init_service!("/tests/slow", state, counter);
+
+        // Retried after the lock ttl, still running the first one.
+        let (first, retry) = tokio::join!(app.call(request!("/tests/slow", "a", "slow")), async {
+            tokio::time::sleep(Duration::from_millis(120)).await;
+            app.call(request!("/tests/slow", "a", "slow")).await
+        });
+        assert_eq!(first.unwrap().status(), StatusCode::CREATED);
+        assert_eq!(retry.unwrap().status(), StatusCode::CONFLICT);
+
+        assert_eq!(counter.load(Ordering::SeqCst), 1);
+    }
+}
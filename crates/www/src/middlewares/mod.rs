@@ -1,4 +1,7 @@
+pub mod admin;
 pub mod extensions;
 pub mod globals;
+pub mod idempotency;
 pub mod prerequisites;
+pub mod response_cache;
 pub mod view;
@@ -0,0 +1,386 @@
+use crate::app::AppState;
+use ntex::http::body::{Body, MessageBody, ResponseBody};
+use ntex::http::header::{HeaderName, CACHE_CONTROL, SET_COOKIE};
+use ntex::util::BytesMut;
+use serde::{Deserialize, Serialize};
+use std::rc::Rc;
+use std::sync::Arc;
+use std::time::Duration;
+use web_cache::prelude::*;
+use web_core::error::AppResult;
+use web_core::middleware_prelude::*;
+
+pub const RESPONSE_CACHE_HEADER_NAME: &str = "x-cache";
+const RESPONSE_CACHE_NAMESPACE: &str = "web_www:responses";
+const CACHE_HIT: HeaderValue = HeaderValue::from_static("HIT");
+const CACHE_MISS: HeaderValue = HeaderValue::from_static("MISS");
+const CACHE_BYPASS: HeaderValue = HeaderValue::from_static("BYPASS");
+
+/// Responses in the memory cache registry.
+struct ResponseCaches;
+
+impl MemoryCacheName for ResponseCaches {
+    const NAME: &'static str = RESPONSE_CACHE_NAMESPACE;
+    type Key = MemoryCacheKey;
+    type Value = CachedResponse;
+}
+
+/// Expired by the ttl of the store.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct CachedResponse {
+    status: u16,
+    headers: Vec<(String, Vec<u8>)>,
+    body: Vec<u8>,
+}
+
+impl MemoryCacheWeigh for CachedResponse {
+    fn weigh(&self) -> usize {
+        self.status.weigh() + self.headers.weigh() + self.body.weigh()
+    }
+}
+
+impl CachedResponse {
+    /// With the `body` read by `read_body`.
+    pub(crate) fn new(res: &WebResponse, body: &[u8]) -> Self {
+        CachedResponse {
+            status: res.status().as_u16(),
+            headers: res
+                .headers()
+                .iter()
+                .map(|(name, value)| (name.as_str().to_string(), value.as_bytes().to_vec()))
+                .collect(),
+            body: body.to_vec(),
+        }
+    }
+
+    pub(crate) fn into_response(self) -> AppResult<ntex::web::HttpResponse> {
+        let mut response = ntex::web::HttpResponse::with_body(
+            StatusCode::from_u16(self.status).map_err(|_| anyhow_error("Invalid cached status code.".into()))?,
+            self.body.into(),
+        );
+
+        for (name, value) in self.headers {
+            response.headers_mut().append(
+                HeaderName::try_from(name).map_err(|_| anyhow_error("Invalid cached header name.".into()))?,
+                HeaderValue::from_bytes(&value)?,
+            );
+        }
+
+        Ok(response)
+    }
+}
+
+#[derive(Default, Clone)]
+pub enum ResponseCacheStore {
+    #[default]
+    Memory,
+    Distribute,
+}
+
+struct ResponseCacheConfig {
+    store: ResponseCacheStore,
+    ttl: Duration,
+    vary: Vec<HeaderName>,
+}
+
+/// Caches the whole `GET`/`HEAD` responses with the `200` status.
+/// Wrap the routes one by one, so each of them can have its own ttl.
+pub struct ResponseCache {
+    config: Rc<ResponseCacheConfig>,
+}
+
+impl ResponseCache {
+    pub fn new(store: ResponseCacheStore, ttl: Duration) -> Self {
+        ResponseCache { config: Rc::new(ResponseCacheConfig { store, ttl, vary: vec![] }) }
+    }
+
+    pub fn memory(ttl: Duration) -> Self {
+        Self::new(ResponseCacheStore::Memory, ttl)
+    }
+
+    pub fn distribute(ttl: Duration) -> Self {
+        Self::new(ResponseCacheStore::Distribute, ttl)
+    }
+
+    /// Request headers which make a difference to the response, e.g. `Accept-Language`.
+    pub fn vary(mut self, header_name: HeaderName) -> Self {
+        // UNWRAP: Only called when building, the config is not shared yet.
+        Rc::get_mut(&mut self.config).unwrap().vary.push(header_name);
+
+        self
+    }
+}
+
+impl<S> Middleware<S> for ResponseCache {
+    type Service = ResponseCacheInner<S>;
+
+    fn create(&self, service: S) -> Self::Service {
+        ResponseCacheInner { service, config: Rc::clone(&self.config) }
+    }
+}
+
+pub struct ResponseCacheInner<S> {
+    service: S,
+    config: Rc<ResponseCacheConfig>,
+}
+
+impl<S, Err> Service<WebRequest<Err>> for ResponseCacheInner<S>
+where
+    S: Service<WebRequest<Err>, Response = WebResponse, Error = Error>,
+    Err: ErrorRenderer,
+{
+    type Response = WebResponse;
+    type Error = S::Error;
+
+    ntex::forward_poll_ready!(service);
+
+    async fn call(&self, req: WebRequest<Err>, ctx: ServiceCtx<'_, Self>) -> Result<Self::Response, Self::Error> {
+        if !matches!(*req.method(), Method::GET | Method::HEAD) {
+            return ctx.call(&self.service, req).await;
+        }
+
+        let key = self.cache_key(&req);
+        let app_state = req.app_state::<AppState>().ok_or(crate::error::MiddlewareError::AppStateMissing)?;
+        let store = match self.config.store {
+            ResponseCacheStore::Memory => {
+                CachedResponseStore::Memory(app_state.memory_caches.cache::<ResponseCaches>()?)
+            }
+            ResponseCacheStore::Distribute => CachedResponseStore::Distribute(Arc::clone(&app_state.distribute_cache)),
+        };
+
+        let bypass = header_contains_no_cache(&req);
+        if !bypass {
+            match store.get(&key).await {
+                Ok(Some(cached)) => {
+                    let mut res = cached.into_response()?.into_web_response(req);
+                    res.headers_mut().insert(HeaderName::from_static(RESPONSE_CACHE_HEADER_NAME), CACHE_HIT);
+
+                    return Ok(res);
+                }
+                Ok(None) => {}
+                Err(error) => warn!(error = %error, key, "Failed to read the cached response."),
+            }
+        }
+
+        let res = ctx.call(&self.service, req).await?;
+        if res.status() != StatusCode::OK || !is_storable(&res) {
+            return Ok(res);
+        }
+
+        let (mut res, body) = read_body(res).await?;
+        let cached = CachedResponse::new(&res, &body);
+
+        if let Err(error) = store.insert(&key, cached, self.config.ttl).await {
+            warn!(error = %error, key, "Failed to store the response.");
+        }
+
+        res.headers_mut().insert(
+            HeaderName::from_static(RESPONSE_CACHE_HEADER_NAME),
+            if bypass { CACHE_BYPASS } else { CACHE_MISS },
+        );
+
+        Ok(res)
+    }
+}
+
+impl<S> ResponseCacheInner<S> {
+    /// Method + path + sorted query + vary header values, each prefixed by its length so they never run together.
+    fn cache_key<Err>(&self, req: &WebRequest<Err>) -> String {
+        let mut query = req.query_string().split('&').filter(|pair| !pair.is_empty()).collect::<Vec<_>>();
+        query.sort_unstable();
+
+        let mut key = format!("{}:{}:{}?{}", RESPONSE_CACHE_NAMESPACE, req.method(), req.path(), query.join("&"));
+        for header_name in self.config.vary.iter() {
+            key.push('|');
+            key.push_str(header_name.as_str());
+            key.push('=');
+
+            for value in req.headers().get_all(header_name) {
+                key.push_str(&value.len().to_string());
+                key.push(':');
+                key.push_str(&String::from_utf8_lossy(value.as_bytes()));
+            }
+        }
+
+        key
+    }
+}
+
+enum CachedResponseStore {
+    Memory(MemoryCacheGlobal<MemoryCacheKey, CachedResponse>),
+    Distribute(DistributeCacheGlobal),
+}
+
+impl CachedResponseStore {
+    async fn get(&self, key: &str) -> Result<Option<CachedResponse>> {
+        Ok(match self {
+            CachedResponseStore::Memory(cache) => cache.get(key).await,
+            CachedResponseStore::Distribute(cache) => cache.get_as::<CachedResponse>(key).await?,
+        })
+    }
+
+    async fn insert(&self, key: &str, cached: CachedResponse, ttl: Duration) -> Result<()> {
+        match self {
+            CachedResponseStore::Memory(cache) => cache.insert_with_ttl(key.into(), cached, ttl).await,
+            CachedResponseStore::Distribute(cache) => cache.set_as(key, &cached, Some(ttl)).await?,
+        }
+
+        Ok(())
+    }
+}
+
+#[inline]
+fn header_contains_no_cache<Err>(req: &WebRequest<Err>) -> bool {
+    req.headers().get_all(CACHE_CONTROL).any(|value| value.to_str().is_ok_and(|value| value.contains("no-cache")))
+}
+
+/// Private or per-user responses must not be shared.
+#[inline]
+fn is_storable(res: &WebResponse) -> bool {
+    !res.headers().contains_key(SET_COOKIE)
+        && !res
+            .headers()
+            .get_all(CACHE_CONTROL)
+            .any(|value| value.to_str().is_ok_and(|value| value.contains("no-store") || value.contains("private")))
+}
+
+/// Buffer the whole body, and put it back.
+pub(crate) async fn read_body(mut res: WebResponse) -> AppResult<(WebResponse, ntex::util::Bytes)> {
+    let mut body = res.take_body();
+    let mut bytes = BytesMut::new();
+
+    while let Some(chunk) = std::future::poll_fn(|cx| body.poll_next_chunk(cx)).await {
+        bytes.extend_from_slice(&chunk.map_err(|error| anyhow_error(error.to_string().into()))?);
+    }
+
+    let bytes = bytes.freeze();
+    let body = bytes.clone();
+
+    Ok((res.map_body(|_, _| ResponseBody::Other(Body::Bytes(body))), bytes))
+}
+
+#[cfg(test)]
+mod tests {
+    use ntex::http::{header, header::HeaderValue, Method, StatusCode};
+    use ntex::web::test::{init_service, read_body, TestRequest};
+    use ntex::web::{resource, App, HttpResponse};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use super::{ResponseCache, RESPONSE_CACHE_HEADER_NAME};
+    use crate::app::AppState;
+
+    macro_rules! init_service {
+        ($path: expr, $counter: expr) => {{
+            let counter = Arc::clone(&$counter);
+            let app = crate::app::App::new(crate::config::Server::testing(), Default::default()).await.unwrap();
+
+            init_service(App::new().state(AppState(Arc::new(app))).service(
+                resource($path).wrap(ResponseCache::memory(Duration::from_secs(60)).vary(header::ACCEPT_LANGUAGE)).to(
+                    move || {
+                        let count = counter.fetch_add(1, Ordering::SeqCst) + 1;
+
+                        async move { HttpResponse::Ok().body(format!("count: {count}")) }
+                    },
+                ),
+            ))
+            .await
+        }};
+    }
+
+    macro_rules! cache_status {
+        ($resp: expr) => {
+            $resp.headers().get(RESPONSE_CACHE_HEADER_NAME).map(|value| value.to_str().unwrap().to_string())
+        };
+    }
+
+    #[ntex::test]
+    async fn hit_after_miss() {
+        let counter = Arc::new(AtomicUsize::new(0));
+        let app = init_service!("/tests/hit-after-miss", counter);
+
+        let resp = app.call(TestRequest::with_uri("/tests/hit-after-miss?b=2&a=1").to_request()).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(cache_status!(resp).as_deref(), Some("MISS"));
+        assert_eq!(read_body(resp).await, "count: 1");
+
+        // The order of the query pairs makes no difference.
+        let resp = app.call(TestRequest::with_uri("/tests/hit-after-miss?a=1&b=2").to_request()).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(cache_status!(resp).as_deref(), Some("HIT"));
+        assert_eq!(read_body(resp).await, "count: 1");
+
+        assert_eq!(counter.load(Ordering::SeqCst), 1);
+    }
+
+    #[ntex::test]
+    async fn vary_and_query() {
+        let counter = Arc::new(AtomicUsize::new(0));
+        let app = init_service!("/tests/vary-and-query", counter);
+
+        let resp = app.call(TestRequest::with_uri("/tests/vary-and-query").to_request()).await.unwrap();
+        assert_eq!(cache_status!(resp).as_deref(), Some("MISS"));
+
+        let resp = app.call(TestRequest::with_uri("/tests/vary-and-query?a=1").to_request()).await.unwrap();
+        assert_eq!(cache_status!(resp).as_deref(), Some("MISS"));
+
+        let mut req = TestRequest::with_uri("/tests/vary-and-query").to_request();
+        req.headers_mut().insert(header::ACCEPT_LANGUAGE, HeaderValue::from_static("zh-CN"));
+        let resp = app.call(req).await.unwrap();
+        assert_eq!(cache_status!(resp).as_deref(), Some("MISS"));
+
+        assert_eq!(counter.load(Ordering::SeqCst), 3);
+    }
+
+    #[ntex::test]
+    async fn vary_values_apart() {
+        let counter = Arc::new(AtomicUsize::new(0));
+        let app = init_service!("/tests/vary-values-apart", counter);
+
+        for values in [["en", "-US"], ["en-", "US"]] {
+            let mut req = TestRequest::with_uri("/tests/vary-values-apart").to_request();
+            for value in values {
+                req.headers_mut().append(header::ACCEPT_LANGUAGE, HeaderValue::from_static(value));
+            }
+            let resp = app.call(req).await.unwrap();
+            assert_eq!(cache_status!(resp).as_deref(), Some("MISS"));
+        }
+
+        assert_eq!(counter.load(Ordering::SeqCst), 2);
+    }
+
+    #[ntex::test]
+    async fn bypass_no_cache() {
+        let counter = Arc::new(AtomicUsize::new(0));
+        let app = init_service!("/tests/bypass-no-cache", counter);
+
+        let resp = app.call(TestRequest::with_uri("/tests/bypass-no-cache").to_request()).await.unwrap();
+        assert_eq!(cache_status!(resp).as_deref(), Some("MISS"));
+
+        let mut req = TestRequest::with_uri("/tests/bypass-no-cache").to_request();
+        req.headers_mut().insert(header::CACHE_CONTROL, HeaderValue::from_static("no-cache"));
+        let resp = app.call(req).await.unwrap();
+        assert_eq!(cache_status!(resp).as_deref(), Some("BYPASS"));
+        assert_eq!(read_body(resp).await, "count: 2");
+
+        // Refreshed by the bypassed one.
+        let resp = app.call(TestRequest::with_uri("/tests/bypass-no-cache").to_request()).await.unwrap();
+        assert_eq!(cache_status!(resp).as_deref(), Some("HIT"));
+        assert_eq!(read_body(resp).await, "count: 2");
+    }
+
+    #[ntex::test]
+    async fn skip_unsafe_methods() {
+        let counter = Arc::new(AtomicUsize::new(0));
+        let app = init_service!("/tests/skip-unsafe-methods", counter);
+
+        for _ in 0..2 {
+            let req = TestRequest::with_uri("/tests/skip-unsafe-methods").method(Method::POST).to_request();
+            let resp = app.call(req).await.unwrap();
+            assert!(cache_status!(resp).is_none());
+        }
+
+        assert_eq!(counter.load(Ordering::SeqCst), 2);
+    }
+}
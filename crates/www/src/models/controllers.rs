@@ -7,3 +7,33 @@ use web_core::response::ServerResponse;
 pub struct HelloWorld {
     pub greeting: &'static str,
 }
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct CacheKeysQuery {
+    pub prefix: Option<String>,
+    pub limit: Option<usize>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct CacheKeys {
+    pub keys: Vec<String>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct MemoryCacheNamespaceOverview {
+    pub namespace: String,
+    #[serde(flatten)]
+    pub summary: web_cache::prelude::MemoryCacheSummary,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct CacheOverview {
+    pub memory: Vec<MemoryCacheNamespaceOverview>,
+    pub distribute: web_cache::prelude::CacheStatsSnapshot,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct LockOverview {
+    pub held: Vec<web_guard::async_op::monitor::HeldAsyncOpLock>,
+    pub stats: web_guard::async_op::monitor::AsyncOpGuardStatsSnapshot,
+}
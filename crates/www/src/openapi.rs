@@ -26,7 +26,8 @@ struct ServerResponseSchema<D> {
         controllers::greeting::hello,
         controllers::greeting::hello2,
         controllers::greeting::hello3,
-        controllers::greeting::hello4
+        controllers::greeting::hello4,
+        controllers::greeting::hello5
     ),
     components(schemas(
         HelloWorld,
@@ -1,9 +1,20 @@
 use web_core::route_prelude::*;
 
+use ntex::http::header;
+use std::time::Duration;
+
 use crate::constants::{INTERNAL_SERVER_ERROR_REQ_PATH, NOT_FOUND_REQ_PATH};
+use crate::middlewares::admin::RequireAdmin;
+use crate::middlewares::idempotency::Idempotency;
+use crate::middlewares::response_cache::ResponseCache;
 
 fn build_view_routes(cfg: &mut ServiceConfig) {
-    cfg.service(resource("/").wrap(crate::middlewares::view::prerequisites()).to(crate::controllers::views::index));
+    cfg.service(
+        resource("/")
+            .wrap(crate::middlewares::view::prerequisites())
+            .wrap(ResponseCache::memory(Duration::from_secs(60)).vary(header::ACCEPT_LANGUAGE))
+            .to(crate::controllers::views::index),
+    );
 
     cfg.service(scope("/").wrap(crate::middlewares::view::prerequisites()).service((
         resource(NOT_FOUND_REQ_PATH).route(get().to(crate::controllers::views::not_found)),
@@ -16,6 +27,8 @@ fn build_greeting_routes(cfg: &mut ServiceConfig) {
 
     cfg.service(resource("/greeting/hello3").to(crate::controllers::greeting::hello3));
 
+    cfg.service(resource("/greeting/hello5/{name}").to(crate::controllers::greeting::hello5));
+
     cfg.service(
         scope("/greeting") // Third one.
             .wrap(crate::middlewares::prerequisites::RequireJson) // Second one. // First middleware.
@@ -26,14 +39,38 @@ fn build_greeting_routes(cfg: &mut ServiceConfig) {
     );
 }
 
+fn build_admin_routes(cfg: &mut ServiceConfig) {
+    cfg.service(
+        scope("/admin").wrap(RequireAdmin).service((
+            scope("/caches").service((
+                resource("").route(get().to(crate::controllers::admin::caches)),
+                resource("/memory/{namespace}")
+                    .route(delete().to(crate::controllers::admin::invalidate_memory_cache_namespace)),
+                resource("/memory/{namespace}/keys").route(get().to(crate::controllers::admin::memory_cache_keys)),
+                resource("/memory/{namespace}/keys/{key}")
+                    .route(delete().to(crate::controllers::admin::invalidate_memory_cache_key)),
+                resource("/distribute/keys/{key}")
+                    .route(delete().to(crate::controllers::admin::invalidate_distribute_cache_key)),
+                // Enqueues a job, so the retries must not enqueue it again.
+                resource("/distribute/tags/{tag}")
+                    .wrap(Idempotency::new(Duration::from_secs(24 * 60 * 60)))
+                    .route(delete().to(crate::controllers::admin::invalidate_distribute_cache_tag)),
+            )),
+            resource("/locks").route(get().to(crate::controllers::admin::locks)),
+        )),
+    );
+}
+
 fn build_swagger_routes(cfg: &mut ServiceConfig) {
     let swagger_config =
         std::sync::Arc::new(utoipa_swagger_ui::Config::new(["/swagger-ui/swagger.json"]).use_base_layout());
 
     cfg.service(
-        scope("/swagger-ui")
-            .state(swagger_config)
-            .service(resource("/{tail}*").route(get().to(crate::openapi::get_swagger))),
+        scope("/swagger-ui").state(swagger_config).service(
+            resource("/{tail}*")
+                .wrap(ResponseCache::memory(Duration::from_secs(10 * 60)))
+                .route(get().to(crate::openapi::get_swagger)),
+        ),
     );
 }
 
@@ -41,6 +78,7 @@ pub fn build_routes(cfg: &mut ServiceConfig) {
     // Swagger.
     build_swagger_routes(cfg);
 
+    build_admin_routes(cfg);
     build_greeting_routes(cfg);
     build_view_routes(cfg);
 }
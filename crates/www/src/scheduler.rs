@@ -0,0 +1,350 @@
+/// Scheduled tasks - Cron expressions run on exactly one instance, e.g. refreshing the caches or cleaning up.
+/// The instances elect a leader on a renewable lease of the async op guard backend, only the leader runs the tasks,
+/// and another instance takes over once the leader died.
+/// A tick is skipped while the previous run of the task is still going on,
+/// the last runs of every task are kept in the distribute cache.
+use chrono::{DateTime, Utc};
+use cron::Schedule;
+use serde::{Deserialize, Serialize};
+use std::future::Future;
+use std::pin::Pin;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::watch;
+use web_cache::prelude::DistributeCacheGlobal;
+use web_core::prelude::*;
+use web_core::utils::unix_millis;
+use web_guard::async_op::{AsyncOpGuardConfig, CancellationToken};
+use web_guard::leader::Leadership;
+use web_guard::lease::LeaseBackendGlobal;
+
+pub type SchedulerGlobal = Arc<Scheduler>;
+
+const SCHEDULER_NAMESPACE: &str = "web_www:scheduler";
+const DEFAULT_LEADER_TTL: Duration = Duration::from_secs(15);
+const DEFAULT_HISTORY_SIZE: usize = 20;
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(300);
+
+/// Leadership, timeout and history of the scheduled tasks, read from the env:
+/// - `SCHEDULER_ENABLED`, `true` by default, `false` never leads, so no task runs on the instance.
+/// - `SCHEDULER_LEADER_TTL`, `15s` by default, how long the leadership of a dead leader is held.
+/// - `SCHEDULER_TIMEOUT`, `5m` by default, a run taking longer is failed.
+/// - `SCHEDULER_HISTORY_SIZE`, `20` by default, the last runs kept for each task.
+#[derive(Clone, Debug)]
+pub struct SchedulerConfig {
+    pub enabled: bool,
+    pub leader_ttl: Duration,
+    pub timeout: Duration,
+    pub history_size: usize,
+}
+
+impl Default for SchedulerConfig {
+    fn default() -> Self {
+        SchedulerConfig {
+            enabled: true,
+            leader_ttl: DEFAULT_LEADER_TTL,
+            timeout: DEFAULT_TIMEOUT,
+            history_size: DEFAULT_HISTORY_SIZE,
+        }
+    }
+}
+
+#[inline]
+fn duration_var(key: &str) -> Result<Option<Duration>> {
+    Ok(web_env::var_parsed::<humantime::Duration>(key)?.map(Duration::from))
+}
+
+impl SchedulerConfig {
+    pub fn from_env() -> Result<Self> {
+        let leader_ttl = duration_var("SCHEDULER_LEADER_TTL")?.unwrap_or(DEFAULT_LEADER_TTL);
+        if leader_ttl < Duration::from_secs(1) {
+            anyhow::bail!("SCHEDULER_LEADER_TTL must be at least 1s.");
+        }
+
+        Ok(SchedulerConfig {
+            enabled: web_env::var_parsed::<bool>("SCHEDULER_ENABLED")?.unwrap_or(true),
+            leader_ttl,
+            timeout: duration_var("SCHEDULER_TIMEOUT")?.unwrap_or(DEFAULT_TIMEOUT),
+            history_size: web_env::var_parsed::<usize>("SCHEDULER_HISTORY_SIZE")?.unwrap_or(DEFAULT_HISTORY_SIZE),
+        })
+    }
+}
+
+/// A run of a scheduled task.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct ScheduledRun {
+    /// The tick of the cron expression, unix time in milliseconds.
+    pub scheduled_at: u64,
+    pub started_at: u64,
+    pub finished_at: u64,
+    /// Id of the instance which ran it.
+    pub instance: String,
+    /// Why it failed.
+    pub error: Option<String>,
+}
+
+pub type ScheduledTaskHandler = Box<dyn Fn() -> Pin<Box<dyn Future<Output = Result<()>> + Send>> + Send + Sync>;
+
+struct ScheduledTask {
+    name: &'static str,
+    schedule: Schedule,
+    handler: ScheduledTaskHandler,
+}
+
+/// The tasks and their cron expressions.
+#[derive(Default)]
+pub struct ScheduledTasks {
+    tasks: Vec<ScheduledTask>,
+}
+
+impl ScheduledTasks {
+    /// The `expression` starts with the seconds and is in UTC, e.g. `0 */10 * * * *` runs every 10 minutes.
+    pub fn register<F, Fut>(mut self, name: &'static str, expression: &str, handler: F) -> Result<Self>
+    where
+        F: Fn() -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<()>> + Send + 'static,
+    {
+        if self.tasks.iter().any(|task| task.name == name) {
+            anyhow::bail!("Scheduled task `{name}` registered twice.");
+        }
+
+        let schedule = Schedule::from_str(expression)
+            .map_err(|error| anyhow::anyhow!("Invalid cron expression `{expression}` of `{name}`: {error}."))?;
+        self.tasks.push(ScheduledTask { name, schedule, handler: Box::new(move || Box::pin(handler())) });
+
+        Ok(self)
+    }
+}
+
+pub struct Scheduler {
+    config: SchedulerConfig,
+    async_op_guard_config: AsyncOpGuardConfig,
+    lease_backend: LeaseBackendGlobal,
+    distribute_cache: DistributeCacheGlobal,
+}
+
+impl Scheduler {
+    /// The last runs of the task, the latest first.
+    pub async fn history(&self, name: &str) -> Result<Vec<ScheduledRun>> {
+        Ok(self.distribute_cache.get_as::<Vec<ScheduledRun>>(&history_key(name)).await?.unwrap_or_default())
+    }
+
+    /// Campaign for the leadership, and run the `tasks` while leading.
+    pub fn start(self: &Arc<Self>, tasks: ScheduledTasks) -> SchedulerWorkers {
+        let stopped = CancellationToken::new();
+        if !self.config.enabled || tasks.tasks.is_empty() {
+            debug!(enabled = self.config.enabled, "No scheduled task runs on this instance.");
+
+            return SchedulerWorkers { leadership: None, stopped, tasks: Vec::new() };
+        }
+
+        let leadership = web_guard::leader::generate_leader_election(
+            &self.async_op_guard_config,
+            &self.lease_backend,
+            SCHEDULER_NAMESPACE,
+            self.config.leader_ttl,
+        )
+        .start();
+
+        let tasks = tasks
+            .tasks
+            .into_iter()
+            .map(|task| {
+                debug!(name = task.name, schedule = %task.schedule, "Scheduling the task.");
+
+                tokio::spawn(run(Arc::clone(self), task, leadership.subscribe(), stopped.clone()))
+            })
+            .collect();
+
+        SchedulerWorkers { leadership: Some(leadership), stopped, tasks }
+    }
+
+    async fn run_once(&self, task: &ScheduledTask, scheduled_at: DateTime<Utc>) {
+        let started_at = unix_millis();
+        let timeout = self.config.timeout;
+        let result = match tokio::time::timeout(timeout, (task.handler)()).await {
+            Ok(result) => result,
+            Err(_) => Err(anyhow::anyhow!("Timed out after {timeout:?}.")),
+        };
+
+        let run = ScheduledRun {
+            scheduled_at: scheduled_at.timestamp_millis() as u64,
+            started_at,
+            finished_at: unix_millis(),
+            instance: self.distribute_cache.id().to_string(),
+            error: result.err().map(|error| error.to_string()),
+        };
+
+        match &run.error {
+            None => debug!(name = task.name, elapsed = run.finished_at - run.started_at, "Scheduled task done."),
+            Some(error) => warn!(name = task.name, error, "Scheduled task failed."),
+        }
+
+        if let Err(error) = self.record(task.name, run).await {
+            warn!(name = task.name, %error, "Failed to record the scheduled run.");
+        }
+    }
+
+    /// Only the leader writes, so the history is never raced.
+    async fn record(&self, name: &str, run: ScheduledRun) -> Result<()> {
+        let mut history = self.history(name).await?;
+        history.insert(0, run);
+        history.truncate(self.config.history_size);
+
+        self.distribute_cache.set_as(&history_key(name), &history, None).await
+    }
+}
+
+#[inline]
+fn history_key(name: &str) -> String {
+    format!("{SCHEDULER_NAMESPACE}:history:{name}")
+}
+
+/// Wait for every tick of the `task`, and run it while leading, until being stopped.
+async fn run(
+    scheduler: SchedulerGlobal,
+    task: ScheduledTask,
+    leading: watch::Receiver<bool>,
+    stopped: CancellationToken,
+) {
+    // The ticks follow the timer, anchored once on the wall clock.
+    let (started_at, started) = (Utc::now(), tokio::time::Instant::now());
+    let mut last: Option<DateTime<Utc>> = None;
+
+    loop {
+        // Never the same tick twice, even if the clock is behind the timer.
+        let now = started_at + started.elapsed();
+        let after = last.map_or(now, |last| last.max(now));
+        let Some(scheduled_at) = task.schedule.after(&after).next() else {
+            debug!(name = task.name, "No more ticks of the scheduled task.");
+            break;
+        };
+
+        let delay = (scheduled_at - now).to_std().unwrap_or_default();
+        tokio::select! {
+            _ = stopped.cancelled() => break,
+            _ = tokio::time::sleep(delay) => {}
+        }
+        last = Some(scheduled_at);
+
+        if *leading.borrow() {
+            scheduler.run_once(&task, scheduled_at).await;
+        }
+    }
+}
+
+/// The running tasks and the leadership, stopped by `shutdown`.
+pub struct SchedulerWorkers {
+    leadership: Option<Leadership>,
+    stopped: CancellationToken,
+    tasks: Vec<tokio::task::JoinHandle<()>>,
+}
+
+impl SchedulerWorkers {
+    #[inline]
+    pub fn is_leader(&self) -> bool {
+        self.leadership.as_ref().is_some_and(Leadership::is_leader)
+    }
+
+    /// No more ticks, the running tasks are waited for, then the leadership is handed over.
+    pub async fn shutdown(self) {
+        self.stopped.cancel();
+
+        for task in self.tasks {
+            let _ = task.await;
+        }
+
+        if let Some(leadership) = self.leadership {
+            leadership.shutdown().await;
+        }
+    }
+}
+
+/// Elects its leader on the leases of the async op guard, its history is kept in the distribute cache.
+/// The tasks are run by `Scheduler::start`.
+pub fn generate(
+    config: SchedulerConfig,
+    async_op_guard_config: &AsyncOpGuardConfig,
+    lease_backend: &LeaseBackendGlobal,
+    distribute_cache: DistributeCacheGlobal,
+) -> SchedulerGlobal {
+    debug!(?config, "Generating the scheduler.");
+
+    Arc::new(Scheduler {
+        config,
+        async_op_guard_config: async_op_guard_config.clone(),
+        lease_backend: Arc::clone(lease_backend),
+        distribute_cache,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{generate, ScheduledTasks, SchedulerConfig};
+    use std::collections::HashSet;
+    use std::sync::atomic::{AtomicU32, Ordering};
+    use std::sync::Arc;
+    use std::time::Duration;
+    use web_cache::prelude::DistributeCacheConfig;
+    use web_guard::async_op::{AsyncOpGuardBackendConfig, AsyncOpGuardConfig};
+    use web_guard::lease::{memory::InMemoryLeaseBackend, LeaseBackendGlobal};
+
+    #[test]
+    fn register_invalid() {
+        assert!(ScheduledTasks::default().register("tests", "every minute", || async { Ok(()) }).is_err());
+        assert!(ScheduledTasks::default()
+            .register("tests", "0 * * * * *", || async { Ok(()) })
+            .unwrap()
+            .register("tests", "0 * * * * *", || async { Ok(()) })
+            .is_err());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn run_on_the_leader_only() {
+        let guard_config = AsyncOpGuardConfig::new(AsyncOpGuardBackendConfig::Memory);
+        let lease_backend: LeaseBackendGlobal = Arc::new(InMemoryLeaseBackend::default());
+        let distribute_cache =
+            web_cache::generate_distribute_cache(DistributeCacheConfig::Memory, Default::default()).await.unwrap();
+        let config = SchedulerConfig { leader_ttl: Duration::from_millis(300), ..Default::default() };
+        let runs = Arc::new(AtomicU32::new(0));
+
+        // Two instances sharing the backends.
+        let instances = (0..2)
+            .map(|_| {
+                let scheduler = generate(config.clone(), &guard_config, &lease_backend, Arc::clone(&distribute_cache));
+                let counter = Arc::clone(&runs);
+                let tasks = ScheduledTasks::default()
+                    .register("tests:every_second", "* * * * * *", move || {
+                        let run = counter.fetch_add(1, Ordering::Relaxed) + 1;
+                        async move {
+                            match run {
+                                1 => anyhow::bail!("Run {run} failed."),
+                                _ => Ok(()),
+                            }
+                        }
+                    })
+                    .unwrap();
+
+                (scheduler.start(tasks), scheduler)
+            })
+            .collect::<Vec<_>>();
+
+        // Skipped ahead, ticks and renewals included.
+        tokio::time::sleep(Duration::from_millis(2500)).await;
+        assert_eq!(instances.iter().filter(|(workers, _)| workers.is_leader()).count(), 1);
+
+        let scheduler = Arc::clone(&instances[0].1);
+        for (workers, _) in instances {
+            workers.shutdown().await;
+        }
+
+        // Once for every tick.
+        let history = scheduler.history("tests:every_second").await.unwrap();
+        assert!(history.len() >= 2);
+        assert_eq!(history.len() as u32, runs.load(Ordering::Relaxed));
+        assert_eq!(history.iter().map(|run| run.scheduled_at).collect::<HashSet<_>>().len(), history.len());
+        assert_eq!(history.last().unwrap().error.as_deref(), Some("Run 1 failed."));
+        assert!(history[0].error.is_none());
+    }
+}
@@ -0,0 +1,38 @@
+use crate::app::AppState;
+use crate::scheduler::ScheduledTasks;
+use web_cache::prelude::*;
+use web_core::prelude::*;
+
+/// Every 10 minutes.
+const REFRESH_CACHE_WARMUP_SCHEDULE: &str = "0 */10 * * * *";
+
+/// Tasks run on the leader instance, started with the server.
+pub fn tasks(state: AppState) -> Result<ScheduledTasks> {
+    let mut tasks = ScheduledTasks::default();
+
+    // Only the distribute cache entries, shared by every instance: the memory ones would be refreshed on the leader only.
+    if let Some(path) = state.config.cache_warmup.manifest.clone() {
+        tasks = tasks.register("refresh_cache_warmup", REFRESH_CACHE_WARMUP_SCHEDULE, move || {
+            let state = state.clone();
+            let path = path.clone();
+            async move {
+                // Read again, so the changes are picked up.
+                let mut manifest = CacheWarmupManifest::from_path(&path)?;
+                manifest.entries.retain(|entry| entry.store == CacheWarmupStore::Distribute);
+                let report = web_cache::warm_up_caches(
+                    &manifest,
+                    &crate::utils::warmup::loaders(),
+                    &state.memory_caches,
+                    &state.distribute_cache,
+                    state.config.cache_warmup.timeout,
+                )
+                .await;
+                info!(warmed = report.warmed.len(), skipped = report.skipped.len(), "Cache warm-up refreshed.");
+
+                Ok(())
+            }
+        })?;
+    }
+
+    Ok(tasks)
+}
@@ -1,3 +1,4 @@
 pub mod extensions;
 pub mod server;
 pub mod tracing;
+pub mod warmup;
@@ -0,0 +1,8 @@
+use web_cache::prelude::*;
+use web_core::prelude::*;
+
+/// Loaders referenced by the cache warm-up manifest.
+pub fn loaders() -> CacheWarmupLoaders {
+    // Same as the one of `/greeting/hello2`.
+    CacheWarmupLoaders::default().register("test", || async { Ok(json!(1)) })
+}